@@ -0,0 +1,148 @@
+//! Keep ships apart, pushing away the ones already too close and steering aside from the ones
+//! that will get too close soon
+
+use crate::math::*;
+
+/// extra distance kept between the borders of two ships
+pub const AVOIDANCE_MARGIN: f32 = 1.0;
+/// seconds ahead a collision is predicted
+pub const AVOIDANCE_TIME_HORIZON: f32 = 2.0;
+
+/// Something to keep away from, stations are obstacles that never move
+#[derive(Clone, Debug)]
+pub struct Obstacle {
+    /// used to break ties when two obstacles are at the same position
+    pub id: u32,
+    pub pos: P2,
+    pub vel: V2,
+    pub size: f32,
+}
+
+/// Correction to add to the desired velocity to keep away from all obstacles. The corrected
+/// velocity is never faster than `max_speed`.
+pub fn compute_avoidance_vel(
+    me: &Obstacle,
+    desired_vel: V2,
+    max_speed: f32,
+    obstacles: &[Obstacle],
+) -> V2 {
+    let mut correction = V2::new(0.0, 0.0);
+
+    for other in obstacles {
+        let delta = other.pos - me.pos;
+        let distance = delta.magnitude();
+        let min_distance = me.size + other.size + AVOIDANCE_MARGIN;
+
+        if distance < min_distance {
+            // already too close, push away proportional to the overlap
+            let away = if distance > 0.001 {
+                -delta / distance
+            } else if me.id < other.id {
+                V2::new(-1.0, 0.0)
+            } else {
+                V2::new(1.0, 0.0)
+            };
+
+            correction += away * max_speed * (min_distance - distance) / min_distance;
+            continue;
+        }
+
+        // close to contact, slide along instead of moving toward it
+        let normal = delta / distance;
+        let approach = (desired_vel - other.vel).dot(&normal);
+        if distance < min_distance * 1.5 && approach > 0.0 {
+            let weight = 1.0 - (distance - min_distance) / (min_distance * 0.5);
+            correction -= normal * approach * weight;
+        }
+
+        // closest approach if we keep the desired velocity
+        let relative_vel = other.vel - desired_vel;
+        let relative_speed_sqr = relative_vel.magnitude_squared();
+        if relative_speed_sqr < 0.001 {
+            continue;
+        }
+
+        let time = -delta.dot(&relative_vel) / relative_speed_sqr;
+        if time <= 0.0 || time > AVOIDANCE_TIME_HORIZON {
+            continue;
+        }
+
+        let closest = delta + relative_vel * time;
+        let closest_distance = closest.magnitude();
+        if closest_distance >= min_distance {
+            continue;
+        }
+
+        // steer away from the side the other will pass, when nearly head-on both turn to their
+        // left so they never pick the same side
+        let side = if closest_distance > min_distance * 0.5 {
+            -closest / closest_distance
+        } else {
+            V2::new(-delta.y, delta.x) / distance
+        };
+
+        // sideways velocity needed to pass at the min distance
+        correction += side * (min_distance - closest_distance) / time;
+    }
+
+    let vel = desired_vel + correction;
+    let speed = vel.magnitude();
+    if speed > max_speed {
+        vel * (max_speed / speed) - desired_vel
+    } else {
+        correction
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn obstacle(id: u32, x: f32, y: f32, vel_x: f32) -> Obstacle {
+        Obstacle {
+            id,
+            pos: P2::new(x, y),
+            vel: V2::new(vel_x, 0.0),
+            size: 4.0,
+        }
+    }
+
+    #[test]
+    fn test_avoid_head_on_by_steering_aside() {
+        let me = obstacle(0, 0.0, 0.0, 10.0);
+        let other = obstacle(1, 30.0, 0.0, -10.0);
+
+        let correction = compute_avoidance_vel(&me, me.vel, 20.0, &[other.clone()]);
+        assert!(correction.y.abs() > 1.0, "{:?}", correction);
+
+        // the other ship turn to the opposite side
+        let other_correction = compute_avoidance_vel(&other, other.vel, 20.0, &[me]);
+        assert!(correction.y * other_correction.y < 0.0);
+    }
+
+    #[test]
+    fn test_ignore_far_and_diverging_obstacles() {
+        let me = obstacle(0, 0.0, 0.0, 10.0);
+        let far = obstacle(1, 100.0, 0.0, -10.0);
+        let behind = obstacle(2, -30.0, 0.0, -10.0);
+        let side = obstacle(3, 0.0, 30.0, 10.0);
+
+        let correction = compute_avoidance_vel(&me, me.vel, 20.0, &[far, behind, side]);
+        assert_eq!(correction, V2::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn test_push_away_overlapping_obstacles() {
+        let me = obstacle(0, 0.0, 0.0, 0.0);
+        let other = obstacle(1, 2.0, 0.0, 0.0);
+        let correction = compute_avoidance_vel(&me, me.vel, 20.0, &[other.clone()]);
+        assert!(correction.x < 0.0);
+        assert!(correction.magnitude() <= 20.0);
+
+        // same position still separate them
+        let stacked = obstacle(1, 0.0, 0.0, 0.0);
+        let a = compute_avoidance_vel(&me, me.vel, 20.0, &[stacked.clone()]);
+        let b = compute_avoidance_vel(&stacked, stacked.vel, 20.0, &[me]);
+        assert!(a.x * b.x < 0.0);
+    }
+}
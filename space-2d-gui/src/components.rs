@@ -0,0 +1,678 @@
+use crate::math::*;
+
+use ggez::graphics::{self, Color};
+use nalgebra::Point2;
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+use specs_derive::Component;
+use std::collections::{BTreeMap, VecDeque};
+
+#[derive(Clone, Debug, Default, Component)]
+pub struct Debug {
+    pub lines: Vec<(P2, P2, Color)>,
+}
+
+impl Debug {
+    pub fn new() -> Self {
+        Debug { lines: Vec::new() }
+    }
+}
+
+#[derive(Clone, Debug, Component, Serialize, Deserialize)]
+pub struct Cfg {
+    pub speed_reduction: f32,
+    pub pause: bool,
+    pub vector_epsilon: f32,
+    /// patrols slow down to the arrival speed of their points
+    pub patrol_arrival: bool,
+    /// ships with a model avoid each other and stations
+    pub avoidance: bool,
+}
+
+impl Default for Cfg {
+    fn default() -> Self {
+        Cfg {
+            speed_reduction: 1.5,
+            pause: false,
+            vector_epsilon: 2.0,
+            patrol_arrival: true,
+            avoidance: true,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Component, Serialize, Deserialize)]
+pub struct Time {
+    pub total_time: f32,
+    pub delta_time: f32,
+}
+
+/// number of ships that can dock at same time by default
+pub const DEFAULT_DOCK_CAPACITY: usize = 2;
+/// distance between ships waiting in the dock queue
+pub const DOCK_QUEUE_SPACING: f32 = 12.0;
+/// sideways distance of the docking and undocking lanes from the entrance axis
+pub const DOCK_LANE_OFFSET: f32 = 5.0;
+/// distance between the dock slots, side by side across the entrance axis
+pub const DOCK_SLOT_SPACING: f32 = 10.0;
+
+#[derive(Clone, Debug, Component)]
+pub struct Station {
+    pub pos: P2,
+    pub entrance_dir: V2,
+    pub entrance_distance: f32,
+    /// max number of ships docking, docked or undocking
+    pub dock_capacity: usize,
+    /// ships holding a dock slot, indexed by slot
+    pub docks: Vec<Option<Entity>>,
+    /// ships waiting for a dock slot, first one is the next
+    pub queue: Vec<Entity>,
+}
+
+impl Station {
+    pub fn new(pos: P2, entrance_dir: V2, entrance_distance: f32) -> Self {
+        Station {
+            pos,
+            entrance_dir,
+            entrance_distance,
+            dock_capacity: DEFAULT_DOCK_CAPACITY,
+            docks: vec![],
+            queue: vec![],
+        }
+    }
+
+    pub fn with_dock_capacity(mut self, dock_capacity: usize) -> Self {
+        self.dock_capacity = dock_capacity;
+        self
+    }
+
+    pub fn get_entrance_pos(&self) -> P2 {
+        self.pos + self.entrance_dir * self.entrance_distance
+    }
+
+    /// right side of the entrance direction
+    fn get_lane_dir(&self) -> V2 {
+        V2::new(-self.entrance_dir.y, self.entrance_dir.x)
+    }
+
+    /// Where a ship wait in the queue, in a line going out of the entrance on the docking lane
+    pub fn get_queue_pos(&self, index: usize) -> P2 {
+        self.get_entrance_pos()
+            + self.get_lane_dir() * DOCK_LANE_OFFSET
+            + self.entrance_dir * DOCK_QUEUE_SPACING * (index + 1) as f32
+    }
+
+    /// Exit point of undocking ships, on the opposite lane of docking ones
+    pub fn get_exit_pos(&self) -> P2 {
+        self.get_entrance_pos() - self.get_lane_dir() * DOCK_LANE_OFFSET
+    }
+
+    /// Where a ship stays docked, slots are centered on the station along the lanes
+    pub fn get_dock_pos(&self, slot: usize) -> P2 {
+        let offset = slot as f32 - (self.dock_capacity.max(1) - 1) as f32 / 2.0;
+        self.pos + self.get_lane_dir() * DOCK_SLOT_SPACING * offset
+    }
+
+    pub fn get_dock_slot(&self, ship: Entity) -> Option<usize> {
+        self.docks.iter().position(|docked| *docked == Some(ship))
+    }
+
+    pub fn list_docked(&self) -> Vec<Entity> {
+        self.docks.iter().flatten().copied().collect()
+    }
+
+    pub fn has_free_dock(&self) -> bool {
+        self.list_docked().len() < self.dock_capacity
+    }
+
+    /// Give the first free slot to `ship`, none when all are used
+    pub fn dock(&mut self, ship: Entity) -> Option<usize> {
+        if !self.has_free_dock() {
+            return None;
+        }
+
+        match self.docks.iter().position(|docked| docked.is_none()) {
+            Some(slot) => {
+                self.docks[slot] = Some(ship);
+                Some(slot)
+            }
+            None => {
+                self.docks.push(Some(ship));
+                Some(self.docks.len() - 1)
+            }
+        }
+    }
+
+    /// Free the slot of `ship`
+    pub fn undock(&mut self, ship: Entity) {
+        for docked in &mut self.docks {
+            if *docked == Some(ship) {
+                *docked = None;
+            }
+        }
+    }
+}
+
+/// leaders move slower than followers behind them so they can catch up
+pub const FOLLOWER_BEHIND_SPEED_FACTOR: f32 = 0.8;
+
+#[derive(Clone, Debug, Component, Serialize, Deserialize)]
+pub struct Movable {
+    pub pos: P2,
+    pub max_speed: f32,
+    pub vel: V2,
+    pub desired_vel: V2,
+    /// correction to the desired velocity to keep away from others, set by `avoidance_system`
+    pub avoidance_vel: V2,
+    pub max_acc: f32,
+    /// lowest max speed of the followers behind, set on every update by the follow system
+    pub follower_behind_max_speed: Option<f32>,
+}
+
+impl Movable {
+    pub fn new(pos: P2, max_speed: f32, max_acc: f32) -> Self {
+        Movable {
+            pos,
+            max_speed,
+            vel: V2::new(0.0, 0.0),
+            desired_vel: V2::new(0.0, 0.0),
+            avoidance_vel: V2::new(0.0, 0.0),
+            max_acc,
+            follower_behind_max_speed: None,
+        }
+    }
+
+    /// max speed considering followers behind
+    pub fn get_max_speed(&self) -> f32 {
+        match self.follower_behind_max_speed {
+            Some(speed) => self.max_speed.min(speed * FOLLOWER_BEHIND_SPEED_FACTOR),
+            None => self.max_speed,
+        }
+    }
+}
+
+/// A coordinate space with its own stations and ships, linked to others by gates
+#[derive(Clone, Debug, Component, Serialize, Deserialize)]
+pub struct Sector {
+    pub name: String,
+}
+
+impl Sector {
+    pub fn new(name: &str) -> Self {
+        Sector {
+            name: name.to_string(),
+        }
+    }
+}
+
+/// Sector of a ship, station or gate. Entities without it are all in the same default sector.
+#[derive(Clone, Debug, PartialEq, Component)]
+pub struct Location {
+    pub sector: Entity,
+}
+
+impl Location {
+    pub fn new(sector: Entity) -> Self {
+        Location { sector }
+    }
+}
+
+/// Ships that reach the gate are moved to `to_pos` in `to_sector`
+#[derive(Clone, Debug, Component)]
+pub struct Gate {
+    pub pos: P2,
+    pub to_sector: Entity,
+    pub to_pos: P2,
+}
+
+impl Gate {
+    pub fn new(pos: P2, to_sector: Entity, to_pos: P2) -> Self {
+        Gate {
+            pos,
+            to_sector,
+            to_pos,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Component)]
+pub struct MoveCommand {
+    pub to: P2,
+    pub arrival: bool,
+    pub predict: bool,
+    /// sector of `to`, when none is the current one
+    pub sector: Option<Entity>,
+}
+
+#[derive(Clone, Debug, Component, Serialize, Deserialize)]
+pub struct PatrolCommand {
+    pub index: usize,
+    pub route: Vec<P2>,
+    /// speed to fly through each point, when none pass at max speed
+    pub arrival_speed: Option<f32>,
+}
+
+impl PatrolCommand {
+    pub fn new(route: Vec<P2>) -> Self {
+        PatrolCommand {
+            index: 0,
+            route,
+            arrival_speed: None,
+        }
+    }
+
+    pub fn with_arrival_speed(mut self, arrival_speed: f32) -> Self {
+        self.arrival_speed = Some(arrival_speed);
+        self
+    }
+
+    /// Point to move to, none when the route is empty
+    pub fn current(&self) -> Option<P2> {
+        self.route.get(self.index).copied()
+    }
+
+    /// move to next point
+    pub fn next(&mut self) {
+        self.index += 1;
+        if self.index >= self.route.len() {
+            self.index = 0;
+        }
+    }
+
+    pub fn route_from_next(&self) -> Vec<P2> {
+        let mut r = vec![];
+        for i in self.index..self.route.len() {
+            r.push(self.route[i]);
+        }
+        for i in 0..self.index {
+            r.push(self.route[i]);
+        }
+        r
+    }
+}
+
+#[derive(Clone, Debug, Component)]
+pub struct FollowCommand {
+    pub target: Entity,
+    /// relative position
+    pub relative_pos: P2,
+}
+
+impl FollowCommand {
+    pub fn new(target: Entity, pos: P2) -> Self {
+        FollowCommand {
+            target,
+            relative_pos: pos,
+        }
+    }
+
+    /// Follow the target at the slot `index` of the formation
+    pub fn escort(target: Entity, formation: &EscortFormation, index: usize) -> Self {
+        FollowCommand::new(target, formation.get_slot(index))
+    }
+}
+
+/// Move to meet a moving target, complete when close to it
+#[derive(Clone, Debug, Component)]
+pub struct InterceptCommand {
+    pub target: Entity,
+}
+
+impl InterceptCommand {
+    pub fn new(target: Entity) -> Self {
+        InterceptCommand { target }
+    }
+}
+
+/// Chase a moving target and stay on it, complete only when the target is gone
+#[derive(Clone, Debug, Component)]
+pub struct PursueCommand {
+    pub target: Entity,
+}
+
+impl PursueCommand {
+    pub fn new(target: Entity) -> Self {
+        PursueCommand { target }
+    }
+}
+
+/// Templates of escort slots around a leader
+#[derive(Clone, Debug, PartialEq)]
+pub enum EscortFormation {
+    /// side by side with the leader, alternating left and right
+    Line { spacing: f32 },
+    /// one behind the other
+    Column { spacing: f32 },
+    /// diagonal lines behind the leader, alternating left and right
+    Wedge { spacing: f32 },
+}
+
+impl EscortFormation {
+    /// Position relative to the leader, x axis is the leader direction and y its left side
+    pub fn get_slot(&self, index: usize) -> P2 {
+        let rank = (index / 2 + 1) as f32;
+        let side = if index % 2 == 0 { 1.0 } else { -1.0 };
+
+        match self {
+            EscortFormation::Line { spacing } => P2::new(0.0, side * rank * spacing),
+            EscortFormation::Column { spacing } => P2::new(-((index + 1) as f32) * spacing, 0.0),
+            EscortFormation::Wedge { spacing } => P2::new(-rank * spacing, side * rank * spacing),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub enum TradeCommandState {
+    /// move to the end of the station queue
+    MoveToDock,
+    /// hold position in the queue until get a dock slot
+    Queued,
+    Docking,
+    Docked {
+        complete_time: f32,
+    },
+    Undocking,
+}
+
+#[derive(Clone, Debug, Component)]
+pub struct TradeCommand {
+    /// stations the ship trade with
+    pub stations: Vec<Entity>,
+    pub index: usize,
+    pub state: TradeCommandState,
+    /// station planned when docked, used after undocking. When none, go to the next one
+    pub next_index: Option<usize>,
+}
+
+impl TradeCommand {
+    pub fn new(stations: Vec<Entity>) -> Self {
+        TradeCommand {
+            stations,
+            index: 0,
+            state: TradeCommandState::MoveToDock,
+            next_index: None,
+        }
+    }
+
+    /// Station to trade with, none when the route is empty
+    pub fn current(&self) -> Option<Entity> {
+        self.stations.get(self.index).copied()
+    }
+
+    pub fn next(&mut self) {
+        self.index += 1;
+        if self.index >= self.stations.len() {
+            self.index = 0;
+        }
+    }
+
+    /// Keep only the stations accepted by `keep`, when the current one is removed move to the
+    /// next one. The planned station is dropped when any is removed
+    pub fn retain_stations<F: Fn(Entity) -> bool>(&mut self, keep: F) {
+        if self.stations.iter().all(|station| keep(*station)) {
+            return;
+        }
+
+        let removed_before = self.stations[..self.index.min(self.stations.len())]
+            .iter()
+            .filter(|station| !keep(**station))
+            .count();
+        let keep_current = self.current().filter(|station| keep(*station)).is_some();
+
+        self.stations.retain(|station| keep(*station));
+        self.index -= removed_before;
+        if self.index >= self.stations.len() {
+            self.index = 0;
+        }
+        self.next_index = None;
+
+        if !keep_current {
+            self.state = TradeCommandState::MoveToDock;
+        }
+    }
+
+    /// Station whose queue or docks the ship is using
+    pub fn get_corridor_station(&self) -> Option<Entity> {
+        match self.state {
+            TradeCommandState::MoveToDock => None,
+            _ => self.current(),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub struct WareId(pub u32);
+
+/// Goods carried by a ship
+#[derive(Clone, Debug, Component, Serialize, Deserialize)]
+pub struct Cargo {
+    pub capacity: f32,
+    pub wares: BTreeMap<WareId, f32>,
+    pub credits: f32,
+}
+
+impl Cargo {
+    pub fn new(capacity: f32, credits: f32) -> Self {
+        Cargo {
+            capacity,
+            wares: BTreeMap::new(),
+            credits,
+        }
+    }
+
+    pub fn get_amount(&self, ware: WareId) -> f32 {
+        self.wares.get(&ware).cloned().unwrap_or(0.0)
+    }
+
+    pub fn get_total(&self) -> f32 {
+        self.wares.values().sum()
+    }
+
+    pub fn get_free(&self) -> f32 {
+        (self.capacity - self.get_total()).max(0.0)
+    }
+
+    pub fn add(&mut self, ware: WareId, amount: f32) {
+        *self.wares.entry(ware).or_insert(0.0) += amount;
+    }
+
+    pub fn remove(&mut self, ware: WareId, amount: f32) {
+        let current = self.get_amount(ware);
+        if current - amount <= 0.001 {
+            self.wares.remove(&ware);
+        } else {
+            self.wares.insert(ware, current - amount);
+        }
+    }
+}
+
+/// Stock of a single ware in a station
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct StationWare {
+    pub amount: f32,
+    pub capacity: f32,
+    /// units per second, negative values are consumption
+    pub production: f32,
+    /// price when the stock is half full
+    pub base_price: f32,
+}
+
+impl StationWare {
+    pub fn new(amount: f32, capacity: f32, production: f32, base_price: f32) -> Self {
+        StationWare {
+            amount,
+            capacity,
+            production,
+            base_price,
+        }
+    }
+
+    /// Price for one unit, from 1.5x the base price when empty to 0.5x when full
+    pub fn get_price(&self) -> f32 {
+        let fill = if self.capacity > 0.0 {
+            (self.amount / self.capacity).max(0.0).min(1.0)
+        } else {
+            1.0
+        };
+
+        self.base_price * (1.5 - fill)
+    }
+
+    pub fn get_free(&self) -> f32 {
+        (self.capacity - self.amount).max(0.0)
+    }
+
+    /// produced wares are sold to ships
+    pub fn is_selling(&self) -> bool {
+        self.production > 0.0
+    }
+
+    /// consumed wares are bought from ships
+    pub fn is_buying(&self) -> bool {
+        self.production < 0.0
+    }
+}
+
+/// Wares produced and consumed by a station
+#[derive(Clone, Debug, Default, Component, Serialize, Deserialize)]
+pub struct Inventory {
+    pub wares: BTreeMap<WareId, StationWare>,
+}
+
+impl Inventory {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn with(mut self, ware: WareId, stock: StationWare) -> Self {
+        self.wares.insert(ware, stock);
+        self
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CommandKind {
+    Move,
+    Patrol,
+    Follow,
+    Trade,
+    Intercept,
+    Pursue,
+}
+
+/// Any command that can be queued, each one is executed by attaching its component
+#[derive(Clone, Debug)]
+pub enum Command {
+    Move(MoveCommand),
+    Patrol(PatrolCommand),
+    Follow(FollowCommand),
+    Trade(TradeCommand),
+    Intercept(InterceptCommand),
+    Pursue(PursueCommand),
+}
+
+impl Command {
+    pub fn kind(&self) -> CommandKind {
+        match self {
+            Command::Move(_) => CommandKind::Move,
+            Command::Patrol(_) => CommandKind::Patrol,
+            Command::Follow(_) => CommandKind::Follow,
+            Command::Trade(_) => CommandKind::Trade,
+            Command::Intercept(_) => CommandKind::Intercept,
+            Command::Pursue(_) => CommandKind::Pursue,
+        }
+    }
+}
+
+/// Ordered commands of a ship. The active command is attached as its own component and
+/// removed from the queue, when the component is removed by its system the command is complete
+/// and the next one become active.
+///
+/// Changes are applied by the `command_queue_system` on next update.
+#[derive(Clone, Debug, Default, Component)]
+pub struct CommandQueue {
+    pub(crate) pending: VecDeque<Command>,
+    pub(crate) active: Option<CommandKind>,
+    /// discard the active command
+    pub(crate) cancel_active: bool,
+    /// move the active command back to the queue, after the first pending one
+    pub(crate) suspend_active: bool,
+}
+
+impl CommandQueue {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add the command to be executed after all others
+    pub fn enqueue(&mut self, command: Command) {
+        self.pending.push_back(command);
+    }
+
+    /// Discard all commands, including the active one, and execute this one
+    pub fn replace(&mut self, command: Command) {
+        self.clear();
+        self.pending.push_back(command);
+    }
+
+    /// Discard all commands, including the active one
+    pub fn clear(&mut self) {
+        self.pending.clear();
+        self.cancel_active = true;
+        self.suspend_active = false;
+    }
+
+    /// Execute the command now and resume the active one when it completes
+    pub fn interrupt(&mut self, command: Command) {
+        self.pending.push_front(command);
+        if !self.cancel_active {
+            self.suspend_active = true;
+        }
+    }
+
+    /// Kind of the command currently executing
+    pub fn get_active(&self) -> Option<CommandKind> {
+        self.active
+    }
+
+    pub fn get_pending(&self) -> &VecDeque<Command> {
+        &self.pending
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.active.is_none() && self.pending.is_empty()
+    }
+}
+
+#[derive(Clone, Debug, Component, Serialize, Deserialize)]
+pub struct Model {
+    pub pos: P2,
+    pub size: f32,
+    #[serde(with = "ColorDef")]
+    pub color: graphics::Color,
+}
+
+/// Serde definition of ggez color
+#[derive(Serialize, Deserialize)]
+#[serde(remote = "Color")]
+struct ColorDef {
+    r: f32,
+    g: f32,
+    b: f32,
+    a: f32,
+}
+
+impl Model {
+    pub fn new(size: f32, color: graphics::Color) -> Self {
+        Model {
+            pos: Point2::new(0.0, 0.0),
+            size,
+            color,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Component)]
+pub struct MovementPrediction {
+    pub points: Vec<P2>,
+}
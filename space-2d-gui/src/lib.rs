@@ -0,0 +1,10 @@
+pub mod avoidance;
+pub mod components;
+pub mod math;
+pub mod pursuit;
+pub mod save;
+pub mod scenery;
+pub mod sectors;
+pub mod simulation;
+pub mod systems;
+pub mod trade;
@@ -0,0 +1,45 @@
+use nalgebra::{Point2, Rotation2, Vector2};
+
+pub type V2 = Vector2<f32>;
+pub type P2 = Point2<f32>;
+
+pub fn angle_vector(v: V2) -> f32 {
+    v.y.atan2(v.x)
+}
+
+pub fn rotate_vector(dir: V2, point: P2) -> P2 {
+    let angle = angle_vector(dir);
+    rotate_vector_by_angle(point, angle)
+}
+
+pub fn rotate_vector_by_angle(point: P2, angle: f32) -> P2 {
+    let rotation = Rotation2::new(angle);
+    rotation * point
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    macro_rules! assert_delta {
+        ($x:expr, $y:expr, $d:expr) => {
+            if !($x - $y < $d || $y - $x < $d) {
+                panic!();
+            }
+        };
+    }
+
+    #[test]
+    fn test_rotate_vector() {
+        let point = P2::new(0.0, 1.0);
+
+        let dir = V2::new(1.0, 0.0);
+        let rotated = rotate_vector(dir, point.clone());
+        assert_eq!(rotated, P2::new(0.0, 1.0));
+
+        let dir = V2::new(-1.0, 0.0);
+        let rotated = rotate_vector(dir, point);
+        assert_delta!(rotated.x, 0.0, 0.001);
+        assert_delta!(rotated.y, -1.0, 0.001);
+    }
+}
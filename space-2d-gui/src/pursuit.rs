@@ -0,0 +1,141 @@
+//! Predict where a moving target can be reached
+
+use crate::components::*;
+use crate::math::*;
+
+/// max seconds ahead of the target position to steer when it can not be intercepted
+pub const PURSUIT_MAX_LOOKAHEAD: f32 = 5.0;
+
+/// Where and when the pursuer meet the target
+#[derive(Clone, Debug, PartialEq)]
+pub struct Interception {
+    pub point: P2,
+    pub time: f32,
+}
+
+/// Smallest time to reach a target at `delta` moving with `target_vel`, when going at `speed`
+/// after `delay` seconds without moving. None when the target can not be reached.
+pub fn solve_intercept_time(delta: V2, target_vel: V2, speed: f32, delay: f32) -> Option<f32> {
+    // |delta + target_vel * t| = speed * (t - delay)
+    let a = target_vel.dot(&target_vel) - speed * speed;
+    let b = 2.0 * (delta.dot(&target_vel) + speed * speed * delay);
+    let c = delta.dot(&delta) - speed * speed * delay * delay;
+
+    let roots = if a.abs() < 0.0001 {
+        if b.abs() < 0.0001 {
+            return None;
+        }
+        vec![-c / b]
+    } else {
+        let discriminant = b * b - 4.0 * a * c;
+        if discriminant < 0.0 {
+            return None;
+        }
+        let sqrt = discriminant.sqrt();
+        vec![(-b - sqrt) / (2.0 * a), (-b + sqrt) / (2.0 * a)]
+    };
+
+    roots
+        .into_iter()
+        .filter(|time| *time >= delay && *time >= 0.0)
+        .fold(None, |best: Option<f32>, time| match best {
+            Some(best) if best < time => Some(best),
+            _ => Some(time),
+        })
+}
+
+/// Meeting point going at max speed, considering the time the pursuer needs to turn and
+/// accelerate. None when the target is too fast to be reached.
+pub fn predict_intercept(pursuer: &Movable, target: &Movable) -> Option<Interception> {
+    let delta = target.pos - pursuer.pos;
+    let speed = pursuer.get_max_speed();
+    let time = solve_intercept_time(delta, target.vel, speed, 0.0)?;
+
+    // changing velocity at constant acceleration lose half of the time it takes
+    let point = target.pos + target.vel * time;
+    let dir = (point - pursuer.pos)
+        .try_normalize(0.0001)
+        .unwrap_or(V2::new(0.0, 0.0));
+    let delay = if pursuer.max_acc > 0.0 {
+        (dir * speed - pursuer.vel).magnitude() / pursuer.max_acc * 0.5
+    } else {
+        0.0
+    };
+
+    let time = solve_intercept_time(delta, target.vel, speed, delay).unwrap_or(time);
+
+    Some(Interception {
+        point: target.pos + target.vel * time,
+        time,
+    })
+}
+
+/// Target position ahead by the time to reach its current position, used when it can not be
+/// intercepted
+pub fn predict_pursuit(pursuer: &Movable, target: &Movable) -> P2 {
+    let distance = (target.pos - pursuer.pos).magnitude();
+    let time = distance / pursuer.get_max_speed().max(0.001);
+    target.pos + target.vel * time.min(PURSUIT_MAX_LOOKAHEAD)
+}
+
+/// Where the pursuer should steer to meet the target
+pub fn get_meeting_point(pursuer: &Movable, target: &Movable) -> P2 {
+    predict_intercept(pursuer, target)
+        .map(|interception| interception.point)
+        .unwrap_or_else(|| predict_pursuit(pursuer, target))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn movable(x: f32, y: f32, vel_x: f32, vel_y: f32, max_speed: f32) -> Movable {
+        let mut movable = Movable::new(P2::new(x, y), max_speed, 1000.0);
+        movable.vel = V2::new(vel_x, vel_y);
+        movable
+    }
+
+    #[test]
+    fn test_intercept_crossing_target() {
+        let pursuer = movable(0.0, 0.0, 0.0, 0.0, 50.0);
+        let target = movable(100.0, -100.0, 0.0, 30.0, 30.0);
+
+        let interception = predict_intercept(&pursuer, &target).unwrap();
+        let pursuer_time = (interception.point - pursuer.pos).magnitude() / 50.0;
+        assert!(interception.point.x == 100.0 && interception.point.y > -100.0);
+        // the acceleration delay is small for a fast acceleration
+        assert!((interception.time - pursuer_time).abs() < 0.1);
+    }
+
+    #[test]
+    fn test_intercept_static_target() {
+        let pursuer = movable(0.0, 0.0, 0.0, 0.0, 50.0);
+        let target = movable(100.0, 0.0, 0.0, 0.0, 30.0);
+
+        let interception = predict_intercept(&pursuer, &target).unwrap();
+        assert_eq!(interception.point, target.pos);
+        assert!(interception.time >= 2.0);
+    }
+
+    #[test]
+    fn test_intercept_consider_acceleration() {
+        // moving away from the target need time to turn back
+        let target = movable(100.0, 0.0, 0.0, 0.0, 30.0);
+        let stopped = movable(0.0, 0.0, 0.0, 0.0, 50.0);
+        let mut moving_away = movable(0.0, 0.0, -50.0, 0.0, 50.0);
+        moving_away.max_acc = 50.0;
+
+        let time_stopped = predict_intercept(&stopped, &target).unwrap().time;
+        let time_moving_away = predict_intercept(&moving_away, &target).unwrap().time;
+        assert!(time_moving_away > time_stopped + 0.5);
+    }
+
+    #[test]
+    fn test_pursuit_faster_target() {
+        let pursuer = movable(0.0, 0.0, 0.0, 0.0, 20.0);
+        let target = movable(100.0, 0.0, 30.0, 0.0, 30.0);
+
+        assert!(predict_intercept(&pursuer, &target).is_none());
+        assert_eq!(get_meeting_point(&pursuer, &target), P2::new(250.0, 0.0));
+    }
+}
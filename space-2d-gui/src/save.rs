@@ -0,0 +1,891 @@
+//! Save and load the whole world as json. Entities are identified by a `SaveMarker`, references
+//! between them are stored as the marker id and restored to the loaded entities.
+
+use crate::components::*;
+use crate::math::*;
+
+use ggez::{GameError, GameResult};
+use serde::{Deserialize, Serialize};
+use specs::error::NoError;
+use specs::prelude::*;
+use specs::saveload::{
+    ConvertSaveload, DeserializeComponents, Marker, MarkerAllocator, SerializeComponents,
+    SimpleMarker, SimpleMarkerAllocator,
+};
+use std::collections::{HashSet, VecDeque};
+use std::fmt;
+
+/// Tag of the markers used by saves
+pub struct Saved;
+
+/// Stable id of an entity between save and load
+pub type SaveMarker = SimpleMarker<Saved>;
+pub type SaveMarkerAllocator = SimpleMarkerAllocator<Saved>;
+
+/// Entity reference that can not be converted
+#[derive(Debug)]
+pub struct SaveError(String);
+
+impl fmt::Display for SaveError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<NoError> for SaveError {
+    fn from(e: NoError) -> Self {
+        match e {}
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct SaveData {
+    time: Time,
+    cfg: Cfg,
+    entities: serde_json::Value,
+}
+
+/// Serialize all entities and the `Time` and `Cfg` resources. Entities without a marker get one
+/// here, commands targeting deleted entities are removed.
+pub fn save_world(world: &mut World) -> GameResult<String> {
+    remove_lost_commands(world);
+
+    {
+        let entities = world.entities();
+        let mut markers = world.write_storage::<SaveMarker>();
+        let mut allocator = world.write_resource::<SaveMarkerAllocator>();
+        for entity in entities.join() {
+            allocator.mark(entity, &mut markers);
+        }
+    }
+
+    let entities = world.entities();
+    let markers = world.read_storage::<SaveMarker>();
+    let storages = (
+        world.read_storage::<Model>(),
+        world.read_storage::<Movable>(),
+        world.read_storage::<Station>(),
+        world.read_storage::<MoveCommand>(),
+        world.read_storage::<PatrolCommand>(),
+        world.read_storage::<FollowCommand>(),
+        world.read_storage::<TradeCommand>(),
+        world.read_storage::<InterceptCommand>(),
+        world.read_storage::<PursueCommand>(),
+        world.read_storage::<CommandQueue>(),
+        world.read_storage::<Cargo>(),
+        world.read_storage::<Inventory>(),
+        world.read_storage::<Sector>(),
+        world.read_storage::<Location>(),
+        world.read_storage::<Gate>(),
+    );
+
+    let data = SaveData {
+        time: (*world.read_resource::<Time>()).clone(),
+        cfg: (*world.read_resource::<Cfg>()).clone(),
+        entities: SerializeComponents::<SaveError, SaveMarker>::serialize(
+            &storages,
+            &entities,
+            &markers,
+            serde_json::value::Serializer,
+        )
+        .map_err(|e| GameError::FilesystemError(format!("fail to save world: {}", e)))?,
+    };
+
+    serde_json::to_string(&data)
+        .map_err(|e| GameError::FilesystemError(format!("fail to save world: {}", e)))
+}
+
+/// Add the saved entities into the world and replace its `Time` and `Cfg`
+pub fn load_world(world: &mut World, data: &str) -> GameResult<()> {
+    let data: SaveData = serde_json::from_str(data)
+        .map_err(|e| GameError::ResourceLoadError(format!("fail to load world: {}", e)))?;
+
+    {
+        let entities = world.entities();
+        let mut markers = world.write_storage::<SaveMarker>();
+        let mut allocator = world.write_resource::<SaveMarkerAllocator>();
+        let mut storages = (
+            world.write_storage::<Model>(),
+            world.write_storage::<Movable>(),
+            world.write_storage::<Station>(),
+            world.write_storage::<MoveCommand>(),
+            world.write_storage::<PatrolCommand>(),
+            world.write_storage::<FollowCommand>(),
+            world.write_storage::<TradeCommand>(),
+            world.write_storage::<InterceptCommand>(),
+            world.write_storage::<PursueCommand>(),
+            world.write_storage::<CommandQueue>(),
+            world.write_storage::<Cargo>(),
+            world.write_storage::<Inventory>(),
+            world.write_storage::<Sector>(),
+            world.write_storage::<Location>(),
+            world.write_storage::<Gate>(),
+        );
+
+        DeserializeComponents::<SaveError, SaveMarker>::deserialize(
+            &mut storages,
+            &entities,
+            &mut markers,
+            &mut allocator,
+            data.entities,
+        )
+        .map_err(|e| GameError::ResourceLoadError(format!("fail to load world: {}", e)))?;
+    }
+
+    world.insert(data.time);
+    world.insert(data.cfg);
+
+    Ok(())
+}
+
+/// The deleted targets have no id to save, the systems would complete these commands on their next
+/// run anyway
+fn remove_lost_commands(world: &mut World) {
+    let entities = world.entities();
+    remove_lost(
+        &entities,
+        &mut world.write_storage::<MoveCommand>(),
+        |command| command.sector,
+    );
+    remove_lost(
+        &entities,
+        &mut world.write_storage::<FollowCommand>(),
+        |command| Some(command.target),
+    );
+    remove_lost(
+        &entities,
+        &mut world.write_storage::<InterceptCommand>(),
+        |command| Some(command.target),
+    );
+    remove_lost(
+        &entities,
+        &mut world.write_storage::<PursueCommand>(),
+        |command| Some(command.target),
+    );
+}
+
+/// Entity the command can not be executed without, deleted stations are dropped from trade routes
+fn get_command_target(command: &Command) -> Option<Entity> {
+    match command {
+        Command::Move(command) => command.sector,
+        Command::Follow(command) => Some(command.target),
+        Command::Intercept(command) => Some(command.target),
+        Command::Pursue(command) => Some(command.target),
+        Command::Patrol(_) | Command::Trade(_) => None,
+    }
+}
+
+fn remove_lost<C, F>(entities: &Entities, commands: &mut WriteStorage<C>, target: F)
+where
+    C: Component,
+    F: Fn(&C) -> Option<Entity>,
+{
+    let lost: Vec<Entity> = (entities, &*commands)
+        .join()
+        .filter(|(_, command)| {
+            target(command)
+                .map(|target| !entities.is_alive(target))
+                .unwrap_or(false)
+        })
+        .map(|(entity, _)| entity)
+        .collect();
+
+    for entity in lost {
+        commands.remove(entity);
+    }
+}
+
+fn to_id<F>(entity: Entity, ids: &mut F) -> Result<SaveMarker, SaveError>
+where
+    F: FnMut(Entity) -> Option<SaveMarker>,
+{
+    ids(entity).ok_or_else(|| SaveError(format!("entity {:?} is not saved", entity)))
+}
+
+fn from_id<F>(id: SaveMarker, ids: &mut F) -> Result<Entity, SaveError>
+where
+    F: FnMut(SaveMarker) -> Option<Entity>,
+{
+    let value = id.id();
+    ids(id).ok_or_else(|| SaveError(format!("saved entity {} not found", value)))
+}
+
+/// References to deleted entities are dropped
+fn to_alive_ids<F>(entities: &[Entity], ids: &mut F) -> Vec<SaveMarker>
+where
+    F: FnMut(Entity) -> Option<SaveMarker>,
+{
+    entities.iter().filter_map(|entity| ids(*entity)).collect()
+}
+
+fn from_ids<F>(markers: Vec<SaveMarker>, ids: &mut F) -> Result<Vec<Entity>, SaveError>
+where
+    F: FnMut(SaveMarker) -> Option<Entity>,
+{
+    markers.into_iter().map(|id| from_id(id, ids)).collect()
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct StationData {
+    pub pos: P2,
+    pub entrance_dir: V2,
+    pub entrance_distance: f32,
+    pub dock_capacity: usize,
+    pub docks: Vec<Option<SaveMarker>>,
+    pub queue: Vec<SaveMarker>,
+}
+
+impl ConvertSaveload<SaveMarker> for Station {
+    type Data = StationData;
+    type Error = SaveError;
+
+    fn convert_into<F>(&self, mut ids: F) -> Result<Self::Data, Self::Error>
+    where
+        F: FnMut(Entity) -> Option<SaveMarker>,
+    {
+        Ok(StationData {
+            pos: self.pos,
+            entrance_dir: self.entrance_dir,
+            entrance_distance: self.entrance_distance,
+            dock_capacity: self.dock_capacity,
+            // deleted ships free their slot
+            docks: self
+                .docks
+                .iter()
+                .map(|ship| ship.and_then(&mut ids))
+                .collect(),
+            queue: to_alive_ids(&self.queue, &mut ids),
+        })
+    }
+
+    fn convert_from<F>(data: Self::Data, mut ids: F) -> Result<Self, Self::Error>
+    where
+        F: FnMut(SaveMarker) -> Option<Entity>,
+    {
+        Ok(Station {
+            pos: data.pos,
+            entrance_dir: data.entrance_dir,
+            entrance_distance: data.entrance_distance,
+            dock_capacity: data.dock_capacity,
+            docks: data
+                .docks
+                .into_iter()
+                .map(|ship| ship.map(|ship| from_id(ship, &mut ids)).transpose())
+                .collect::<Result<_, _>>()?,
+            queue: from_ids(data.queue, &mut ids)?,
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct LocationData {
+    pub sector: SaveMarker,
+}
+
+impl ConvertSaveload<SaveMarker> for Location {
+    type Data = LocationData;
+    type Error = SaveError;
+
+    fn convert_into<F>(&self, mut ids: F) -> Result<Self::Data, Self::Error>
+    where
+        F: FnMut(Entity) -> Option<SaveMarker>,
+    {
+        Ok(LocationData {
+            sector: to_id(self.sector, &mut ids)?,
+        })
+    }
+
+    fn convert_from<F>(data: Self::Data, mut ids: F) -> Result<Self, Self::Error>
+    where
+        F: FnMut(SaveMarker) -> Option<Entity>,
+    {
+        Ok(Location::new(from_id(data.sector, &mut ids)?))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct GateData {
+    pub pos: P2,
+    pub to_sector: SaveMarker,
+    pub to_pos: P2,
+}
+
+impl ConvertSaveload<SaveMarker> for Gate {
+    type Data = GateData;
+    type Error = SaveError;
+
+    fn convert_into<F>(&self, mut ids: F) -> Result<Self::Data, Self::Error>
+    where
+        F: FnMut(Entity) -> Option<SaveMarker>,
+    {
+        Ok(GateData {
+            pos: self.pos,
+            to_sector: to_id(self.to_sector, &mut ids)?,
+            to_pos: self.to_pos,
+        })
+    }
+
+    fn convert_from<F>(data: Self::Data, mut ids: F) -> Result<Self, Self::Error>
+    where
+        F: FnMut(SaveMarker) -> Option<Entity>,
+    {
+        Ok(Gate::new(
+            data.pos,
+            from_id(data.to_sector, &mut ids)?,
+            data.to_pos,
+        ))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct MoveCommandData {
+    pub to: P2,
+    pub arrival: bool,
+    pub predict: bool,
+    pub sector: Option<SaveMarker>,
+}
+
+impl ConvertSaveload<SaveMarker> for MoveCommand {
+    type Data = MoveCommandData;
+    type Error = SaveError;
+
+    fn convert_into<F>(&self, mut ids: F) -> Result<Self::Data, Self::Error>
+    where
+        F: FnMut(Entity) -> Option<SaveMarker>,
+    {
+        Ok(MoveCommandData {
+            to: self.to,
+            arrival: self.arrival,
+            predict: self.predict,
+            sector: self
+                .sector
+                .map(|sector| to_id(sector, &mut ids))
+                .transpose()?,
+        })
+    }
+
+    fn convert_from<F>(data: Self::Data, mut ids: F) -> Result<Self, Self::Error>
+    where
+        F: FnMut(SaveMarker) -> Option<Entity>,
+    {
+        Ok(MoveCommand {
+            to: data.to,
+            arrival: data.arrival,
+            predict: data.predict,
+            sector: data
+                .sector
+                .map(|sector| from_id(sector, &mut ids))
+                .transpose()?,
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct FollowCommandData {
+    pub target: SaveMarker,
+    pub relative_pos: P2,
+}
+
+impl ConvertSaveload<SaveMarker> for FollowCommand {
+    type Data = FollowCommandData;
+    type Error = SaveError;
+
+    fn convert_into<F>(&self, mut ids: F) -> Result<Self::Data, Self::Error>
+    where
+        F: FnMut(Entity) -> Option<SaveMarker>,
+    {
+        Ok(FollowCommandData {
+            target: to_id(self.target, &mut ids)?,
+            relative_pos: self.relative_pos,
+        })
+    }
+
+    fn convert_from<F>(data: Self::Data, mut ids: F) -> Result<Self, Self::Error>
+    where
+        F: FnMut(SaveMarker) -> Option<Entity>,
+    {
+        Ok(FollowCommand::new(
+            from_id(data.target, &mut ids)?,
+            data.relative_pos,
+        ))
+    }
+}
+
+/// Intercept and pursue commands
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TargetCommandData {
+    pub target: SaveMarker,
+}
+
+impl ConvertSaveload<SaveMarker> for InterceptCommand {
+    type Data = TargetCommandData;
+    type Error = SaveError;
+
+    fn convert_into<F>(&self, mut ids: F) -> Result<Self::Data, Self::Error>
+    where
+        F: FnMut(Entity) -> Option<SaveMarker>,
+    {
+        Ok(TargetCommandData {
+            target: to_id(self.target, &mut ids)?,
+        })
+    }
+
+    fn convert_from<F>(data: Self::Data, mut ids: F) -> Result<Self, Self::Error>
+    where
+        F: FnMut(SaveMarker) -> Option<Entity>,
+    {
+        Ok(InterceptCommand::new(from_id(data.target, &mut ids)?))
+    }
+}
+
+impl ConvertSaveload<SaveMarker> for PursueCommand {
+    type Data = TargetCommandData;
+    type Error = SaveError;
+
+    fn convert_into<F>(&self, mut ids: F) -> Result<Self::Data, Self::Error>
+    where
+        F: FnMut(Entity) -> Option<SaveMarker>,
+    {
+        Ok(TargetCommandData {
+            target: to_id(self.target, &mut ids)?,
+        })
+    }
+
+    fn convert_from<F>(data: Self::Data, mut ids: F) -> Result<Self, Self::Error>
+    where
+        F: FnMut(SaveMarker) -> Option<Entity>,
+    {
+        Ok(PursueCommand::new(from_id(data.target, &mut ids)?))
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct TradeCommandData {
+    pub stations: Vec<SaveMarker>,
+    pub index: usize,
+    pub state: TradeCommandState,
+    pub next_index: Option<usize>,
+}
+
+impl ConvertSaveload<SaveMarker> for TradeCommand {
+    type Data = TradeCommandData;
+    type Error = SaveError;
+
+    fn convert_into<F>(&self, mut ids: F) -> Result<Self::Data, Self::Error>
+    where
+        F: FnMut(Entity) -> Option<SaveMarker>,
+    {
+        // deleted stations are dropped from the route
+        let alive: HashSet<Entity> = self
+            .stations
+            .iter()
+            .copied()
+            .filter(|station| ids(*station).is_some())
+            .collect();
+        let mut command = self.clone();
+        command.retain_stations(|station| alive.contains(&station));
+
+        Ok(TradeCommandData {
+            stations: to_alive_ids(&command.stations, &mut ids),
+            index: command.index,
+            state: command.state,
+            next_index: command.next_index,
+        })
+    }
+
+    fn convert_from<F>(data: Self::Data, mut ids: F) -> Result<Self, Self::Error>
+    where
+        F: FnMut(SaveMarker) -> Option<Entity>,
+    {
+        Ok(TradeCommand {
+            stations: from_ids(data.stations, &mut ids)?,
+            index: data.index,
+            state: data.state,
+            next_index: data.next_index,
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub enum CommandData {
+    Move(MoveCommandData),
+    Patrol(PatrolCommand),
+    Follow(FollowCommandData),
+    Trade(TradeCommandData),
+    Intercept(TargetCommandData),
+    Pursue(TargetCommandData),
+}
+
+impl ConvertSaveload<SaveMarker> for Command {
+    type Data = CommandData;
+    type Error = SaveError;
+
+    fn convert_into<F>(&self, mut ids: F) -> Result<Self::Data, Self::Error>
+    where
+        F: FnMut(Entity) -> Option<SaveMarker>,
+    {
+        Ok(match self {
+            Command::Move(command) => CommandData::Move(command.convert_into(&mut ids)?),
+            Command::Patrol(command) => CommandData::Patrol(command.clone()),
+            Command::Follow(command) => CommandData::Follow(command.convert_into(&mut ids)?),
+            Command::Trade(command) => CommandData::Trade(command.convert_into(&mut ids)?),
+            Command::Intercept(command) => CommandData::Intercept(command.convert_into(&mut ids)?),
+            Command::Pursue(command) => CommandData::Pursue(command.convert_into(&mut ids)?),
+        })
+    }
+
+    fn convert_from<F>(data: Self::Data, mut ids: F) -> Result<Self, Self::Error>
+    where
+        F: FnMut(SaveMarker) -> Option<Entity>,
+    {
+        Ok(match data {
+            CommandData::Move(data) => Command::Move(MoveCommand::convert_from(data, &mut ids)?),
+            CommandData::Patrol(command) => Command::Patrol(command),
+            CommandData::Follow(data) => {
+                Command::Follow(FollowCommand::convert_from(data, &mut ids)?)
+            }
+            CommandData::Trade(data) => Command::Trade(TradeCommand::convert_from(data, &mut ids)?),
+            CommandData::Intercept(data) => {
+                Command::Intercept(InterceptCommand::convert_from(data, &mut ids)?)
+            }
+            CommandData::Pursue(data) => {
+                Command::Pursue(PursueCommand::convert_from(data, &mut ids)?)
+            }
+        })
+    }
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+pub struct CommandQueueData {
+    pub pending: Vec<CommandData>,
+    pub active: Option<CommandKind>,
+    pub cancel_active: bool,
+    pub suspend_active: bool,
+}
+
+impl ConvertSaveload<SaveMarker> for CommandQueue {
+    type Data = CommandQueueData;
+    type Error = SaveError;
+
+    fn convert_into<F>(&self, mut ids: F) -> Result<Self::Data, Self::Error>
+    where
+        F: FnMut(Entity) -> Option<SaveMarker>,
+    {
+        // commands whose target was deleted are dropped
+        let alive: Vec<&Command> = self
+            .pending
+            .iter()
+            .filter(|command| {
+                get_command_target(command)
+                    .map(|target| ids(target).is_some())
+                    .unwrap_or(true)
+            })
+            .collect();
+
+        let pending = alive
+            .into_iter()
+            .map(|command| command.convert_into(&mut ids))
+            .collect::<Result<_, _>>()?;
+
+        Ok(CommandQueueData {
+            pending,
+            active: self.active,
+            cancel_active: self.cancel_active,
+            suspend_active: self.suspend_active,
+        })
+    }
+
+    fn convert_from<F>(data: Self::Data, mut ids: F) -> Result<Self, Self::Error>
+    where
+        F: FnMut(SaveMarker) -> Option<Entity>,
+    {
+        let pending = data
+            .pending
+            .into_iter()
+            .map(|command| Command::convert_from(command, &mut ids))
+            .collect::<Result<VecDeque<_>, _>>()?;
+
+        Ok(CommandQueue {
+            pending,
+            active: data.active,
+            cancel_active: data.cancel_active,
+            suspend_active: data.suspend_active,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::scenery::*;
+    use crate::simulation::Simulation;
+
+    fn run(simulation: &mut Simulation, seconds: f32) {
+        let delta = 1.0 / 30.0;
+        for _ in 0..(seconds / delta) as usize {
+            simulation.update(delta).unwrap();
+        }
+    }
+
+    fn get_id(world: &World, entity: Entity) -> u64 {
+        world.read_storage::<SaveMarker>().get(entity).unwrap().id()
+    }
+
+    /// Entity of `loaded` with the same id as `entity` in `world`
+    fn find_loaded(world: &World, loaded: &World, entity: Entity) -> Entity {
+        let id = get_id(world, entity);
+        (&loaded.entities(), &loaded.read_storage::<SaveMarker>())
+            .join()
+            .find(|(_, marker)| marker.id() == id)
+            .map(|(entity, _)| entity)
+            .unwrap()
+    }
+
+    #[test]
+    fn test_save_and_load_keep_entity_references() {
+        let mut simulation = Simulation::new(Cfg {
+            avoidance: false,
+            ..Default::default()
+        });
+        scenery_patrol_and_follow(simulation.get_world_mut());
+        scenery_two_stations(simulation.get_world_mut());
+
+        let leader = simulation.list_movables()[0];
+        let mut cargo = Cargo::new(10.0, 0.0);
+        cargo.add(WARE_FOOD, 5.0);
+        let ship = simulation
+            .get_world_mut()
+            .create_entity()
+            .with(Movable::new(P2::new(0.0, 0.0), 50.0, 100.0))
+            .with(cargo)
+            .build();
+        simulation
+            .enqueue_command(
+                ship,
+                Command::Follow(FollowCommand::new(leader, P2::new(-20.0, 0.0))),
+            )
+            .unwrap();
+        simulation
+            .interrupt_command(
+                ship,
+                Command::Patrol(PatrolCommand::new(vec![P2::new(50.0, 0.0)])),
+            )
+            .unwrap();
+
+        run(&mut simulation, 2.0);
+
+        let data = simulation.save().unwrap();
+        let mut loaded = Simulation::load(&data).unwrap();
+
+        assert_eq!(loaded.get_total_time(), simulation.get_total_time());
+        assert!(!loaded.get_cfg().avoidance);
+
+        {
+            let world = simulation.get_world();
+            let loaded_world = loaded.get_world();
+            let loaded_by_id: std::collections::HashMap<u64, Entity> = (
+                &loaded_world.entities(),
+                &loaded_world.read_storage::<SaveMarker>(),
+            )
+                .join()
+                .map(|(entity, marker)| (marker.id(), entity))
+                .collect();
+            let find = |entity: Entity| loaded_by_id[&get_id(world, entity)];
+
+            for entity in simulation.list_movables() {
+                let movable = simulation.get_movable(entity).unwrap();
+                assert_eq!(loaded.get_movable(find(entity)).unwrap().pos, movable.pos);
+            }
+
+            let follows = world.read_storage::<FollowCommand>();
+            let loaded_follows = loaded_world.read_storage::<FollowCommand>();
+            let mut follow_count = 0;
+            for (entity, follow) in (&world.entities(), &follows).join() {
+                let loaded_follow = loaded_follows.get(find(entity)).unwrap();
+                assert_eq!(loaded_follow.target, find(follow.target));
+                follow_count += 1;
+            }
+            assert_eq!(follow_count, 3);
+
+            let trades = world.read_storage::<TradeCommand>();
+            let loaded_trades = loaded_world.read_storage::<TradeCommand>();
+            for (entity, trade) in (&world.entities(), &trades).join() {
+                let loaded_trade = loaded_trades.get(find(entity)).unwrap();
+                let stations: Vec<Entity> = trade.stations.iter().map(|e| find(*e)).collect();
+                assert_eq!(loaded_trade.stations, stations);
+                assert_eq!(loaded_trade.state, trade.state);
+            }
+
+            let cargo = loaded.get_cargo(find(ship)).unwrap();
+            assert_eq!(cargo.get_amount(WARE_FOOD), 5.0);
+
+            // the follow command is still waiting the interruption to complete
+            let queue = loaded.get_command_queue(find(ship)).unwrap();
+            assert_eq!(queue.get_active(), Some(CommandKind::Patrol));
+            match &queue.get_pending()[0] {
+                Command::Follow(command) => assert_eq!(command.target, find(leader)),
+                other => panic!("unexpected command {:?}", other),
+            }
+        }
+
+        // new entities do not reuse the ids of the loaded ones
+        let new_ship = loaded
+            .get_world_mut()
+            .create_entity()
+            .with(Movable::new(P2::new(0.0, 0.0), 50.0, 100.0))
+            .build();
+        run(&mut loaded, 2.0);
+        let data = loaded.save().unwrap();
+        let reloaded = Simulation::load(&data).unwrap();
+        assert_eq!(
+            reloaded.list_movables().len(),
+            simulation.list_movables().len() + 1
+        );
+        let ids: Vec<u64> = loaded
+            .list_movables()
+            .into_iter()
+            .map(|entity| get_id(loaded.get_world(), entity))
+            .collect();
+        let new_id = get_id(loaded.get_world(), new_ship);
+        assert_eq!(ids.iter().filter(|id| **id == new_id).count(), 1);
+    }
+
+    #[test]
+    fn test_save_drop_references_to_deleted_entities() {
+        let mut simulation = Simulation::new(Default::default());
+        scenery_patrol_and_follow(simulation.get_world_mut());
+        scenery_two_stations(simulation.get_world_mut());
+
+        let leader = simulation.list_movables()[0];
+        let station_0 = simulation.list_stations()[0];
+        let station_1 = simulation.list_stations()[1];
+        let ship = simulation
+            .get_world_mut()
+            .create_entity()
+            .with(Movable::new(P2::new(0.0, 0.0), 50.0, 100.0))
+            .build();
+        simulation
+            .enqueue_command(
+                ship,
+                Command::Follow(FollowCommand::new(leader, P2::new(-20.0, 0.0))),
+            )
+            .unwrap();
+        simulation
+            .interrupt_command(
+                ship,
+                Command::Patrol(PatrolCommand::new(vec![P2::new(50.0, 0.0)])),
+            )
+            .unwrap();
+
+        run(&mut simulation, 1.0);
+        simulation.get_world_mut().delete_entity(leader).unwrap();
+        simulation.get_world_mut().delete_entity(station_0).unwrap();
+
+        let data = simulation.save().unwrap();
+        let loaded = Simulation::load(&data).unwrap();
+        let world = simulation.get_world();
+        let loaded_world = loaded.get_world();
+
+        // only the follower of an escort keeps following
+        let follows = loaded_world.read_storage::<FollowCommand>();
+        assert_eq!(follows.join().count(), 1);
+        for follow in follows.join() {
+            assert!(loaded.get_movable(follow.target).is_some());
+        }
+
+        let queue = loaded
+            .get_command_queue(find_loaded(world, loaded_world, ship))
+            .unwrap();
+        assert_eq!(queue.get_active(), Some(CommandKind::Patrol));
+        assert!(queue.get_pending().is_empty());
+
+        let trades = loaded_world.read_storage::<TradeCommand>();
+        assert_eq!(trades.join().count(), 10);
+        for trade in trades.join() {
+            assert_eq!(
+                trade.stations,
+                vec![find_loaded(world, loaded_world, station_1)]
+            );
+        }
+    }
+
+    #[test]
+    fn test_save_and_load_keep_sectors_and_stations_references() {
+        let mut simulation = Simulation::new(Default::default());
+        scenery_sectors(simulation.get_world_mut());
+
+        let sector_0 = simulation.list_sectors()[0];
+        let sector_1 = simulation.list_sectors()[1];
+        let traders = simulation.list_movables();
+        let ship = simulation
+            .get_world_mut()
+            .create_entity()
+            .with(Location::new(sector_0))
+            .with(Movable::new(P2::new(0.0, 0.0), 50.0, 100.0))
+            .build();
+        for &sector in &[sector_1, sector_0] {
+            simulation
+                .enqueue_command(
+                    ship,
+                    Command::Move(MoveCommand {
+                        to: P2::new(400.0, 300.0),
+                        arrival: true,
+                        predict: false,
+                        sector: Some(sector),
+                    }),
+                )
+                .unwrap();
+        }
+
+        run(&mut simulation, 0.1);
+
+        let station = simulation.list_stations()[0];
+        {
+            let mut stations = simulation.get_world_mut().write_storage::<Station>();
+            let station = stations.get_mut(station).unwrap();
+            station.docks = vec![None, Some(traders[0])];
+            station.queue = vec![traders[1], traders[2]];
+        }
+
+        let data = simulation.save().unwrap();
+        let loaded = Simulation::load(&data).unwrap();
+        let world = simulation.get_world();
+        let loaded_world = loaded.get_world();
+        let find = |entity: Entity| find_loaded(world, loaded_world, entity);
+
+        let loaded_station = loaded.get_station(find(station)).unwrap();
+        assert_eq!(loaded_station.docks, vec![None, Some(find(traders[0]))]);
+        assert_eq!(
+            loaded_station.queue,
+            vec![find(traders[1]), find(traders[2])]
+        );
+
+        let locations = world.read_storage::<Location>();
+        let loaded_locations = loaded_world.read_storage::<Location>();
+        let mut location_count = 0;
+        for (entity, location) in (&world.entities(), &locations).join() {
+            let loaded_location = loaded_locations.get(find(entity)).unwrap();
+            assert_eq!(loaded_location.sector, find(location.sector));
+            location_count += 1;
+        }
+        // gates, stations, traders and the ship
+        assert_eq!(location_count, 11);
+
+        let gates = world.read_storage::<Gate>();
+        let loaded_gates = loaded_world.read_storage::<Gate>();
+        for (entity, gate) in (&world.entities(), &gates).join() {
+            let loaded_gate = loaded_gates.get(find(entity)).unwrap();
+            assert_eq!(loaded_gate.to_sector, find(gate.to_sector));
+            assert_eq!(loaded_gate.to_pos, gate.to_pos);
+        }
+
+        let loaded_ship = find(ship);
+        let moves = loaded_world.read_storage::<MoveCommand>();
+        assert_eq!(moves.get(loaded_ship).unwrap().sector, Some(find(sector_1)));
+        let queue = loaded.get_command_queue(loaded_ship).unwrap();
+        match &queue.get_pending()[0] {
+            Command::Move(command) => assert_eq!(command.sector, Some(find(sector_0))),
+            other => panic!("unexpected command {:?}", other),
+        }
+    }
+}
@@ -0,0 +1,179 @@
+//! Worlds built from code, used by the gui and tests
+
+use crate::components::*;
+use crate::math::*;
+
+use ggez::graphics;
+use nalgebra::Point2;
+use rand::{thread_rng, Rng};
+use specs::prelude::*;
+use specs::{World, WorldExt};
+
+pub fn scenery_patrol_and_follow(world: &mut World) {
+    {
+        let entity_0 = world
+            .create_entity()
+            .with(Model::new(6.0, graphics::WHITE))
+            .with(Movable::new(Point2::new(400.0, 300.0), 80.0, 70.0))
+            .with(
+                PatrolCommand::new(vec![
+                    Point2::new(200.0, 300.0),
+                    Point2::new(400.0, 150.0),
+                    Point2::new(600.0, 300.0),
+                    Point2::new(400.0, 550.0),
+                ])
+                .with_arrival_speed(30.0),
+            )
+            .build();
+
+        let formation = EscortFormation::Line { spacing: 10.0 };
+
+        let escort_0 = world
+            .create_entity()
+            .with(Model::new(2.0, graphics::Color::new(1.0, 0.0, 0.0, 1.0)))
+            .with(Movable::new(Point2::new(450.0, 320.0), 70.0, 40.0))
+            .with(FollowCommand::escort(entity_0, &formation, 1))
+            .build();
+
+        world
+            .create_entity()
+            .with(Model::new(2.0, graphics::Color::new(0.0, 1.0, 0.0, 1.0)))
+            .with(Movable::new(Point2::new(450.0, 320.0), 55.0, 80.0))
+            .with(FollowCommand::escort(entity_0, &formation, 0))
+            .build();
+
+        // chain, following a follower
+        world
+            .create_entity()
+            .with(Model::new(2.0, graphics::Color::new(1.0, 0.0, 1.0, 1.0)))
+            .with(Movable::new(Point2::new(450.0, 340.0), 60.0, 60.0))
+            .with(FollowCommand::escort(
+                escort_0,
+                &EscortFormation::Column { spacing: 10.0 },
+                0,
+            ))
+            .build();
+    }
+}
+
+pub const WARE_ORE: WareId = WareId(0);
+pub const WARE_FOOD: WareId = WareId(1);
+
+pub fn scenery_two_stations(world: &mut World) {
+    let station_0 = world
+        .create_entity()
+        .with(Model::new(15.0, graphics::Color::new(0.0, 1.0, 0.0, 1.0)))
+        .with(Station::new(
+            Point2::new(110.0, 200.0),
+            V2::new(1.0, 0.0),
+            40.0,
+        ))
+        .with(
+            Inventory::new()
+                .with(WARE_ORE, StationWare::new(50.0, 200.0, 2.0, 10.0))
+                .with(WARE_FOOD, StationWare::new(0.0, 100.0, -1.0, 20.0)),
+        )
+        .build();
+
+    let station_1 = world
+        .create_entity()
+        .with(Model::new(15.0, graphics::Color::new(1.0, 0.0, 0.0, 1.0)))
+        .with(Station::new(
+            Point2::new(700.0, 300.0),
+            V2::new(0.0, 1.0),
+            40.0,
+        ))
+        .with(
+            Inventory::new()
+                .with(WARE_ORE, StationWare::new(0.0, 200.0, -2.0, 10.0))
+                .with(WARE_FOOD, StationWare::new(50.0, 100.0, 1.0, 20.0)),
+        )
+        .build();
+
+    let mut rng = thread_rng();
+
+    for _ in 0..10 {
+        let x = rng.gen_range(0, 800) as f32;
+        let y = rng.gen_range(0, 600) as f32;
+        let speed = rng.gen_range(20, 100) as f32;
+        let acc = rng.gen_range(50, 100) as f32;
+
+        world
+            .create_entity()
+            .with(Model::new(4.0, graphics::WHITE))
+            .with(Movable::new(Point2::new(x, y), speed, acc))
+            .with(Cargo::new(20.0, 500.0))
+            .with(TradeCommand::new(vec![station_0, station_1]))
+            .build();
+    }
+}
+
+/// Two sectors linked by a pair of gates, each one with a station, and traders between them
+pub fn scenery_sectors(world: &mut World) {
+    let sector_0 = world.create_entity().with(Sector::new("alpha")).build();
+    let sector_1 = world.create_entity().with(Sector::new("beta")).build();
+
+    let gate_color = graphics::Color::new(0.6, 0.2, 1.0, 1.0);
+    for &(sector, pos, to_sector, to_pos) in &[
+        (
+            sector_0,
+            Point2::new(700.0, 300.0),
+            sector_1,
+            Point2::new(130.0, 300.0),
+        ),
+        (
+            sector_1,
+            Point2::new(100.0, 300.0),
+            sector_0,
+            Point2::new(670.0, 300.0),
+        ),
+    ] {
+        world
+            .create_entity()
+            .with(Model::new(10.0, gate_color))
+            .with(Location::new(sector))
+            .with(Gate::new(pos, to_sector, to_pos))
+            .build();
+    }
+
+    let station_0 = world
+        .create_entity()
+        .with(Model::new(15.0, graphics::Color::new(0.0, 1.0, 0.0, 1.0)))
+        .with(Location::new(sector_0))
+        .with(Station::new(
+            Point2::new(200.0, 300.0),
+            V2::new(1.0, 0.0),
+            40.0,
+        ))
+        .with(Inventory::new().with(WARE_ORE, StationWare::new(50.0, 200.0, 2.0, 10.0)))
+        .build();
+
+    let station_1 = world
+        .create_entity()
+        .with(Model::new(15.0, graphics::Color::new(1.0, 0.0, 0.0, 1.0)))
+        .with(Location::new(sector_1))
+        .with(Station::new(
+            Point2::new(600.0, 300.0),
+            V2::new(-1.0, 0.0),
+            40.0,
+        ))
+        .with(Inventory::new().with(WARE_ORE, StationWare::new(0.0, 200.0, -2.0, 10.0)))
+        .build();
+
+    let mut rng = thread_rng();
+
+    for i in 0..6 {
+        let sector = if i % 2 == 0 { sector_0 } else { sector_1 };
+        let x = rng.gen_range(0, 800) as f32;
+        let y = rng.gen_range(0, 600) as f32;
+
+        world
+            .create_entity()
+            .with(Model::new(4.0, graphics::WHITE))
+            .with(Location::new(sector))
+            .with(Movable::new(Point2::new(x, y), 60.0, 80.0))
+            .with(Cargo::new(20.0, 500.0))
+            .with(TradeCommand::new(vec![station_0, station_1]))
+            .build();
+    }
+}
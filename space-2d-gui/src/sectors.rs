@@ -0,0 +1,158 @@
+//! Find the gates to cross to travel between sectors
+
+use crate::math::*;
+
+use specs::Entity;
+
+/// A gate as seen by the pathfinding. Sector none is the default sector of entities without
+/// `Location`.
+#[derive(Clone, Debug)]
+pub struct GateLink {
+    pub gate: Entity,
+    pub sector: Option<Entity>,
+    pub pos: P2,
+    pub to_sector: Option<Entity>,
+    pub to_pos: P2,
+}
+
+/// Gates to cross, in order, for the shortest travel from `from_pos` in `from_sector` to `to_pos`
+/// in `to_sector`. Empty when in the same sector, none when there is no path.
+pub fn find_path(
+    links: &[GateLink],
+    from_sector: Option<Entity>,
+    from_pos: P2,
+    to_sector: Option<Entity>,
+    to_pos: P2,
+) -> Option<Vec<Entity>> {
+    if from_sector == to_sector {
+        return Some(vec![]);
+    }
+
+    // distance travelled to reach each gate, and the previous gate crossed
+    let mut distances: Vec<Option<f32>> = links
+        .iter()
+        .map(|link| {
+            if link.sector == from_sector {
+                Some((link.pos - from_pos).magnitude())
+            } else {
+                None
+            }
+        })
+        .collect();
+    let mut previous: Vec<Option<usize>> = vec![None; links.len()];
+    let mut visited = vec![false; links.len()];
+
+    loop {
+        let current = closest(
+            (0..links.len())
+                .filter(|i| !visited[*i])
+                .filter_map(|i| distances[i].map(|distance| (i, distance))),
+        );
+
+        let (current, distance) = match current {
+            Some(current) => current,
+            None => break,
+        };
+        visited[current] = true;
+
+        let link = &links[current];
+        for (next, next_link) in links.iter().enumerate() {
+            if visited[next] || next_link.sector != link.to_sector {
+                continue;
+            }
+
+            let next_distance = distance + (next_link.pos - link.to_pos).magnitude();
+            if distances[next].map(|d| next_distance < d).unwrap_or(true) {
+                distances[next] = Some(next_distance);
+                previous[next] = Some(current);
+            }
+        }
+    }
+
+    // best last gate into the target sector
+    let last = closest(
+        links
+            .iter()
+            .enumerate()
+            .filter(|(_, link)| link.to_sector == to_sector)
+            .filter_map(|(i, link)| {
+                distances[i].map(|distance| (i, distance + (to_pos - link.to_pos).magnitude()))
+            }),
+    )?;
+
+    let mut path = vec![];
+    let mut current = Some(last.0);
+    while let Some(index) = current {
+        path.push(links[index].gate);
+        current = previous[index];
+    }
+    path.reverse();
+
+    Some(path)
+}
+
+/// Index with the smallest distance
+fn closest<I>(candidates: I) -> Option<(usize, f32)>
+where
+    I: Iterator<Item = (usize, f32)>,
+{
+    candidates.fold(None, |best, (i, distance)| match best {
+        Some((_, best_distance)) if best_distance <= distance => best,
+        _ => Some((i, distance)),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use specs::{Builder, World, WorldExt};
+
+    fn link(
+        gate: Entity,
+        sector: Entity,
+        pos: (f32, f32),
+        to_sector: Entity,
+        to_pos: (f32, f32),
+    ) -> GateLink {
+        GateLink {
+            gate,
+            sector: Some(sector),
+            pos: P2::new(pos.0, pos.1),
+            to_sector: Some(to_sector),
+            to_pos: P2::new(to_pos.0, to_pos.1),
+        }
+    }
+
+    #[test]
+    fn test_find_shortest_path_between_sectors() {
+        let mut world = World::new();
+        let mut new_entity = || world.create_entity().build();
+        let (a, b, c, d) = (new_entity(), new_entity(), new_entity(), new_entity());
+        let gates: Vec<Entity> = (0..5).map(|_| new_entity()).collect();
+
+        // a -> b -> c is shorter than the direct a -> c, on the other side of a
+        let links = vec![
+            link(gates[0], a, (100.0, 0.0), b, (0.0, 0.0)),
+            link(gates[1], b, (10.0, 0.0), c, (0.0, 0.0)),
+            link(gates[2], a, (-500.0, 0.0), c, (0.0, 0.0)),
+            link(gates[3], b, (0.0, 0.0), a, (100.0, 0.0)),
+            link(gates[4], d, (0.0, 0.0), a, (0.0, 0.0)),
+        ];
+
+        let origin = P2::new(0.0, 0.0);
+        assert_eq!(
+            find_path(&links, Some(a), origin, Some(c), origin),
+            Some(vec![gates[0], gates[1]])
+        );
+        assert_eq!(
+            find_path(&links, Some(b), origin, Some(a), origin),
+            Some(vec![gates[3]])
+        );
+        assert_eq!(
+            find_path(&links, Some(a), origin, Some(a), origin),
+            Some(vec![])
+        );
+        // there is no gate into d
+        assert_eq!(find_path(&links, Some(a), origin, Some(d), origin), None);
+    }
+}
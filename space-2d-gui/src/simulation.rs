@@ -0,0 +1,399 @@
+use crate::components::*;
+use crate::math::*;
+use crate::save::*;
+use crate::systems::*;
+
+use ggez::{GameError, GameResult};
+use specs::prelude::*;
+use specs::{World, WorldExt};
+
+/// Owns the world and advance it, independent of any rendering
+pub struct Simulation {
+    world: World,
+}
+
+impl Simulation {
+    pub fn new(cfg: Cfg) -> Self {
+        let mut world = World::new();
+        world.register::<Model>();
+        world.register::<Movable>();
+        world.register::<Station>();
+        world.register::<MoveCommand>();
+        world.register::<PatrolCommand>();
+        world.register::<FollowCommand>();
+        world.register::<MovementPrediction>();
+        world.register::<Cfg>();
+        world.register::<Time>();
+        world.register::<TradeCommand>();
+        world.register::<InterceptCommand>();
+        world.register::<PursueCommand>();
+        world.register::<CommandQueue>();
+        world.register::<Cargo>();
+        world.register::<Inventory>();
+        world.register::<Sector>();
+        world.register::<Location>();
+        world.register::<Gate>();
+        world.register::<SaveMarker>();
+
+        world.insert(Debug::new());
+        world.insert(SaveMarkerAllocator::new());
+        world.insert(cfg);
+        world.insert(Time {
+            total_time: 0.0,
+            delta_time: 0.0,
+        });
+
+        Simulation { world }
+    }
+
+    /// Simulation with the world saved by `save`
+    pub fn load(data: &str) -> GameResult<Self> {
+        let mut simulation = Simulation::new(Default::default());
+        load_world(&mut simulation.world, data)?;
+        Ok(simulation)
+    }
+
+    /// Serialize the world, entities keep their ids in the loaded simulation
+    pub fn save(&mut self) -> GameResult<String> {
+        save_world(&mut self.world)
+    }
+
+    pub fn get_world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn get_world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    /// Advance the simulation by `delta` seconds, nothing moves when `Cfg::pause` is set
+    pub fn update(&mut self, delta: f32) -> GameResult<()> {
+        {
+            let time = &mut self.world.write_resource::<Time>();
+            time.total_time += delta;
+            time.delta_time = delta;
+        }
+
+        if !self.world.read_resource::<Cfg>().pause {
+            command_queue_system(&mut self.world)?;
+            follow_command_system(&mut self.world)?;
+            patrol_command_system(&mut self.world)?;
+            move_command_system(&mut self.world)?;
+            intercept_command_system(&mut self.world)?;
+            pursue_command_system(&mut self.world)?;
+            trade_command_system(&mut self.world)?;
+            avoidance_system(&mut self.world)?;
+            movable_system(delta, &mut self.world)?;
+            production_system(delta, &mut self.world)?;
+        }
+
+        model_system(&mut self.world)?;
+
+        Ok(())
+    }
+
+    pub fn get_total_time(&self) -> f32 {
+        self.world.read_resource::<Time>().total_time
+    }
+
+    pub fn get_cfg(&self) -> Cfg {
+        let cfg: &Cfg = &self.world.read_resource::<Cfg>();
+        cfg.clone()
+    }
+
+    pub fn set_cfg(&mut self, cfg: Cfg) {
+        self.world.insert(cfg);
+    }
+
+    pub fn get_pos(&self, entity: Entity) -> Option<P2> {
+        if let Some(movable) = self.world.read_storage::<Movable>().get(entity) {
+            return Some(movable.pos);
+        }
+
+        self.world
+            .read_storage::<Station>()
+            .get(entity)
+            .map(|station| station.pos)
+    }
+
+    /// Sector of the entity, none when is in the default one
+    pub fn get_sector(&self, entity: Entity) -> Option<Entity> {
+        self.world
+            .read_storage::<Location>()
+            .get(entity)
+            .map(|location| location.sector)
+    }
+
+    pub fn list_sectors(&self) -> Vec<Entity> {
+        let entities = self.world.entities();
+        let sectors = self.world.read_storage::<Sector>();
+        (&entities, &sectors).join().map(|(e, _)| e).collect()
+    }
+
+    pub fn get_movable(&self, entity: Entity) -> Option<Movable> {
+        self.world.read_storage::<Movable>().get(entity).cloned()
+    }
+
+    pub fn get_station(&self, entity: Entity) -> Option<Station> {
+        self.world.read_storage::<Station>().get(entity).cloned()
+    }
+
+    pub fn get_patrol_command(&self, entity: Entity) -> Option<PatrolCommand> {
+        self.world
+            .read_storage::<PatrolCommand>()
+            .get(entity)
+            .cloned()
+    }
+
+    pub fn get_trade_command(&self, entity: Entity) -> Option<TradeCommand> {
+        self.world
+            .read_storage::<TradeCommand>()
+            .get(entity)
+            .cloned()
+    }
+
+    pub fn get_cargo(&self, entity: Entity) -> Option<Cargo> {
+        self.world.read_storage::<Cargo>().get(entity).cloned()
+    }
+
+    pub fn get_inventory(&self, entity: Entity) -> Option<Inventory> {
+        self.world.read_storage::<Inventory>().get(entity).cloned()
+    }
+
+    pub fn get_prediction(&self, entity: Entity) -> Option<MovementPrediction> {
+        self.world
+            .read_storage::<MovementPrediction>()
+            .get(entity)
+            .cloned()
+    }
+
+    /// All entities that can move
+    pub fn list_movables(&self) -> Vec<Entity> {
+        let entities = self.world.entities();
+        let movables = self.world.read_storage::<Movable>();
+        (&entities, &movables).join().map(|(e, _)| e).collect()
+    }
+
+    pub fn list_stations(&self) -> Vec<Entity> {
+        let entities = self.world.entities();
+        let stations = self.world.read_storage::<Station>();
+        (&entities, &stations).join().map(|(e, _)| e).collect()
+    }
+
+    pub fn get_command_queue(&self, entity: Entity) -> Option<CommandQueue> {
+        self.world
+            .read_storage::<CommandQueue>()
+            .get(entity)
+            .cloned()
+    }
+
+    pub fn enqueue_command(&mut self, entity: Entity, command: Command) -> GameResult<()> {
+        self.update_command_queue(entity, |queue| queue.enqueue(command))
+    }
+
+    pub fn replace_command(&mut self, entity: Entity, command: Command) -> GameResult<()> {
+        self.update_command_queue(entity, |queue| queue.replace(command))
+    }
+
+    pub fn interrupt_command(&mut self, entity: Entity, command: Command) -> GameResult<()> {
+        self.update_command_queue(entity, |queue| queue.interrupt(command))
+    }
+
+    pub fn clear_commands(&mut self, entity: Entity) -> GameResult<()> {
+        self.update_command_queue(entity, |queue| queue.clear())
+    }
+
+    /// Replace the commands of each escort to follow the leader, in the formation slot of its
+    /// index
+    pub fn assign_escorts(
+        &mut self,
+        leader: Entity,
+        escorts: &[Entity],
+        formation: &EscortFormation,
+    ) -> GameResult<()> {
+        for (index, escort) in escorts.iter().enumerate() {
+            let command = FollowCommand::escort(leader, formation, index);
+            self.replace_command(*escort, Command::Follow(command))?;
+        }
+
+        Ok(())
+    }
+
+    /// Change the entity queue, creating it if not exists
+    fn update_command_queue<F>(&mut self, entity: Entity, f: F) -> GameResult<()>
+    where
+        F: FnOnce(&mut CommandQueue),
+    {
+        let mut queues = self.world.write_storage::<CommandQueue>();
+        let queue = queues
+            .entry(entity)
+            .map_err(|_| GameError::EventLoopError(format!("invalid entity {:?}", entity)))?
+            .or_insert_with(CommandQueue::new);
+        f(queue);
+        Ok(())
+    }
+
+    /// Debug lines produced since last call
+    pub fn take_debug_lines(&mut self) -> Vec<(P2, P2, ggez::graphics::Color)> {
+        std::mem::replace(&mut self.world.write_resource::<Debug>().lines, Vec::new())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use ggez::graphics;
+
+    fn run(simulation: &mut Simulation, seconds: f32) {
+        let delta = 1.0 / 30.0;
+        for _ in 0..(seconds / delta) as usize {
+            simulation.update(delta).unwrap();
+        }
+    }
+
+    #[test]
+    fn test_patrol_visit_route_in_loop() {
+        let mut simulation = Simulation::new(Default::default());
+        let ship = simulation
+            .get_world_mut()
+            .create_entity()
+            .with(Movable::new(P2::new(0.0, 0.0), 50.0, 100.0))
+            .with(PatrolCommand::new(vec![
+                P2::new(100.0, 0.0),
+                P2::new(0.0, 0.0),
+            ]))
+            .build();
+
+        run(&mut simulation, 3.0);
+        assert_eq!(simulation.get_patrol_command(ship).unwrap().index, 1);
+        assert_eq!(simulation.get_prediction(ship).unwrap().points.len(), 4);
+
+        run(&mut simulation, 3.0);
+        assert_eq!(simulation.get_patrol_command(ship).unwrap().index, 0);
+    }
+
+    #[test]
+    fn test_trade_loop_between_stations() {
+        let mut simulation = Simulation::new(Default::default());
+        let world = simulation.get_world_mut();
+
+        let station_0 = world
+            .create_entity()
+            .with(Model::new(15.0, graphics::WHITE))
+            .with(Station::new(P2::new(0.0, 0.0), V2::new(1.0, 0.0), 40.0))
+            .build();
+        let station_1 = world
+            .create_entity()
+            .with(Model::new(15.0, graphics::WHITE))
+            .with(Station::new(P2::new(200.0, 0.0), V2::new(-1.0, 0.0), 40.0))
+            .build();
+        let ship = world
+            .create_entity()
+            .with(Model::new(4.0, graphics::WHITE))
+            .with(Movable::new(P2::new(100.0, 0.0), 50.0, 100.0))
+            .with(TradeCommand::new(vec![station_0, station_1]))
+            .build();
+
+        assert_eq!(simulation.list_movables(), vec![ship]);
+        assert_eq!(simulation.list_stations().len(), 2);
+
+        let mut visited = vec![];
+        let mut docked = false;
+        for _ in 0..(60.0 * 30.0) as usize {
+            simulation.update(1.0 / 30.0).unwrap();
+
+            let command = simulation.get_trade_command(ship).unwrap();
+            let is_docked = match command.state {
+                TradeCommandState::Docked { .. } => true,
+                _ => false,
+            };
+
+            if is_docked && !docked {
+                visited.push(command.current().unwrap());
+            }
+            docked = is_docked;
+        }
+
+        assert_eq!(&visited[0..3], &[station_0, station_1, station_0]);
+        assert!(simulation.get_total_time() > 59.9);
+    }
+
+    #[test]
+    fn test_trade_buy_and_sell_wares() {
+        let ore = WareId(0);
+
+        let mut simulation = Simulation::new(Default::default());
+        let world = simulation.get_world_mut();
+
+        let mine = world
+            .create_entity()
+            .with(Station::new(P2::new(0.0, 0.0), V2::new(1.0, 0.0), 40.0))
+            .with(Inventory::new().with(ore, StationWare::new(50.0, 100.0, 1.0, 10.0)))
+            .build();
+        let factory = world
+            .create_entity()
+            .with(Station::new(P2::new(200.0, 0.0), V2::new(-1.0, 0.0), 40.0))
+            .with(Inventory::new().with(ore, StationWare::new(0.0, 100.0, -0.5, 10.0)))
+            .build();
+        let ship = world
+            .create_entity()
+            .with(Movable::new(P2::new(40.0, 0.0), 50.0, 100.0))
+            .with(Cargo::new(10.0, 100.0))
+            .with(TradeCommand::new(vec![mine, factory]))
+            .build();
+
+        // bought when leaving the mine
+        let mut seconds = 0.0;
+        while simulation.get_trade_command(ship).unwrap().state != TradeCommandState::Undocking {
+            run(&mut simulation, 0.1);
+            seconds += 0.1;
+            assert!(seconds < 10.0, "never undocked");
+        }
+        assert_eq!(simulation.get_cargo(ship).unwrap().get_amount(ore), 10.0);
+        assert_eq!(
+            simulation.get_trade_command(ship).unwrap().next_index,
+            Some(1)
+        );
+
+        // sold when leaving the factory
+        loop {
+            run(&mut simulation, 0.1);
+            seconds += 0.1;
+            assert!(seconds < 30.0, "never undocked at the factory");
+
+            let command = simulation.get_trade_command(ship).unwrap();
+            if command.current() == Some(factory) && command.state == TradeCommandState::Undocking {
+                break;
+            }
+        }
+        let cargo = simulation.get_cargo(ship).unwrap();
+        assert!(cargo.credits > 100.0, "{:?}", cargo);
+        assert!(simulation.get_inventory(factory).unwrap().wares[&ore].amount > 0.0);
+    }
+
+    #[test]
+    fn test_pause_do_not_move() {
+        let mut simulation = Simulation::new(Cfg {
+            pause: true,
+            ..Default::default()
+        });
+        let ship = simulation
+            .get_world_mut()
+            .create_entity()
+            .with(Movable::new(P2::new(0.0, 0.0), 50.0, 100.0))
+            .with(MoveCommand {
+                to: P2::new(100.0, 0.0),
+                arrival: true,
+                predict: false,
+                sector: None,
+            })
+            .build();
+
+        run(&mut simulation, 1.0);
+        assert_eq!(simulation.get_pos(ship), Some(P2::new(0.0, 0.0)));
+
+        simulation.set_cfg(Default::default());
+        run(&mut simulation, 1.0);
+        assert!(simulation.get_pos(ship).unwrap().x > 0.0);
+    }
+}
@@ -0,0 +1,1968 @@
+use crate::avoidance::*;
+use crate::components::*;
+use crate::math::*;
+use crate::pursuit::*;
+use crate::sectors::*;
+use crate::trade::*;
+
+use ggez::{GameError, GameResult};
+use specs::prelude::*;
+use specs::{World, WorldExt};
+use std::borrow::BorrowMut;
+use std::collections::HashSet;
+
+pub const ARRIVAL_DISTANCE: f32 = 2.0;
+
+/// Storages of every command component
+struct CommandStorages<'a> {
+    moves: WriteStorage<'a, MoveCommand>,
+    patrols: WriteStorage<'a, PatrolCommand>,
+    follows: WriteStorage<'a, FollowCommand>,
+    trades: WriteStorage<'a, TradeCommand>,
+    intercepts: WriteStorage<'a, InterceptCommand>,
+    pursues: WriteStorage<'a, PursueCommand>,
+}
+
+impl<'a> CommandStorages<'a> {
+    fn contains(&self, entity: Entity, kind: CommandKind) -> bool {
+        match kind {
+            CommandKind::Move => self.moves.contains(entity),
+            CommandKind::Patrol => self.patrols.contains(entity),
+            CommandKind::Follow => self.follows.contains(entity),
+            CommandKind::Trade => self.trades.contains(entity),
+            CommandKind::Intercept => self.intercepts.contains(entity),
+            CommandKind::Pursue => self.pursues.contains(entity),
+        }
+    }
+
+    fn take(&mut self, entity: Entity, kind: CommandKind) -> Option<Command> {
+        match kind {
+            CommandKind::Move => self.moves.remove(entity).map(Command::Move),
+            CommandKind::Patrol => self.patrols.remove(entity).map(Command::Patrol),
+            CommandKind::Follow => self.follows.remove(entity).map(Command::Follow),
+            CommandKind::Trade => self.trades.remove(entity).map(Command::Trade),
+            CommandKind::Intercept => self.intercepts.remove(entity).map(Command::Intercept),
+            CommandKind::Pursue => self.pursues.remove(entity).map(Command::Pursue),
+        }
+    }
+
+    fn remove_all(&mut self, entity: Entity) {
+        self.moves.remove(entity);
+        self.patrols.remove(entity);
+        self.follows.remove(entity);
+        self.trades.remove(entity);
+        self.intercepts.remove(entity);
+        self.pursues.remove(entity);
+    }
+
+    fn attach(&mut self, entity: Entity, command: Command) -> GameResult<()> {
+        let result = match command {
+            Command::Move(command) => self.moves.insert(entity, command).map(|_| ()),
+            Command::Patrol(command) => self.patrols.insert(entity, command).map(|_| ()),
+            Command::Follow(command) => self.follows.insert(entity, command).map(|_| ()),
+            Command::Trade(command) => self.trades.insert(entity, command).map(|_| ()),
+            Command::Intercept(command) => self.intercepts.insert(entity, command).map(|_| ()),
+            Command::Pursue(command) => self.pursues.insert(entity, command).map(|_| ()),
+        };
+
+        result.map_err(|_| GameError::EventLoopError(format!("invalid entity {:?}", entity)))
+    }
+}
+
+/// Apply changes of each `CommandQueue` and activate the next command when the active one is
+/// complete. Must run before the command systems.
+pub fn command_queue_system(world: &mut World) -> GameResult<()> {
+    let entities = world.entities();
+    let mut queues = world.write_storage::<CommandQueue>();
+    let mut movables = world.write_storage::<Movable>();
+    let mut predictions = world.write_storage::<MovementPrediction>();
+    let mut commands = CommandStorages {
+        moves: world.write_storage::<MoveCommand>(),
+        patrols: world.write_storage::<PatrolCommand>(),
+        follows: world.write_storage::<FollowCommand>(),
+        trades: world.write_storage::<TradeCommand>(),
+        intercepts: world.write_storage::<InterceptCommand>(),
+        pursues: world.write_storage::<PursueCommand>(),
+    };
+
+    for (entity, queue) in (&*entities, &mut queues).join() {
+        let mut detached = false;
+
+        // the command system removed the component
+        if let Some(kind) = queue.active {
+            if !commands.contains(entity, kind) {
+                queue.active = None;
+            }
+        }
+
+        if let Some(kind) = queue.active {
+            if queue.cancel_active {
+                commands.take(entity, kind);
+                queue.active = None;
+                detached = true;
+            } else if queue.suspend_active {
+                if let Some(command) = commands.take(entity, kind) {
+                    let index = queue.pending.len().min(1);
+                    queue.pending.insert(index, command);
+                }
+                queue.active = None;
+                detached = true;
+            }
+        }
+
+        queue.cancel_active = false;
+        queue.suspend_active = false;
+
+        if queue.active.is_none() {
+            if let Some(command) = queue.pending.pop_front() {
+                // commands not created by the queue would conflict with it
+                commands.remove_all(entity);
+                queue.active = Some(command.kind());
+                commands.attach(entity, command)?;
+                detached = true;
+            }
+        }
+
+        if detached {
+            predictions.remove(entity);
+
+            if queue.active.is_none() {
+                if let Some(movable) = movables.get_mut(entity) {
+                    movable.desired_vel = V2::new(0.0, 0.0);
+                }
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// distance from the follow position to consider a follower behind
+pub const FOLLOWER_BEHIND_DISTANCE: f32 = 10.0;
+
+/// Keep followers at their position relative to the target. Followers behind slow down their
+/// target, through `Movable::follower_behind_max_speed`, so must run before the other command
+/// systems. When the target is gone the command is complete.
+pub fn follow_command_system(world: &mut World) -> GameResult<()> {
+    let entities = world.entities();
+    let mut follow_commands = world.write_storage::<FollowCommand>();
+    let mut movables = world.write_storage::<Movable>();
+    let predictions = &mut world.write_storage::<MovementPrediction>();
+    let mut locations = world.write_storage::<Location>();
+    let gates = world.read_storage::<Gate>();
+    let mut debug = world.write_resource::<Debug>();
+
+    let links = collect_gate_links(&entities, &gates, &locations);
+
+    //  collect for each follow the target position
+    let mut changes = vec![];
+    let mut follower_behind_flag = vec![];
+    let mut lost_targets = vec![];
+    let mut jumps = vec![];
+
+    for (entity, follow, movable) in (&*entities, &follow_commands, &movables).join() {
+        // deleted entities are not found in the storage
+        let target_movable = if let Some(m) = (&movables).get(follow.target) {
+            m
+        } else {
+            lost_targets.push(entity);
+            continue;
+        };
+
+        // the target jumped to other sector, follow it through the gates
+        let sector = locations.get(entity).map(|location| location.sector);
+        let target_sector = locations.get(follow.target).map(|location| location.sector);
+        if sector != target_sector {
+            match action_move_to_sector(&links, sector, movable, target_sector, target_movable.pos)
+            {
+                Some((result, gate)) => {
+                    changes.push((entity, result.desired_vel));
+                    follower_behind_flag.push((follow.target, movable.get_max_speed()));
+                    if result.complete {
+                        jumps.push((entity, gate));
+                    }
+                }
+                None => lost_targets.push(entity),
+            }
+            continue;
+        }
+
+        // update movable with target position
+        let mut relative_pos =
+            rotate_vector(target_movable.vel.normalize(), follow.relative_pos.clone());
+        if relative_pos.x.is_nan() || relative_pos.y.is_nan() {
+            relative_pos = follow.relative_pos.clone();
+        }
+
+        let target_pos = target_movable.pos.clone() + relative_pos.coords;
+        let delta_to_pos = target_pos - movable.pos.clone();
+        let mut desired_vel = target_movable.vel.clone() + delta_to_pos;
+
+        // followers can be leaders of others
+        let max_speed = movable.get_max_speed();
+        if desired_vel.magnitude() > max_speed {
+            desired_vel = desired_vel.normalize() * max_speed;
+        }
+
+        changes.push((entity, desired_vel));
+
+        predictions
+            .insert(
+                entity,
+                MovementPrediction {
+                    points: vec![movable.pos, target_pos],
+                },
+            )
+            .unwrap();
+
+        if delta_to_pos.magnitude() > FOLLOWER_BEHIND_DISTANCE {
+            follower_behind_flag.push((follow.target, max_speed));
+        }
+    }
+
+    for entity in lost_targets {
+        follow_commands.remove(entity);
+        predictions.remove(entity);
+        if let Some(movable) = movables.get_mut(entity) {
+            movable.desired_vel = V2::new(0.0, 0.0);
+        }
+    }
+
+    for (entity, desired_vel) in changes {
+        let movable = movables.get_mut(entity).unwrap();
+        movable.desired_vel = desired_vel;
+    }
+
+    for (movable,) in (&mut movables,).join() {
+        movable.follower_behind_max_speed = None;
+    }
+
+    for (entity, speed) in follower_behind_flag {
+        let movable = movables.get_mut(entity).unwrap();
+
+        movable.follower_behind_max_speed = match &movable.follower_behind_max_speed {
+            Some(value) if *value > speed => Some(speed),
+            Some(value) => Some(*value),
+            None => Some(speed),
+        };
+    }
+
+    apply_jumps(jumps, &gates, &mut locations, &mut movables)?;
+
+    Ok(())
+}
+
+pub fn patrol_command_system(world: &mut World) -> GameResult<()> {
+    let entities = world.entities();
+    let cfg = world.read_resource::<Cfg>();
+    let mut patrols = world.write_storage::<PatrolCommand>();
+    let mut predictions = world.write_storage::<MovementPrediction>();
+    let mut movable = world.write_storage::<Movable>();
+    let mut empty_routes = vec![];
+
+    // patrol
+    for (entity, command, movable) in (&*entities, &mut patrols, &mut movable).join() {
+        let target = match command.current() {
+            Some(target) => target,
+            None => {
+                empty_routes.push(entity);
+                continue;
+            }
+        };
+
+        let arrival_speed = if cfg.patrol_arrival {
+            command.arrival_speed
+        } else {
+            None
+        };
+
+        let mut result = action_move_to(
+            movable.pos,
+            target,
+            movable.get_max_speed(),
+            movable.max_acc,
+            arrival_speed,
+        );
+
+        let is_complete = result.complete;
+
+        // if we complete, get path for next step
+        let mut target = target;
+        if result.complete {
+            command.next();
+            target = command.current().unwrap_or(target);
+
+            result = action_move_to(
+                movable.pos,
+                target,
+                movable.get_max_speed(),
+                movable.max_acc,
+                arrival_speed,
+            );
+        }
+
+        // update movement
+        movable.desired_vel = result.desired_vel;
+
+        // update prediction
+        let prediction = predictions.borrow_mut().get_mut(entity);
+        if is_complete || prediction.is_none() {
+            let mut points = vec![];
+            points.push(movable.pos);
+            points.push(target);
+            points.extend(command.route_from_next());
+
+            predictions
+                .borrow_mut()
+                .insert(entity, MovementPrediction { points })
+                .unwrap();
+        } else {
+            prediction.unwrap().points[0] = movable.pos;
+        }
+    }
+
+    // nothing to patrol, the command queue moves to the next command
+    for entity in empty_routes {
+        patrols.remove(entity);
+        predictions.remove(entity);
+    }
+
+    Ok(())
+}
+
+pub fn move_command_system(world: &mut World) -> GameResult<()> {
+    let entities = &world.entities();
+    let mut move_commands = world.write_storage::<MoveCommand>();
+    let mut movables = world.write_storage::<Movable>();
+    let mut predictions = world.write_storage::<MovementPrediction>();
+    let mut locations = world.write_storage::<Location>();
+    let gates = world.read_storage::<Gate>();
+    let mut completes = vec![];
+    let mut jumps = vec![];
+
+    let links = collect_gate_links(entities, &gates, &locations);
+
+    // move to position
+    for (entity, movable, move_command, location) in (
+        entities,
+        &mut movables,
+        &mut move_commands,
+        locations.maybe(),
+    )
+        .join()
+    {
+        let sector = location.map(|location| location.sector);
+        let target_sector = move_command.sector.or(sector);
+
+        let (to, gate) =
+            match plan_travel(&links, sector, movable.pos, target_sector, move_command.to) {
+                Some(step) => step,
+                None => {
+                    // there is no path to the target sector
+                    movable.desired_vel = V2::new(0.0, 0.0);
+                    completes.push((entity, move_command.predict));
+                    continue;
+                }
+            };
+
+        let arrival_speed = if move_command.arrival && gate.is_none() {
+            Some(0.0)
+        } else {
+            None
+        };
+
+        let result = action_move_to(
+            movable.pos,
+            to,
+            movable.get_max_speed(),
+            movable.max_acc,
+            arrival_speed,
+        );
+        movable.desired_vel = result.desired_vel;
+
+        if result.complete {
+            match gate {
+                Some(gate) => jumps.push((entity, gate)),
+                None => completes.push((entity, move_command.predict)),
+            }
+        } else {
+            if move_command.predict {
+                let mut points = vec![];
+                points.push(movable.pos);
+                points.push(to);
+
+                predictions
+                    .insert(entity, MovementPrediction { points: points })
+                    .unwrap();
+            }
+        }
+    }
+
+    for (entity, was_predicted) in completes {
+        move_commands.remove(entity);
+        if was_predicted {
+            predictions.remove(entity);
+        }
+    }
+
+    apply_jumps(jumps, &gates, &mut locations, &mut movables)?;
+
+    Ok(())
+}
+
+fn collect_gate_links(
+    entities: &Entities,
+    gates: &ReadStorage<Gate>,
+    locations: &WriteStorage<Location>,
+) -> Vec<GateLink> {
+    (entities, gates, locations.maybe())
+        .join()
+        .map(|(entity, gate, location)| GateLink {
+            gate: entity,
+            sector: location.map(|location| location.sector),
+            pos: gate.pos,
+            to_sector: Some(gate.to_sector),
+            to_pos: gate.to_pos,
+        })
+        .collect()
+}
+
+/// Next position to move to reach the target, and the gate to jump when it is one. None when
+/// the target sector can not be reached.
+fn plan_travel(
+    links: &[GateLink],
+    sector: Option<Entity>,
+    pos: P2,
+    target_sector: Option<Entity>,
+    target_pos: P2,
+) -> Option<(P2, Option<Entity>)> {
+    let path = find_path(links, sector, pos, target_sector, target_pos)?;
+
+    let step = match path.first() {
+        Some(gate) => {
+            let link = links.iter().find(|link| link.gate == *gate)?;
+            (link.pos, Some(*gate))
+        }
+        None => (target_pos, None),
+    };
+
+    Some(step)
+}
+
+/// Move to the next gate toward a target in another sector, returning the gate to jump when
+/// reached. None when the target sector can not be reached.
+fn action_move_to_sector(
+    links: &[GateLink],
+    sector: Option<Entity>,
+    movable: &Movable,
+    target_sector: Option<Entity>,
+    target_pos: P2,
+) -> Option<(ActionMoveResult, Entity)> {
+    match plan_travel(links, sector, movable.pos, target_sector, target_pos)? {
+        (to, Some(gate)) => {
+            let result = action_move_to(
+                movable.pos,
+                to,
+                movable.get_max_speed(),
+                movable.max_acc,
+                None,
+            );
+            Some((result, gate))
+        }
+        (_, None) => None,
+    }
+}
+
+/// Move each ship to the exit of the gate it reached
+fn apply_jumps(
+    jumps: Vec<(Entity, Entity)>,
+    gates: &ReadStorage<Gate>,
+    locations: &mut WriteStorage<Location>,
+    movables: &mut WriteStorage<Movable>,
+) -> GameResult<()> {
+    for (entity, gate_entity) in jumps {
+        let gate = match gates.get(gate_entity) {
+            Some(gate) => gate,
+            None => continue,
+        };
+
+        if let Some(movable) = movables.get_mut(entity) {
+            movable.pos = gate.to_pos;
+        }
+
+        locations
+            .insert(entity, Location::new(gate.to_sector))
+            .map_err(|_| GameError::EventLoopError(format!("invalid entity {:?}", entity)))?;
+    }
+
+    Ok(())
+}
+
+/// distance to the target to complete an intercept
+pub const INTERCEPT_DISTANCE: f32 = 5.0;
+
+/// Move at max speed to the predicted meeting point with the target
+pub fn intercept_command_system(world: &mut World) -> GameResult<()> {
+    let entities = world.entities();
+    let mut commands = world.write_storage::<InterceptCommand>();
+    let mut movables = world.write_storage::<Movable>();
+    let mut predictions = world.write_storage::<MovementPrediction>();
+    let mut locations = world.write_storage::<Location>();
+    let gates = world.read_storage::<Gate>();
+
+    let links = collect_gate_links(&entities, &gates, &locations);
+
+    let mut changes = vec![];
+    let mut completes = vec![];
+    let mut jumps = vec![];
+
+    for (entity, command, movable) in (&*entities, &commands, &movables).join() {
+        let target = match movables.get(command.target) {
+            Some(target) => target,
+            None => {
+                completes.push(entity);
+                continue;
+            }
+        };
+
+        let sector = locations.get(entity).map(|location| location.sector);
+        let target_sector = locations
+            .get(command.target)
+            .map(|location| location.sector);
+        if sector != target_sector {
+            match action_move_to_sector(&links, sector, movable, target_sector, target.pos) {
+                Some((result, gate)) => {
+                    let gate_pos = gates.get(gate).map(|gate| gate.pos).unwrap_or(target.pos);
+                    changes.push((entity, result.desired_vel, gate_pos));
+                    if result.complete {
+                        jumps.push((entity, gate));
+                    }
+                }
+                None => completes.push(entity),
+            }
+            continue;
+        }
+
+        if (target.pos - movable.pos).magnitude() < INTERCEPT_DISTANCE {
+            completes.push(entity);
+            continue;
+        }
+
+        let point = get_meeting_point(movable, target);
+        let result = action_move_to(
+            movable.pos,
+            point,
+            movable.get_max_speed(),
+            movable.max_acc,
+            None,
+        );
+        changes.push((entity, result.desired_vel, point));
+    }
+
+    for (entity, desired_vel, point) in changes {
+        let movable = movables.get_mut(entity).unwrap();
+        movable.desired_vel = desired_vel;
+
+        predictions
+            .insert(
+                entity,
+                MovementPrediction {
+                    points: vec![movable.pos, point],
+                },
+            )
+            .unwrap();
+    }
+
+    for entity in completes {
+        commands.remove(entity);
+        predictions.remove(entity);
+        if let Some(movable) = movables.get_mut(entity) {
+            movable.desired_vel = V2::new(0.0, 0.0);
+        }
+    }
+
+    apply_jumps(jumps, &gates, &mut locations, &mut movables)?;
+
+    Ok(())
+}
+
+/// Steer to the predicted meeting point with the target and match its velocity once there
+pub fn pursue_command_system(world: &mut World) -> GameResult<()> {
+    let entities = world.entities();
+    let mut commands = world.write_storage::<PursueCommand>();
+    let mut movables = world.write_storage::<Movable>();
+    let mut predictions = world.write_storage::<MovementPrediction>();
+    let mut locations = world.write_storage::<Location>();
+    let gates = world.read_storage::<Gate>();
+
+    let links = collect_gate_links(&entities, &gates, &locations);
+
+    let mut changes = vec![];
+    let mut completes = vec![];
+    let mut jumps = vec![];
+
+    for (entity, command, movable) in (&*entities, &commands, &movables).join() {
+        let target = match movables.get(command.target) {
+            Some(target) => target,
+            None => {
+                completes.push(entity);
+                continue;
+            }
+        };
+
+        let sector = locations.get(entity).map(|location| location.sector);
+        let target_sector = locations
+            .get(command.target)
+            .map(|location| location.sector);
+        if sector != target_sector {
+            match action_move_to_sector(&links, sector, movable, target_sector, target.pos) {
+                Some((result, gate)) => {
+                    let gate_pos = gates.get(gate).map(|gate| gate.pos).unwrap_or(target.pos);
+                    changes.push((entity, result.desired_vel, gate_pos));
+                    if result.complete {
+                        jumps.push((entity, gate));
+                    }
+                }
+                None => completes.push(entity),
+            }
+            continue;
+        }
+
+        let point = get_meeting_point(movable, target);
+        let max_speed = movable.get_max_speed();
+        let result = action_move_to(movable.pos, point, max_speed, movable.max_acc, Some(0.0));
+
+        let mut desired_vel = result.desired_vel + target.vel;
+        if desired_vel.magnitude() > max_speed {
+            desired_vel = desired_vel.normalize() * max_speed;
+        }
+
+        changes.push((entity, desired_vel, point));
+    }
+
+    for (entity, desired_vel, point) in changes {
+        let movable = movables.get_mut(entity).unwrap();
+        movable.desired_vel = desired_vel;
+
+        predictions
+            .insert(
+                entity,
+                MovementPrediction {
+                    points: vec![movable.pos, point],
+                },
+            )
+            .unwrap();
+    }
+
+    for entity in completes {
+        commands.remove(entity);
+        predictions.remove(entity);
+        if let Some(movable) = movables.get_mut(entity) {
+            movable.desired_vel = V2::new(0.0, 0.0);
+        }
+    }
+
+    apply_jumps(jumps, &gates, &mut locations, &mut movables)?;
+
+    Ok(())
+}
+
+pub struct ActionMoveResult {
+    pub desired_vel: V2,
+    pub complete: bool,
+}
+
+/// fraction of `Movable::max_acc` used to plan braking, the rest absorb the delay of one update
+pub const BRAKING_FACTOR: f32 = 0.9;
+
+/// Max speed at `distance` that can still slow down to `arrival_speed` at the target
+pub fn get_braking_speed(distance: f32, max_acc: f32, arrival_speed: f32) -> f32 {
+    (arrival_speed * arrival_speed + 2.0 * max_acc * BRAKING_FACTOR * distance).sqrt()
+}
+
+/// Desired velocity to reach the target, accelerating up to max speed and braking to reach it
+/// at `arrival_speed`. When `arrival_speed` is none, pass through the target at max speed.
+pub fn action_move_to(
+    current_pos: P2,
+    target_pos: P2,
+    max_speed: f32,
+    max_acc: f32,
+    arrival_speed: Option<f32>,
+) -> ActionMoveResult {
+    let delta = target_pos - current_pos;
+    let distance = delta.magnitude();
+
+    if distance < ARRIVAL_DISTANCE {
+        ActionMoveResult {
+            desired_vel: V2::new(0.0, 0.0),
+            complete: true,
+        }
+    } else {
+        let dir = delta / distance;
+        let speed = match arrival_speed {
+            Some(arrival_speed) => {
+                let arrival_speed = arrival_speed.min(max_speed);
+                max_speed.min(get_braking_speed(distance, max_acc, arrival_speed))
+            }
+            None => max_speed,
+        };
+
+        let desired_vel = dir * speed;
+        ActionMoveResult {
+            desired_vel,
+            complete: false,
+        }
+    }
+}
+
+pub fn movable_system(delta: f32, world: &mut World) -> GameResult<()> {
+    let mut movables = world.write_storage::<Movable>();
+
+    for (movable,) in (&mut movables,).join() {
+        tick_movable(delta, movable);
+    }
+
+    Ok(())
+}
+
+/// Set the avoidance velocity of ships with a model to keep away from other ships and stations
+/// in the same sector. Ships in a station queue or docks ignore the station and its other
+/// traffic, so they can pass through the docking corridor.
+pub fn avoidance_system(world: &mut World) -> GameResult<()> {
+    let entities = world.entities();
+    let cfg = world.read_resource::<Cfg>();
+    let models = world.read_storage::<Model>();
+    let stations = world.read_storage::<Station>();
+    let trade_commands = world.read_storage::<TradeCommand>();
+    let locations = world.read_storage::<Location>();
+    let mut movables = world.write_storage::<Movable>();
+
+    // obstacles with their sector and the station corridor they are in
+    let mut obstacles: Vec<(Entity, Option<Entity>, Option<Entity>, Obstacle)> = vec![];
+
+    for (entity, model, station, location) in
+        (&*entities, &models, &stations, locations.maybe()).join()
+    {
+        let obstacle = Obstacle {
+            id: entity.id(),
+            pos: station.pos,
+            vel: V2::new(0.0, 0.0),
+            size: model.size,
+        };
+        let sector = location.map(|location| location.sector);
+        obstacles.push((entity, sector, None, obstacle));
+    }
+
+    for (entity, model, movable, command, location) in (
+        &*entities,
+        &models,
+        &movables,
+        trade_commands.maybe(),
+        locations.maybe(),
+    )
+        .join()
+    {
+        let obstacle = Obstacle {
+            id: entity.id(),
+            pos: movable.pos,
+            vel: movable.vel,
+            size: model.size,
+        };
+        let corridor = command.and_then(|command| command.get_corridor_station());
+        let sector = location.map(|location| location.sector);
+        obstacles.push((entity, sector, corridor, obstacle));
+    }
+
+    for (entity, model, movable, command, location) in (
+        &*entities,
+        models.maybe(),
+        &mut movables,
+        trade_commands.maybe(),
+        locations.maybe(),
+    )
+        .join()
+    {
+        let model = match model {
+            Some(model) if cfg.avoidance => model,
+            _ => {
+                movable.avoidance_vel = V2::new(0.0, 0.0);
+                continue;
+            }
+        };
+
+        let sector = location.map(|location| location.sector);
+        let corridor = command.and_then(|command| command.get_corridor_station());
+        let others: Vec<Obstacle> = obstacles
+            .iter()
+            .filter(|(other, other_sector, other_corridor, _)| {
+                *other != entity
+                    && *other_sector == sector
+                    && Some(*other) != corridor
+                    && (corridor.is_none() || *other_corridor != corridor)
+            })
+            .map(|(_, _, _, obstacle)| obstacle.clone())
+            .collect();
+
+        let me = Obstacle {
+            id: entity.id(),
+            pos: movable.pos,
+            vel: movable.vel,
+            size: model.size,
+        };
+
+        movable.avoidance_vel =
+            compute_avoidance_vel(&me, movable.desired_vel, movable.max_speed, &others);
+    }
+
+    Ok(())
+}
+
+pub fn tick_movable(delta: f32, movable: &mut Movable) {
+    let desired_vel = movable.desired_vel.clone() + movable.avoidance_vel.clone();
+    let delta_vel = desired_vel - movable.vel.clone();
+    let mag = delta_vel.magnitude();
+    if mag > 0.01 {
+        // never change more than needed, or the velocity oscillate around the desired one
+        let change = (movable.max_acc * delta).min(mag);
+        movable.vel = movable.vel.clone() + delta_vel / mag * change;
+    }
+    movable.pos = movable.pos.clone() + movable.vel.clone() * delta;
+}
+
+/// distance from the end of the station queue to join it
+pub const DOCK_QUEUE_JOIN_DISTANCE: f32 = 4.0;
+
+pub fn trade_command_system(world: &mut World) -> GameResult<()> {
+    let entities = &world.entities();
+    let mut trade_commands = world.write_storage::<TradeCommand>();
+    let mut movables = world.write_storage::<Movable>();
+    let mut predictions = world.write_storage::<MovementPrediction>();
+    let mut cargos = world.write_storage::<Cargo>();
+    let mut inventories = world.write_storage::<Inventory>();
+    let mut stations = world.write_storage::<Station>();
+    let mut locations = world.write_storage::<Location>();
+    let gates = world.read_storage::<Gate>();
+    let total_time = world.read_resource::<Time>().total_time;
+    let mut jumps = vec![];
+    let mut empty_routes = vec![];
+    let mut lost_stations = vec![];
+    let mut unreachable_stations = vec![];
+
+    let links = collect_gate_links(entities, &gates, &locations);
+
+    // ships that are not trading with the station anymore release its place
+    let mut users: HashSet<(Entity, Entity)> = HashSet::new();
+    for (entity, command) in (*&entities, &trade_commands).join() {
+        if let Some(station) = command.current() {
+            users.insert((station, entity));
+        }
+    }
+
+    for (station_entity, station) in (*&entities, &mut stations).join() {
+        station
+            .queue
+            .retain(|ship| users.contains(&(station_entity, *ship)));
+        for docked in &mut station.docks {
+            if let Some(ship) = *docked {
+                if !users.contains(&(station_entity, ship)) {
+                    *docked = None;
+                }
+            }
+        }
+    }
+
+    for (entity, command, movable, prediction, cargo, location) in (
+        *&entities,
+        &mut trade_commands,
+        &mut movables,
+        predictions.maybe(),
+        (&mut cargos).maybe(),
+        locations.maybe(),
+    )
+        .join()
+    {
+        let station_entity = match command.current() {
+            Some(station_entity) => station_entity,
+            None => {
+                empty_routes.push(entity);
+                continue;
+            }
+        };
+
+        let station_sector = locations
+            .get(station_entity)
+            .map(|location| location.sector);
+        // deleted entities are not found in the storage
+        let station = if let Some(station) = stations.get_mut(station_entity) {
+            station
+        } else {
+            lost_stations.push(entity);
+            continue;
+        };
+
+        let dock_pos = station
+            .get_dock_slot(entity)
+            .map(|slot| station.get_dock_pos(slot))
+            .unwrap_or(station.pos);
+
+        match command.state {
+            TradeCommandState::MoveToDock => {
+                let queue_pos = station.get_queue_pos(station.queue.len());
+                let sector = location.map(|location| location.sector);
+
+                let (to, gate) =
+                    match plan_travel(&links, sector, movable.pos, station_sector, queue_pos) {
+                        Some(step) => step,
+                        None => {
+                            // there is no path to the station sector
+                            movable.desired_vel = V2::new(0.0, 0.0);
+                            unreachable_stations.push((entity, station_entity));
+                            continue;
+                        }
+                    };
+
+                if let Some(gate) = gate {
+                    let result = action_move_to(
+                        movable.pos,
+                        to,
+                        movable.get_max_speed(),
+                        movable.max_acc,
+                        None,
+                    );
+
+                    movable.desired_vel = result.desired_vel;
+                    if result.complete {
+                        jumps.push((entity, gate));
+                    }
+                    continue;
+                }
+
+                let result = action_move_to(
+                    movable.pos,
+                    to,
+                    movable.get_max_speed(),
+                    movable.max_acc,
+                    Some(0.0),
+                );
+
+                movable.desired_vel = result.desired_vel;
+                if sector == station_sector
+                    && (queue_pos - movable.pos).magnitude() < DOCK_QUEUE_JOIN_DISTANCE
+                {
+                    station.queue.push(entity);
+                    command.state = TradeCommandState::Queued;
+                }
+            }
+
+            TradeCommandState::Queued => {
+                let index = station
+                    .queue
+                    .iter()
+                    .position(|ship| *ship == entity)
+                    .unwrap_or(station.queue.len());
+
+                if index == 0 && station.dock(entity).is_some() {
+                    station.queue.remove(0);
+                    command.state = TradeCommandState::Docking;
+                } else {
+                    // hold position, moving forward when the queue advances
+                    let queue_pos = station.get_queue_pos(index);
+                    let result = action_move_to(
+                        movable.pos,
+                        queue_pos,
+                        movable.get_max_speed(),
+                        movable.max_acc,
+                        Some(0.0),
+                    );
+                    movable.desired_vel = result.desired_vel;
+                }
+            }
+
+            TradeCommandState::Docking => {
+                let result = action_move_to(
+                    movable.pos,
+                    dock_pos,
+                    movable.get_max_speed(),
+                    movable.max_acc,
+                    Some(0.0),
+                );
+
+                movable.desired_vel = result.desired_vel;
+                if result.complete {
+                    command.state = TradeCommandState::Docked {
+                        complete_time: total_time + 1.0,
+                    };
+
+                    if let Some(cargo) = cargo {
+                        let mut route_inventories: Vec<Option<Inventory>> = command
+                            .stations
+                            .iter()
+                            .map(|station| inventories.get(*station).cloned())
+                            .collect();
+
+                        command.next_index =
+                            trade_at_station(cargo, &mut route_inventories, command.index);
+
+                        if let (Some(inventory), Some(changed)) = (
+                            inventories.get_mut(station_entity),
+                            route_inventories[command.index].take(),
+                        ) {
+                            *inventory = changed;
+                        }
+                    }
+                }
+            }
+
+            TradeCommandState::Docked { complete_time } if total_time > complete_time => {
+                command.state = TradeCommandState::Undocking;
+            }
+
+            TradeCommandState::Docked { .. } => {
+                let result = action_move_to(
+                    movable.pos,
+                    dock_pos,
+                    movable.get_max_speed(),
+                    movable.max_acc,
+                    Some(0.0),
+                );
+                movable.desired_vel = result.desired_vel;
+            }
+
+            TradeCommandState::Undocking => {
+                // leave the slot by the exit lane, clear of docking ships. The slot is kept until
+                // the exit, so nobody docks in the way
+                let exit_pos = station.get_exit_pos();
+
+                let result = action_move_to(
+                    movable.pos,
+                    exit_pos,
+                    movable.get_max_speed(),
+                    movable.max_acc,
+                    None,
+                );
+
+                movable.desired_vel = result.desired_vel;
+                if result.complete {
+                    station.undock(entity);
+
+                    match command.next_index.take() {
+                        Some(index) => command.index = index,
+                        None => command.next(),
+                    }
+                    command.state = TradeCommandState::MoveToDock;
+                }
+            }
+        }
+    }
+
+    // drop deleted stations from the route and continue with the next one
+    for entity in lost_stations {
+        let command = trade_commands.get_mut(entity).unwrap();
+        command.retain_stations(|station| stations.contains(station));
+        if command.stations.is_empty() {
+            empty_routes.push(entity);
+        }
+    }
+
+    // same for the stations that can not be reached
+    for (entity, unreachable) in unreachable_stations {
+        let command = trade_commands.get_mut(entity).unwrap();
+        command.retain_stations(|station| station != unreachable);
+        if command.stations.is_empty() {
+            empty_routes.push(entity);
+        }
+    }
+
+    // nothing to trade with, the command queue moves to the next command
+    for entity in empty_routes {
+        trade_commands.remove(entity);
+        predictions.remove(entity);
+    }
+
+    apply_jumps(jumps, &gates, &mut locations, &mut movables)?;
+
+    Ok(())
+}
+
+/// Stations produce and consume its wares
+pub fn production_system(delta: f32, world: &mut World) -> GameResult<()> {
+    let mut inventories = world.write_storage::<Inventory>();
+
+    for inventory in (&mut inventories).join() {
+        for stock in inventory.wares.values_mut() {
+            stock.amount = (stock.amount + stock.production * delta)
+                .max(0.0)
+                .min(stock.capacity);
+        }
+    }
+
+    Ok(())
+}
+
+pub fn model_system(world: &mut World) -> GameResult<()> {
+    let movables = world.read_storage::<Movable>();
+    let stations = world.read_storage::<Station>();
+    let gates = world.read_storage::<Gate>();
+    let mut models = world.write_storage::<Model>();
+
+    for (movable, model) in (&movables, &mut models).join() {
+        model.pos = movable.pos;
+    }
+
+    for (station, model) in (&stations, &mut models).join() {
+        model.pos = station.pos;
+    }
+
+    for (gate, model) in (&gates, &mut models).join() {
+        model.pos = gate.pos;
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::simulation::Simulation;
+    use super::*;
+    use std::collections::HashMap;
+
+    fn move_to(x: f32, y: f32) -> Command {
+        Command::Move(MoveCommand {
+            to: P2::new(x, y),
+            arrival: true,
+            predict: false,
+            sector: None,
+        })
+    }
+
+    fn run(simulation: &mut Simulation, seconds: f32) {
+        let delta = 1.0 / 30.0;
+        for _ in 0..(seconds / delta) as usize {
+            simulation.update(delta).unwrap();
+        }
+    }
+
+    /// Ship for the tests, at rest without model, location nor command unless set
+    struct TestShip {
+        pos: P2,
+        max_speed: f32,
+        max_acc: f32,
+        vel: V2,
+        model: bool,
+        sector: Option<Entity>,
+        command: Option<Command>,
+    }
+
+    impl TestShip {
+        fn new(pos: P2, max_speed: f32) -> Self {
+            TestShip {
+                pos,
+                max_speed,
+                max_acc: 100.0,
+                vel: V2::new(0.0, 0.0),
+                model: false,
+                sector: None,
+                command: None,
+            }
+        }
+
+        /// Ship already moving at its max speed
+        fn moving(pos: P2, vel: V2) -> Self {
+            TestShip {
+                vel,
+                ..TestShip::new(pos, vel.magnitude())
+            }
+        }
+
+        fn with_max_acc(mut self, max_acc: f32) -> Self {
+            self.max_acc = max_acc;
+            self
+        }
+
+        fn with_model(mut self) -> Self {
+            self.model = true;
+            self
+        }
+
+        fn in_sector(mut self, sector: Entity) -> Self {
+            self.sector = Some(sector);
+            self
+        }
+
+        fn with_command(mut self, command: Command) -> Self {
+            self.command = Some(command);
+            self
+        }
+
+        fn build(self, simulation: &mut Simulation) -> Entity {
+            let mut movable = Movable::new(self.pos, self.max_speed, self.max_acc);
+            movable.vel = self.vel;
+            movable.desired_vel = self.vel;
+
+            let mut builder = simulation.get_world_mut().create_entity().with(movable);
+            if self.model {
+                builder = builder.with(Model::new(4.0, ggez::graphics::WHITE));
+            }
+            if let Some(sector) = self.sector {
+                builder = builder.with(Location::new(sector));
+            }
+            let ship = builder.build();
+
+            if let Some(command) = self.command {
+                simulation.enqueue_command(ship, command).unwrap();
+            }
+            ship
+        }
+    }
+
+    #[test]
+    fn test_command_queue_execute_in_order() {
+        let mut simulation = Simulation::new(Default::default());
+        let ship = TestShip::new(P2::new(0.0, 0.0), 50.0).build(&mut simulation);
+
+        simulation
+            .enqueue_command(ship, move_to(50.0, 0.0))
+            .unwrap();
+        simulation
+            .enqueue_command(ship, move_to(50.0, 50.0))
+            .unwrap();
+
+        run(&mut simulation, 0.5);
+        let queue = simulation.get_command_queue(ship).unwrap();
+        assert_eq!(queue.get_active(), Some(CommandKind::Move));
+        assert_eq!(queue.get_pending().len(), 1);
+
+        run(&mut simulation, 10.0);
+        let pos = simulation.get_pos(ship).unwrap();
+        assert!((pos - P2::new(50.0, 50.0)).magnitude() < 5.0, "{:?}", pos);
+        assert!(simulation.get_command_queue(ship).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_command_queue_interrupt_resume_patrol() {
+        let mut simulation = Simulation::new(Default::default());
+        let ship = TestShip::new(P2::new(0.0, 0.0), 50.0).build(&mut simulation);
+
+        let patrol = Command::Patrol(PatrolCommand::new(vec![
+            P2::new(40.0, 0.0),
+            P2::new(80.0, 0.0),
+            P2::new(0.0, 0.0),
+        ]));
+        simulation.enqueue_command(ship, patrol).unwrap();
+        run(&mut simulation, 1.5);
+        assert_eq!(simulation.get_patrol_command(ship).unwrap().index, 1);
+
+        simulation
+            .interrupt_command(ship, move_to(40.0, 40.0))
+            .unwrap();
+        run(&mut simulation, 0.1);
+        assert!(simulation.get_patrol_command(ship).is_none());
+        assert_eq!(
+            simulation.get_command_queue(ship).unwrap().get_active(),
+            Some(CommandKind::Move)
+        );
+
+        run(&mut simulation, 4.0);
+        // patrol continue from where it was
+        let patrol = simulation.get_patrol_command(ship).unwrap();
+        assert!(patrol.index >= 1, "{:?}", patrol);
+    }
+
+    #[test]
+    fn test_command_queue_replace_and_clear() {
+        let mut simulation = Simulation::new(Default::default());
+        let ship = TestShip::new(P2::new(0.0, 0.0), 50.0).build(&mut simulation);
+
+        // a command attached without queue conflict with the queued one
+        simulation
+            .get_world_mut()
+            .write_storage::<PatrolCommand>()
+            .insert(ship, PatrolCommand::new(vec![P2::new(100.0, 100.0)]))
+            .unwrap();
+
+        simulation
+            .enqueue_command(ship, move_to(100.0, 0.0))
+            .unwrap();
+        simulation
+            .enqueue_command(ship, move_to(0.0, 100.0))
+            .unwrap();
+        run(&mut simulation, 0.1);
+        assert!(simulation.get_patrol_command(ship).is_none());
+
+        simulation
+            .replace_command(ship, move_to(-100.0, 0.0))
+            .unwrap();
+        run(&mut simulation, 0.1);
+        let queue = simulation.get_command_queue(ship).unwrap();
+        assert_eq!(queue.get_active(), Some(CommandKind::Move));
+        assert!(queue.get_pending().is_empty());
+        let to = simulation
+            .get_world()
+            .read_storage::<MoveCommand>()
+            .get(ship)
+            .unwrap()
+            .to;
+        assert_eq!(to, P2::new(-100.0, 0.0));
+
+        simulation.clear_commands(ship).unwrap();
+        run(&mut simulation, 0.1);
+        assert!(simulation.get_command_queue(ship).unwrap().is_empty());
+        assert!(!simulation
+            .get_world()
+            .read_storage::<MoveCommand>()
+            .contains(ship));
+        assert_eq!(
+            simulation.get_movable(ship).unwrap().desired_vel,
+            V2::new(0.0, 0.0)
+        );
+    }
+
+    #[test]
+    fn test_command_queue_skip_empty_routes() {
+        let mut simulation = Simulation::new(Default::default());
+        let ship = TestShip::new(P2::new(0.0, 0.0), 50.0).build(&mut simulation);
+
+        simulation
+            .enqueue_command(ship, Command::Patrol(PatrolCommand::new(vec![])))
+            .unwrap();
+        simulation
+            .enqueue_command(ship, Command::Trade(TradeCommand::new(vec![])))
+            .unwrap();
+        simulation
+            .enqueue_command(ship, move_to(50.0, 0.0))
+            .unwrap();
+
+        run(&mut simulation, 0.5);
+        let queue = simulation.get_command_queue(ship).unwrap();
+        assert_eq!(queue.get_active(), Some(CommandKind::Move));
+        assert!(queue.get_pending().is_empty());
+    }
+
+    #[test]
+    fn test_tick_move_with_empty_movable() {
+        let mut movable = Movable::new(P2::new(400.0, 300.0), 10.0, 10.0);
+        tick_movable(0.0, &mut movable);
+        assert_eq!(movable.pos.x, 400.0);
+        assert_eq!(movable.pos.y, 300.0);
+    }
+
+    #[test]
+    fn test_tick_move_reach_desired_vel_without_oscillation() {
+        let mut movable = Movable::new(P2::new(0.0, 0.0), 10.0, 10.0);
+        movable.vel = V2::new(0.05, 0.0);
+
+        for _ in 0..10 {
+            tick_movable(1.0 / 30.0, &mut movable);
+        }
+
+        assert_eq!(movable.vel, V2::new(0.0, 0.0));
+    }
+
+    fn is_using_dock(state: &TradeCommandState) -> bool {
+        match state {
+            TradeCommandState::Docking
+            | TradeCommandState::Docked { .. }
+            | TradeCommandState::Undocking => true,
+            _ => false,
+        }
+    }
+
+    #[test]
+    fn test_trade_dock_queue_rush_of_20_ships() {
+        let mut simulation = Simulation::new(Default::default());
+        let world = simulation.get_world_mut();
+
+        let station_0 = world
+            .create_entity()
+            .with(
+                Station::new(P2::new(100.0, 300.0), V2::new(1.0, 0.0), 40.0).with_dock_capacity(2),
+            )
+            .build();
+        let station_1 = world
+            .create_entity()
+            .with(
+                Station::new(P2::new(700.0, 300.0), V2::new(-1.0, 0.0), 40.0).with_dock_capacity(2),
+            )
+            .build();
+
+        let ships: Vec<Entity> = (0..20)
+            .map(|i| {
+                let pos = P2::new(250.0 + (i % 5) as f32 * 20.0, 200.0 + (i / 5) as f32 * 50.0);
+                world
+                    .create_entity()
+                    .with(Movable::new(pos, 60.0, 80.0))
+                    .with(TradeCommand::new(vec![station_0, station_1]))
+                    .build()
+            })
+            .collect();
+
+        let mut docked_at_0 = HashSet::new();
+        let mut max_queue = 0;
+        let mut queued_since: HashMap<Entity, (usize, usize)> = HashMap::new();
+
+        for tick in 0..(200.0 * 30.0) as usize {
+            simulation.update(1.0 / 30.0).unwrap();
+
+            let world = simulation.get_world();
+            let commands = world.read_storage::<TradeCommand>();
+            let stations = world.read_storage::<Station>();
+            let movables = world.read_storage::<Movable>();
+
+            for &station_entity in &[station_0, station_1] {
+                let station = stations.get(station_entity).unwrap();
+                let using = ships
+                    .iter()
+                    .map(|ship| commands.get(*ship).unwrap())
+                    .filter(|command| {
+                        command.current() == Some(station_entity) && is_using_dock(&command.state)
+                    })
+                    .count();
+
+                assert!(using <= 2, "tick {} using {}", tick, using);
+                assert_eq!(station.list_docked().len(), using);
+
+                // docked ships have their own slot
+                let docked: Vec<P2> = station
+                    .list_docked()
+                    .iter()
+                    .filter_map(|ship| match commands.get(*ship).unwrap().state {
+                        TradeCommandState::Docked { .. } => Some(movables.get(*ship).unwrap().pos),
+                        _ => None,
+                    })
+                    .collect();
+                for (i, pos) in docked.iter().enumerate() {
+                    for other in &docked[i + 1..] {
+                        let distance = (pos - other).magnitude();
+                        assert!(
+                            distance > DOCK_SLOT_SPACING / 2.0,
+                            "tick {} distance {}",
+                            tick,
+                            distance
+                        );
+                    }
+                }
+                max_queue = max_queue.max(station.queue.len());
+
+                // ships that keep the same place in the queue hold position
+                for (index, ship) in station.queue.iter().enumerate() {
+                    let since = match queued_since.get(ship) {
+                        Some((previous, since)) if *previous == index => *since,
+                        _ => {
+                            queued_since.insert(*ship, (index, tick));
+                            tick
+                        }
+                    };
+
+                    if tick - since > 120 {
+                        let movable = movables.get(*ship).unwrap();
+                        let distance = (station.get_queue_pos(index) - movable.pos).magnitude();
+                        assert!(
+                            distance < ARRIVAL_DISTANCE * 2.0,
+                            "tick {} distance {}",
+                            tick,
+                            distance
+                        );
+                        // velocity oscillate around the desired one by a step of acceleration
+                        let speed = movable.vel.magnitude();
+                        assert!(speed < 2.0 * movable.max_acc / 30.0, "speed {}", speed);
+                    }
+                }
+            }
+
+            queued_since
+                .retain(|ship, _| commands.get(*ship).unwrap().state == TradeCommandState::Queued);
+
+            for ship in &ships {
+                let command = commands.get(*ship).unwrap();
+                if let TradeCommandState::Docked { .. } = command.state {
+                    if command.current() == Some(station_0) {
+                        docked_at_0.insert(*ship);
+                    }
+                }
+            }
+        }
+
+        assert!(max_queue > 10);
+        assert_eq!(docked_at_0.len(), 20);
+    }
+
+    #[test]
+    fn test_trade_skip_deleted_stations() {
+        let mut simulation = Simulation::new(Default::default());
+        let world = simulation.get_world_mut();
+
+        let station_0 = world
+            .create_entity()
+            .with(Station::new(P2::new(100.0, 0.0), V2::new(1.0, 0.0), 40.0))
+            .build();
+        let station_1 = world
+            .create_entity()
+            .with(Station::new(P2::new(-100.0, 0.0), V2::new(-1.0, 0.0), 40.0))
+            .build();
+        let ship = TestShip::new(P2::new(0.0, 0.0), 50.0).build(&mut simulation);
+        simulation
+            .enqueue_command(
+                ship,
+                Command::Trade(TradeCommand::new(vec![station_0, station_1])),
+            )
+            .unwrap();
+
+        run(&mut simulation, 0.5);
+        simulation.get_world_mut().delete_entity(station_0).unwrap();
+        run(&mut simulation, 0.5);
+
+        let command = simulation.get_trade_command(ship).unwrap();
+        assert_eq!(command.stations, vec![station_1]);
+        assert_eq!(command.current(), Some(station_1));
+
+        simulation.get_world_mut().delete_entity(station_1).unwrap();
+        run(&mut simulation, 0.5);
+
+        assert!(simulation.get_trade_command(ship).is_none());
+        assert!(simulation.get_command_queue(ship).unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_avoidance_keep_ships_and_stations_apart() {
+        let mut simulation = Simulation::new(Default::default());
+        simulation
+            .get_world_mut()
+            .create_entity()
+            .with(Model::new(15.0, ggez::graphics::WHITE))
+            .with(Station::new(P2::new(0.0, 100.0), V2::new(1.0, 0.0), 40.0))
+            .build();
+
+        // head-on ships and one crossing the station
+        let ship_a = TestShip::new(P2::new(-100.0, 0.0), 50.0)
+            .with_model()
+            .with_command(move_to(100.0, 0.0))
+            .build(&mut simulation);
+        let ship_b = TestShip::new(P2::new(100.0, 0.0), 50.0)
+            .with_model()
+            .with_command(move_to(-100.0, 0.0))
+            .build(&mut simulation);
+        let ship_c = TestShip::new(P2::new(0.0, 0.0), 50.0)
+            .with_model()
+            .with_command(move_to(0.0, 200.0))
+            .build(&mut simulation);
+
+        let mut min_ships_distance = std::f32::MAX;
+        let mut min_station_distance = std::f32::MAX;
+        for _ in 0..(15.0 * 30.0) as usize {
+            simulation.update(1.0 / 30.0).unwrap();
+
+            let pos_a = simulation.get_pos(ship_a).unwrap();
+            let pos_b = simulation.get_pos(ship_b).unwrap();
+            let pos_c = simulation.get_pos(ship_c).unwrap();
+            min_ships_distance = min_ships_distance
+                .min((pos_a - pos_b).magnitude())
+                .min((pos_a - pos_c).magnitude())
+                .min((pos_b - pos_c).magnitude());
+            min_station_distance =
+                min_station_distance.min((pos_c - P2::new(0.0, 100.0)).magnitude());
+        }
+
+        assert!(min_ships_distance > 8.0, "{}", min_ships_distance);
+        assert!(min_station_distance > 19.0, "{}", min_station_distance);
+        // all arrived
+        let move_commands = simulation.get_world().read_storage::<MoveCommand>();
+        for ship in &[ship_a, ship_b, ship_c] {
+            assert!(move_commands.get(*ship).is_none());
+        }
+    }
+
+    #[test]
+    fn test_patrol_leader_wait_for_stragglers() {
+        let mut simulation = Simulation::new(Default::default());
+        let leader = TestShip::new(P2::new(0.0, 0.0), 80.0).build(&mut simulation);
+        let escort = TestShip::new(P2::new(-100.0, 0.0), 20.0).build(&mut simulation);
+
+        simulation
+            .enqueue_command(
+                leader,
+                Command::Patrol(PatrolCommand::new(vec![
+                    P2::new(1000.0, 0.0),
+                    P2::new(0.0, 0.0),
+                ])),
+            )
+            .unwrap();
+        simulation
+            .assign_escorts(
+                leader,
+                &[escort],
+                &EscortFormation::Column { spacing: 10.0 },
+            )
+            .unwrap();
+
+        run(&mut simulation, 3.0);
+        let speed = simulation.get_movable(leader).unwrap().vel.magnitude();
+        assert!(
+            speed < 20.0 * FOLLOWER_BEHIND_SPEED_FACTOR + 5.0,
+            "{}",
+            speed
+        );
+
+        assert_eq!(
+            simulation
+                .get_movable(leader)
+                .unwrap()
+                .follower_behind_max_speed,
+            Some(20.0)
+        );
+
+        // without followers the leader goes back to its own max speed
+        simulation.clear_commands(escort).unwrap();
+        run(&mut simulation, 1.0);
+        let movable = simulation.get_movable(leader).unwrap();
+        assert_eq!(movable.follower_behind_max_speed, None);
+        assert!(movable.vel.magnitude() > 70.0);
+    }
+
+    #[test]
+    fn test_escort_chain_in_formation() {
+        let mut simulation = Simulation::new(Default::default());
+        let leader = TestShip::new(P2::new(0.0, 0.0), 30.0).build(&mut simulation);
+        let escort = TestShip::new(P2::new(0.0, 50.0), 50.0).build(&mut simulation);
+        let chained = TestShip::new(P2::new(0.0, -50.0), 50.0).build(&mut simulation);
+
+        let wedge = EscortFormation::Wedge { spacing: 10.0 };
+        assert_eq!(wedge.get_slot(0), P2::new(-10.0, 10.0));
+        assert_eq!(wedge.get_slot(1), P2::new(-10.0, -10.0));
+        assert_eq!(wedge.get_slot(2), P2::new(-20.0, 20.0));
+
+        simulation
+            .enqueue_command(leader, move_to(300.0, 0.0))
+            .unwrap();
+        simulation
+            .assign_escorts(leader, &[escort], &wedge)
+            .unwrap();
+        simulation
+            .assign_escorts(
+                escort,
+                &[chained],
+                &EscortFormation::Column { spacing: 10.0 },
+            )
+            .unwrap();
+
+        run(&mut simulation, 6.0);
+
+        // moving along x, so slots are not rotated
+        let leader_pos = simulation.get_pos(leader).unwrap();
+        let escort_pos = simulation.get_pos(escort).unwrap();
+        let chained_pos = simulation.get_pos(chained).unwrap();
+        assert!(leader_pos.x > 100.0);
+        assert!((escort_pos - (leader_pos + V2::new(-10.0, 10.0))).magnitude() < 3.0);
+        assert!((chained_pos - (escort_pos + V2::new(-10.0, 0.0))).magnitude() < 3.0);
+    }
+
+    #[test]
+    fn test_follow_complete_when_target_is_deleted() {
+        let mut simulation = Simulation::new(Default::default());
+        let leader = TestShip::new(P2::new(0.0, 0.0), 50.0).build(&mut simulation);
+        let escort = TestShip::new(P2::new(-20.0, 0.0), 50.0).build(&mut simulation);
+
+        simulation
+            .enqueue_command(
+                escort,
+                Command::Follow(FollowCommand::new(leader, P2::new(-10.0, 0.0))),
+            )
+            .unwrap();
+        simulation
+            .enqueue_command(escort, move_to(-50.0, 0.0))
+            .unwrap();
+        run(&mut simulation, 0.5);
+        assert_eq!(
+            simulation.get_command_queue(escort).unwrap().get_active(),
+            Some(CommandKind::Follow)
+        );
+
+        simulation.get_world_mut().delete_entity(leader).unwrap();
+        simulation.get_world_mut().maintain();
+        run(&mut simulation, 0.5);
+
+        assert!(simulation.get_prediction(escort).is_none());
+        assert_eq!(
+            simulation.get_command_queue(escort).unwrap().get_active(),
+            Some(CommandKind::Move)
+        );
+    }
+
+    #[test]
+    fn test_intercept_crossing_ship() {
+        let mut simulation = Simulation::new(Default::default());
+        let target =
+            TestShip::moving(P2::new(100.0, -100.0), V2::new(0.0, 30.0)).build(&mut simulation);
+        let interceptor = TestShip::new(P2::new(0.0, 0.0), 50.0).build(&mut simulation);
+
+        simulation
+            .enqueue_command(
+                interceptor,
+                Command::Intercept(InterceptCommand::new(target)),
+            )
+            .unwrap();
+
+        run(&mut simulation, 0.1);
+        let point = simulation.get_prediction(interceptor).unwrap().points[1];
+        assert!((point.x - 100.0).abs() < 0.001 && point.y > -100.0);
+
+        // meet the target close to the predicted point
+        let mut time = 0.1;
+        while simulation
+            .get_command_queue(interceptor)
+            .unwrap()
+            .get_active()
+            == Some(CommandKind::Intercept)
+        {
+            run(&mut simulation, 0.1);
+            time += 0.1;
+            assert!(time < 3.5, "not intercepted");
+        }
+
+        let meeting = simulation.get_pos(interceptor).unwrap();
+        assert!(
+            (meeting - point).magnitude() < 10.0,
+            "{:?} {:?}",
+            meeting,
+            point
+        );
+        assert!(simulation.get_prediction(interceptor).is_none());
+    }
+
+    #[test]
+    fn test_pursue_faster_target_and_complete_when_deleted() {
+        let mut simulation = Simulation::new(Default::default());
+        let target =
+            TestShip::moving(P2::new(100.0, 0.0), V2::new(60.0, 0.0)).build(&mut simulation);
+        let pursuer = TestShip::new(P2::new(0.0, 0.0), 50.0).build(&mut simulation);
+
+        simulation
+            .enqueue_command(pursuer, Command::Pursue(PursueCommand::new(target)))
+            .unwrap();
+
+        run(&mut simulation, 2.0);
+
+        // can not intercept, steer ahead of the target
+        let target_pos = simulation.get_pos(target).unwrap();
+        let point = simulation.get_prediction(pursuer).unwrap().points[1];
+        assert!(point.x > target_pos.x + 10.0);
+        let movable = simulation.get_movable(pursuer).unwrap();
+        assert!(movable.vel.x > 45.0);
+
+        simulation.get_world_mut().delete_entity(target).unwrap();
+        simulation.get_world_mut().maintain();
+        run(&mut simulation, 0.1);
+
+        assert!(simulation.get_command_queue(pursuer).unwrap().is_empty());
+        assert!(simulation.get_prediction(pursuer).is_none());
+    }
+
+    #[test]
+    fn test_slow_acceleration_stop_at_target() {
+        let mut simulation = Simulation::new(Default::default());
+        let ship = TestShip::new(P2::new(0.0, 0.0), 50.0)
+            .with_max_acc(10.0)
+            .with_command(move_to(200.0, 0.0))
+            .build(&mut simulation);
+
+        let mut max_x: f32 = 0.0;
+        let mut max_speed: f32 = 0.0;
+        for _ in 0..(20.0 * 30.0) as usize {
+            simulation.update(1.0 / 30.0).unwrap();
+            let movable = simulation.get_movable(ship).unwrap();
+            max_x = max_x.max(movable.pos.x);
+            max_speed = max_speed.max(movable.vel.magnitude());
+        }
+
+        // accelerate, cruise and brake without orbiting the target
+        assert!(max_speed > 40.0, "{}", max_speed);
+        assert!(max_x < 200.0 + ARRIVAL_DISTANCE, "{}", max_x);
+        let movable = simulation.get_movable(ship).unwrap();
+        assert!((movable.pos - P2::new(200.0, 0.0)).magnitude() < ARRIVAL_DISTANCE);
+        assert_eq!(movable.vel, V2::new(0.0, 0.0));
+    }
+
+    #[test]
+    fn test_patrol_fly_through_points_at_arrival_speed() {
+        let mut simulation = Simulation::new(Default::default());
+        let route = vec![P2::new(200.0, 0.0), P2::new(200.0, 200.0)];
+        let ship = TestShip::new(P2::new(0.0, 0.0), 50.0)
+            .with_max_acc(20.0)
+            .with_command(Command::Patrol(
+                PatrolCommand::new(route).with_arrival_speed(10.0),
+            ))
+            .build(&mut simulation);
+
+        let mut speed_at_point = None;
+        for _ in 0..(20.0 * 30.0) as usize {
+            simulation.update(1.0 / 30.0).unwrap();
+            let patrol = simulation.get_patrol_command(ship).unwrap();
+            if patrol.index == 1 {
+                speed_at_point = Some(simulation.get_movable(ship).unwrap().vel.magnitude());
+                break;
+            }
+        }
+
+        let speed = speed_at_point.expect("first point not reached");
+        assert!(speed > 5.0 && speed < 15.0, "{}", speed);
+    }
+
+    #[test]
+    fn test_braking_speed() {
+        assert_eq!(get_braking_speed(0.0, 10.0, 5.0), 5.0);
+        let speed = get_braking_speed(100.0, 10.0, 0.0);
+        // distance to stop at max acceleration
+        assert!(speed * speed / (2.0 * 10.0) < 100.0);
+    }
+
+    /// Sectors a, b and c linked in line by gates in both directions
+    fn new_sectors(simulation: &mut Simulation) -> (Entity, Entity, Entity) {
+        let world = simulation.get_world_mut();
+        let a = world.create_entity().with(Sector::new("a")).build();
+        let b = world.create_entity().with(Sector::new("b")).build();
+        let c = world.create_entity().with(Sector::new("c")).build();
+
+        for &(from, pos, to, to_pos) in &[
+            (a, P2::new(100.0, 0.0), b, P2::new(-90.0, 0.0)),
+            (b, P2::new(-100.0, 0.0), a, P2::new(90.0, 0.0)),
+            (b, P2::new(100.0, 0.0), c, P2::new(-90.0, 0.0)),
+            (c, P2::new(-100.0, 0.0), b, P2::new(90.0, 0.0)),
+        ] {
+            world
+                .create_entity()
+                .with(Location::new(from))
+                .with(Gate::new(pos, to, to_pos))
+                .build();
+        }
+
+        (a, b, c)
+    }
+
+    #[test]
+    fn test_move_to_other_sector_through_gates() {
+        let mut simulation = Simulation::new(Default::default());
+        let (a, b, c) = new_sectors(&mut simulation);
+        let ship = TestShip::new(P2::new(0.0, 0.0), 50.0)
+            .in_sector(a)
+            .with_command(Command::Move(MoveCommand {
+                to: P2::new(0.0, 50.0),
+                arrival: true,
+                predict: true,
+                sector: Some(c),
+            }))
+            .build(&mut simulation);
+
+        let mut visited = vec![a];
+        for _ in 0..(20.0 * 30.0) as usize {
+            simulation.update(1.0 / 30.0).unwrap();
+            let sector = simulation.get_sector(ship).unwrap();
+            if visited.last() != Some(&sector) {
+                visited.push(sector);
+            }
+        }
+
+        assert_eq!(visited, vec![a, b, c]);
+        assert!(simulation.get_command_queue(ship).unwrap().is_empty());
+        let pos = simulation.get_pos(ship).unwrap();
+        assert!((pos - P2::new(0.0, 50.0)).magnitude() < ARRIVAL_DISTANCE);
+    }
+
+    #[test]
+    fn test_trade_and_follow_between_sectors() {
+        let mut simulation = Simulation::new(Default::default());
+        let (a, _, c) = new_sectors(&mut simulation);
+        let world = simulation.get_world_mut();
+
+        let station_a = world
+            .create_entity()
+            .with(Location::new(a))
+            .with(Station::new(P2::new(0.0, 100.0), V2::new(0.0, -1.0), 40.0))
+            .build();
+        let station_c = world
+            .create_entity()
+            .with(Location::new(c))
+            .with(Station::new(P2::new(0.0, 100.0), V2::new(0.0, -1.0), 40.0))
+            .build();
+        let trader = TestShip::new(P2::new(0.0, 0.0), 50.0)
+            .in_sector(a)
+            .with_command(Command::Trade(TradeCommand::new(vec![
+                station_c, station_a,
+            ])))
+            .build(&mut simulation);
+        let escort = TestShip::new(P2::new(-20.0, 0.0), 60.0)
+            .in_sector(a)
+            .with_command(Command::Follow(FollowCommand::new(
+                trader,
+                P2::new(-10.0, 0.0),
+            )))
+            .build(&mut simulation);
+
+        let mut docked_at = vec![];
+        let mut escort_sectors = HashSet::new();
+        for _ in 0..(60.0 * 30.0) as usize {
+            simulation.update(1.0 / 30.0).unwrap();
+            escort_sectors.insert(simulation.get_sector(escort));
+
+            let command = simulation.get_trade_command(trader).unwrap();
+            if let TradeCommandState::Docked { .. } = command.state {
+                let station = command.current().unwrap();
+                if docked_at.last() != Some(&station) {
+                    docked_at.push(station);
+                    assert_eq!(
+                        simulation.get_sector(trader),
+                        simulation.get_sector(station)
+                    );
+                }
+            }
+        }
+
+        assert_eq!(&docked_at[0..3], &[station_c, station_a, station_c]);
+        // the escort keep following across sectors
+        assert!(escort_sectors.contains(&Some(c)));
+        assert!(simulation
+            .get_world()
+            .read_storage::<FollowCommand>()
+            .get(escort)
+            .is_some());
+    }
+
+    #[test]
+    fn test_trade_skip_unreachable_stations() {
+        let mut simulation = Simulation::new(Default::default());
+        let (a, _, _) = new_sectors(&mut simulation);
+        let world = simulation.get_world_mut();
+
+        // no gate goes to d
+        let d = world.create_entity().with(Sector::new("d")).build();
+        let station_a = world
+            .create_entity()
+            .with(Location::new(a))
+            .with(Station::new(P2::new(0.0, 100.0), V2::new(0.0, -1.0), 40.0))
+            .build();
+        let station_d = world
+            .create_entity()
+            .with(Location::new(d))
+            .with(Station::new(P2::new(0.0, 100.0), V2::new(0.0, -1.0), 40.0))
+            .build();
+
+        let trader = TestShip::new(P2::new(0.0, 0.0), 50.0)
+            .in_sector(a)
+            .with_command(Command::Trade(TradeCommand::new(vec![
+                station_d, station_a,
+            ])))
+            .build(&mut simulation);
+        let lost_trader = TestShip::new(P2::new(0.0, 0.0), 50.0)
+            .in_sector(a)
+            .with_command(Command::Trade(TradeCommand::new(vec![station_d])))
+            .build(&mut simulation);
+
+        run(&mut simulation, 0.5);
+
+        let command = simulation.get_trade_command(trader).unwrap();
+        assert_eq!(command.stations, vec![station_a]);
+        assert!(simulation.get_trade_command(lost_trader).is_none());
+        assert!(simulation
+            .get_command_queue(lost_trader)
+            .unwrap()
+            .is_empty());
+    }
+}
@@ -0,0 +1,247 @@
+//! Buy and sell wares between ships and stations, and plan the most profitable station to visit
+
+use crate::components::*;
+
+/// smaller amounts are not worth trading
+const MIN_AMOUNT: f32 = 0.01;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TradeOffer {
+    pub ware: WareId,
+    pub amount: f32,
+    pub buy_price: f32,
+    pub sell_price: f32,
+    /// index of the station that buy the ware
+    pub to: usize,
+}
+
+impl TradeOffer {
+    pub fn get_profit(&self) -> f32 {
+        (self.sell_price - self.buy_price) * self.amount
+    }
+}
+
+/// Sell to the station all cargo it consumes, prices are computed before the transfer.
+/// Return the credits received.
+pub fn sell_cargo(cargo: &mut Cargo, inventory: &mut Inventory) -> f32 {
+    let mut total = 0.0;
+
+    for (ware, amount) in cargo.wares.clone() {
+        let stock = match inventory.wares.get_mut(&ware) {
+            Some(stock) if stock.is_buying() => stock,
+            _ => continue,
+        };
+
+        let amount = amount.min(stock.get_free());
+        if amount < MIN_AMOUNT {
+            continue;
+        }
+
+        let credits = amount * stock.get_price();
+        stock.amount += amount;
+        cargo.remove(ware, amount);
+        cargo.credits += credits;
+        total += credits;
+    }
+
+    total
+}
+
+/// Most profitable ware to buy at `from` and sell in one of the `candidates`, given by station
+/// index and inventory
+pub fn find_best_offer(
+    free_capacity: f32,
+    credits: f32,
+    from: &Inventory,
+    candidates: &[(usize, &Inventory)],
+) -> Option<TradeOffer> {
+    let mut best: Option<TradeOffer> = None;
+
+    for (ware, stock) in &from.wares {
+        if !stock.is_selling() {
+            continue;
+        }
+
+        let buy_price = stock.get_price();
+        let affordable = if buy_price > 0.0 {
+            credits / buy_price
+        } else {
+            free_capacity
+        };
+
+        for (index, inventory) in candidates {
+            let target = match inventory.wares.get(ware) {
+                Some(target) if target.is_buying() => target,
+                _ => continue,
+            };
+
+            let amount = stock
+                .amount
+                .min(free_capacity)
+                .min(affordable)
+                .min(target.get_free());
+
+            if amount < MIN_AMOUNT {
+                continue;
+            }
+
+            let offer = TradeOffer {
+                ware: *ware,
+                amount,
+                buy_price,
+                sell_price: target.get_price(),
+                to: *index,
+            };
+
+            let is_better = best
+                .as_ref()
+                .map(|best| offer.get_profit() > best.get_profit())
+                .unwrap_or(true);
+
+            if offer.get_profit() > 0.0 && is_better {
+                best = Some(offer);
+            }
+        }
+    }
+
+    best
+}
+
+/// Move the offer amount from the station into the cargo
+pub fn buy(cargo: &mut Cargo, inventory: &mut Inventory, offer: &TradeOffer) {
+    if let Some(stock) = inventory.wares.get_mut(&offer.ware) {
+        let amount = offer.amount.min(stock.amount);
+        stock.amount -= amount;
+        cargo.add(offer.ware, amount);
+        cargo.credits -= amount * offer.buy_price;
+    }
+}
+
+/// Index of the station with the best value to visit next, from selling the current cargo and
+/// buying a new one. None when there is nothing profitable to do.
+pub fn plan_next_station(
+    cargo: &Cargo,
+    current: usize,
+    stations: &[Option<Inventory>],
+) -> Option<usize> {
+    let mut best: Option<(usize, f32)> = None;
+
+    for (index, inventory) in stations.iter().enumerate() {
+        let inventory = match inventory {
+            Some(inventory) if index != current => inventory,
+            _ => continue,
+        };
+
+        let mut after_sell = cargo.clone();
+        let mut inventory_after_sell = inventory.clone();
+        let sell_value = sell_cargo(&mut after_sell, &mut inventory_after_sell);
+
+        let candidates = candidates_except(stations, index);
+        let buy_profit = find_best_offer(
+            after_sell.get_free(),
+            after_sell.credits,
+            &inventory_after_sell,
+            &candidates,
+        )
+        .map(|offer| offer.get_profit())
+        .unwrap_or(0.0);
+
+        let value = sell_value + buy_profit;
+        let is_better = best.map(|(_, best)| value > best).unwrap_or(true);
+        if value > MIN_AMOUNT && is_better {
+            best = Some((index, value));
+        }
+    }
+
+    best.map(|(index, _)| index)
+}
+
+/// Sell cargo and buy the best offer at the docked station, changing its inventory. Return the
+/// next station to visit.
+pub fn trade_at_station(
+    cargo: &mut Cargo,
+    stations: &mut [Option<Inventory>],
+    current: usize,
+) -> Option<usize> {
+    if let Some(mut inventory) = stations[current].take() {
+        sell_cargo(cargo, &mut inventory);
+
+        let offer = {
+            let candidates = candidates_except(stations, current);
+            find_best_offer(cargo.get_free(), cargo.credits, &inventory, &candidates)
+        };
+
+        if let Some(offer) = offer {
+            buy(cargo, &mut inventory, &offer);
+        }
+
+        stations[current] = Some(inventory);
+    }
+
+    plan_next_station(cargo, current, stations)
+}
+
+fn candidates_except(stations: &[Option<Inventory>], except: usize) -> Vec<(usize, &Inventory)> {
+    stations
+        .iter()
+        .enumerate()
+        .filter(|(index, _)| *index != except)
+        .filter_map(|(index, inventory)| inventory.as_ref().map(|inventory| (index, inventory)))
+        .collect()
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    const ORE: WareId = WareId(0);
+    const FOOD: WareId = WareId(1);
+
+    fn mine() -> Inventory {
+        Inventory::new().with(ORE, StationWare::new(100.0, 100.0, 1.0, 10.0))
+    }
+
+    fn factory(ore_amount: f32) -> Inventory {
+        Inventory::new()
+            .with(ORE, StationWare::new(ore_amount, 100.0, -1.0, 10.0))
+            .with(FOOD, StationWare::new(50.0, 100.0, 1.0, 5.0))
+    }
+
+    #[test]
+    fn test_price_respond_to_supply() {
+        let empty = StationWare::new(0.0, 100.0, -1.0, 10.0);
+        let full = StationWare::new(100.0, 100.0, -1.0, 10.0);
+        assert_eq!(empty.get_price(), 15.0);
+        assert_eq!(full.get_price(), 5.0);
+    }
+
+    #[test]
+    fn test_plan_most_profitable_station() {
+        // the factory without ore pays more for it
+        let mut stations = vec![Some(mine()), Some(factory(90.0)), Some(factory(0.0))];
+        let mut cargo = Cargo::new(20.0, 1000.0);
+
+        let next = trade_at_station(&mut cargo, &mut stations, 0);
+        assert_eq!(next, Some(2));
+        assert_eq!(cargo.get_amount(ORE), 20.0);
+        assert_eq!(cargo.credits, 1000.0 - 20.0 * 5.0);
+        assert_eq!(stations[0].as_ref().unwrap().wares[&ORE].amount, 80.0);
+
+        let next = trade_at_station(&mut cargo, &mut stations, 2);
+        assert_eq!(cargo.get_amount(ORE), 0.0);
+        assert_eq!(cargo.credits, 900.0 + 20.0 * 15.0);
+        assert_eq!(stations[2].as_ref().unwrap().wares[&ORE].amount, 20.0);
+        // nobody consumes food, go back to buy ore
+        assert_eq!(next, Some(0));
+    }
+
+    #[test]
+    fn test_nothing_to_trade() {
+        let mut stations = vec![Some(mine()), None, Some(mine())];
+        let mut cargo = Cargo::new(20.0, 1000.0);
+
+        assert_eq!(trade_at_station(&mut cargo, &mut stations, 0), None);
+        assert_eq!(cargo.get_total(), 0.0);
+        assert_eq!(cargo.credits, 1000.0);
+    }
+}
@@ -9,29 +9,46 @@ use specs::{World, WorldExt};
 use specs_derive::Component;
 use std::ops::Deref;
 use steerning::steerning::components::*;
+use steerning::steerning::debug::*;
+use steerning::steerning::scenario::{load_scenario, Scenario};
+use steerning::steerning::simulation::{Command, Simulation};
 use steerning::steerning::*;
 
+const FIXED_DELTA: f32 = 1.0 / 60.0;
+
 struct App {
     update_next: bool,
-    world: World,
+    /// scenario file given as first argument, when not defined the world is created from cfg
+    scenario_path: Option<String>,
+    simulation: Simulation<'static, 'static>,
 }
 
 impl App {
-    pub fn new(ctx: &mut Context, cfg: Cfg) -> GameResult<App> {
-        let mut world = create_world(cfg)?;
-        initialize_world(&mut world);
+    pub fn new(ctx: &mut Context, cfg: Cfg, scenario_path: Option<String>) -> GameResult<App> {
+        let simulation = App::create_simulation(cfg, scenario_path.as_ref())?;
         let game = App {
             update_next: true,
-            world,
+            scenario_path,
+            simulation,
         };
         Ok(game)
     }
 
+    fn create_simulation(
+        cfg: Cfg,
+        scenario_path: Option<&String>,
+    ) -> GameResult<Simulation<'static, 'static>> {
+        let scenario = match scenario_path {
+            Some(path) => load_scenario(path)?,
+            None => Scenario::from_cfg(&cfg),
+        };
+
+        Simulation::new_with_scenario(cfg, FIXED_DELTA, &scenario)
+    }
+
     pub fn reload(&mut self) -> GameResult<()> {
         let cfg = load_cfg()?;
-        let mut world = create_world(cfg)?;
-        initialize_world(&mut world);
-        self.world = world;
+        self.simulation = App::create_simulation(cfg, self.scenario_path.as_ref())?;
         Ok(())
     }
 }
@@ -60,11 +77,36 @@ fn draw_line(ctx: &mut Context, p0: P2, p1: P2, color: Color, width: f32) -> Gam
     graphics::draw(ctx, &mesh, graphics::DrawParam::default())
 }
 
+fn draw_debug(ctx: &mut Context, primitive: &DebugPrimitive) -> GameResult<()> {
+    let color = primitive.color;
+    match &primitive.shape {
+        DebugShape::Line { from, to } => draw_line(ctx, *from, *to, color, 1.0),
+        DebugShape::Circle { center, radius } => {
+            draw_circle(ctx, *center, *radius, color, 1.0, false)
+        }
+        DebugShape::Arrow { from, to } => {
+            let head = arrow_head(*from, *to);
+            let mesh =
+                graphics::Mesh::new_line(ctx, &[*from, *to, head[0], *to, head[1]], 1.0, color)?;
+            graphics::draw(ctx, &mesh, graphics::DrawParam::default())
+        }
+        DebugShape::Text { pos, text } => {
+            let text = graphics::Text::new(text.as_str());
+            graphics::draw(ctx, &text, (*pos, color))
+        }
+        DebugShape::Polygon { points } => {
+            let mesh =
+                graphics::Mesh::new_polygon(ctx, graphics::DrawMode::stroke(1.0), points, color)?;
+            graphics::draw(ctx, &mesh, graphics::DrawParam::default())
+        }
+    }
+}
+
 impl EventHandler for App {
     fn update(&mut self, ctx: &mut Context) -> GameResult<()> {
         if self.update_next {
             let delta = timer::delta(ctx).as_secs_f32();
-            steerning::steerning::run(delta, &mut self.world);
+            self.simulation.update(delta)?;
         }
         Ok(())
     }
@@ -75,7 +117,7 @@ impl EventHandler for App {
         let color_wall = Color::new(0.0, 1.0, 0.5, 0.5);
 
         {
-            for wall in (&self.world.read_storage::<Wall>()).join() {
+            for wall in (&self.simulation.get_world().read_storage::<Wall>()).join() {
                 let mut mb = graphics::MeshBuilder::new();
                 mb.line(
                     &[wall.pos, Point2::from(wall.pos.clone().coords + wall.vec)],
@@ -110,7 +152,7 @@ impl EventHandler for App {
         // }
 
         {
-            let models = &self.world.read_storage::<Model>();
+            let models = &self.simulation.get_world().read_storage::<Model>();
 
             for (model) in (models).join() {
                 draw_circle(ctx, model.pos, model.size, model.color, 1.0, false)?;
@@ -124,11 +166,8 @@ impl EventHandler for App {
             }
         }
 
-        {
-            let lines = take_debug_lines(&mut self.world);
-            for (a, b, color) in lines {
-                draw_line(ctx, a, b, color, 1.0).unwrap();
-            }
+        for primitive in get_debug_primitives(self.simulation.get_world()) {
+            draw_debug(ctx, &primitive)?;
         }
 
         let text = graphics::Text::new(format!("ftps: {}", ggez::timer::fps(ctx) as i32,));
@@ -139,7 +178,8 @@ impl EventHandler for App {
 
     fn mouse_button_up_event(&mut self, ctx: &mut Context, button: MouseButton, x: f32, y: f32) {
         if button == MouseButton::Left {
-            move_to(&mut self.world, Point2::new(x, y)).unwrap();
+            self.simulation
+                .push_command(Command::MoveTo(Point2::new(x, y)));
         }
     }
 
@@ -171,7 +211,7 @@ fn main() -> GameResult<()> {
         .build()
         .expect("aieee, could not create ggez context!");
 
-    let mut app = App::new(&mut ctx, cfg)?;
+    let mut app = App::new(&mut ctx, cfg, std::env::args().nth(1))?;
 
     // Run!
     match event::run(&mut ctx, &mut event_loop, &mut app) {
@@ -1,4 +1,6 @@
 extern crate steerning;
+use steerning::steerning::components::Wall;
+use steerning::steerning::scenario::load_scenario;
 use steerning::*;
 
 use commons::math::*;
@@ -13,16 +15,7 @@ use rand::{Rng, SeedableRng};
 
 const WIDTH: f32 = 800.0;
 const HEIGHT: f32 = 600.0;
-
-#[derive(Debug, Clone)]
-struct Wall {
-    /// position of the wall
-    pos: P2,
-    /// vector that represent wall direction and size
-    vec: V2,
-    /// min distance until get affected by the wall
-    min_distance: f32,
-}
+const SCENARIO_PATH: &str = "steering/resources/scenarios/walls.json";
 
 #[derive(Debug)]
 struct App {
@@ -32,26 +25,11 @@ struct App {
 
 impl App {
     pub fn new(ctx: &mut Context) -> GameResult<App> {
-        let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+        let scenario = load_scenario(SCENARIO_PATH)?;
 
         let mut walls = vec![];
-        {
-            for i in 0..10 {
-                let wall = Wall {
-                    pos: Point2::new(rng.gen_range(0.0, WIDTH), rng.gen_range(0.0, HEIGHT)),
-                    vec: v2(rng.gen_range(0.0, WIDTH), rng.gen_range(0.0, HEIGHT)),
-                    min_distance: 50.0,
-                };
-
-                walls.push(wall);
-            }
-        }
-        {
-            // walls = vec![Wall {
-            //     pos: Point2::new(100.0, 300.0),
-            //     vec: Vector2::new(500.0, 0.0),
-            //     min_distance: 50.0,
-            // }];
+        for def in &scenario.walls {
+            walls.extend(def.build_walls()?);
         }
 
         Ok(App {
@@ -107,17 +85,14 @@ impl EventHandler for App {
 
         {
             for wall in &self.walls {
-                if let Some(vec) =
-                    compute_vector_from_point_to_segment(wall.pos, wall.vec, self.point)
-                {
-                    let color = if vec.magnitude() > wall.min_distance {
-                        proj_miss_color
-                    } else {
-                        proj_hit_color
-                    };
-
-                    draw_line(ctx, self.point, self.point + vec, color, 1.0)?;
-                }
+                let (_, distance) = wall.push_direction(self.point);
+                let color = if distance > wall.min_distance {
+                    proj_miss_color
+                } else {
+                    proj_hit_color
+                };
+
+                draw_line(ctx, self.point, wall.closest_point(self.point), color, 1.0)?;
             }
         }
 
@@ -0,0 +1,144 @@
+use ggez::{GameError, GameResult};
+use std::io::Write;
+use steerning::steerning::config::{CfgLoader, DEFAULT_CFG_PATH};
+use steerning::steerning::metrics::*;
+use steerning::steerning::scenario::{load_scenario, Scenario};
+use steerning::steerning::simulation::Simulation;
+use steerning::steerning::*;
+
+const USAGE: &str = "usage: steering_headless [--ticks N] [--delta SECONDS] [--format csv|json] \
+                     [--output FILE] [--debug-svg FILE] [--config FILE] [--profile NAME] [--scenario FILE] [--set FIELD=VALUE]...";
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Format {
+    Csv,
+    Json,
+}
+
+#[derive(Debug)]
+struct Args {
+    ticks: u64,
+    delta: f32,
+    format: Format,
+    output: Option<String>,
+    /// write debug primitives of the last tick as SVG
+    debug_svg: Option<String>,
+    /// world to build instead of the one created from the cfg
+    scenario_file: Option<String>,
+    cfg_loader: CfgLoader,
+}
+
+fn parse_args() -> GameResult<Args> {
+    let mut args = Args {
+        ticks: 1000,
+        delta: 1.0 / 60.0,
+        format: Format::Csv,
+        output: None,
+        debug_svg: None,
+        scenario_file: None,
+        cfg_loader: CfgLoader::new().path(DEFAULT_CFG_PATH).env(true),
+    };
+
+    let mut iter = std::env::args().skip(1);
+    while let Some(arg) = iter.next() {
+        let mut value = || {
+            iter.next()
+                .ok_or_else(|| GameError::ConfigError(format!("missing value for {}", arg)))
+        };
+
+        match arg.as_str() {
+            "--ticks" => {
+                args.ticks = value()?
+                    .parse()
+                    .map_err(|e| GameError::ConfigError(format!("invalid --ticks: {}", e)))?;
+            }
+            "--delta" => {
+                args.delta = value()?
+                    .parse()
+                    .map_err(|e| GameError::ConfigError(format!("invalid --delta: {}", e)))?;
+            }
+            "--format" => {
+                args.format = match value()?.as_str() {
+                    "csv" => Format::Csv,
+                    "json" => Format::Json,
+                    other => {
+                        return Err(GameError::ConfigError(format!(
+                            "invalid --format {}, expected csv or json",
+                            other
+                        )))
+                    }
+                };
+            }
+            "--output" => {
+                args.output = Some(value()?);
+            }
+            "--debug-svg" => {
+                args.debug_svg = Some(value()?);
+            }
+            "--scenario" => {
+                args.scenario_file = Some(value()?);
+            }
+            "--config" => {
+                args.cfg_loader = args.cfg_loader.path(value()?);
+            }
+            "--profile" => {
+                args.cfg_loader = args.cfg_loader.profile(&value()?);
+            }
+            "--set" => {
+                args.cfg_loader = args.cfg_loader.override_arg(&value()?)?;
+            }
+            other => {
+                return Err(GameError::ConfigError(format!(
+                    "unknown argument {}\n{}",
+                    other, USAGE
+                )))
+            }
+        }
+    }
+
+    Ok(args)
+}
+
+fn main() -> GameResult<()> {
+    let args = parse_args()?;
+    let cfg = args.cfg_loader.load()?;
+
+    let scenario = match &args.scenario_file {
+        Some(path) => load_scenario(path)?,
+        None => Scenario::from_cfg(&cfg),
+    };
+
+    let mut simulation = Simulation::new_with_scenario(cfg, args.delta, &scenario)?;
+    let mut metrics = Vec::with_capacity(args.ticks as usize);
+
+    for _ in 0..args.ticks {
+        simulation.step()?;
+        metrics.push(compute_metrics(
+            simulation.get_tick(),
+            simulation.get_world(),
+        ));
+    }
+
+    if let Some(path) = &args.debug_svg {
+        std::fs::write(path, debug_svg(simulation.get_world()))
+            .map_err(|e| GameError::FilesystemError(format!("fail to write {}: {}", path, e)))?;
+    }
+
+    let mut writer: Box<dyn Write> = match &args.output {
+        Some(path) => Box::new(std::io::BufWriter::new(
+            std::fs::File::create(path).map_err(|e| {
+                GameError::FilesystemError(format!("fail to create {}: {}", path, e))
+            })?,
+        )),
+        None => Box::new(std::io::stdout()),
+    };
+
+    let result = match args.format {
+        Format::Csv => write_metrics_csv(&mut writer, &metrics),
+        Format::Json => write_metrics_json(&mut writer, &metrics),
+    };
+
+    result
+        .and_then(|_| writer.flush())
+        .map_err(|e| GameError::FilesystemError(format!("fail to write metrics: {}", e)))
+}
@@ -0,0 +1,330 @@
+//! Optimal reciprocal collision avoidance (ORCA), based on the RVO2 library
+//! http://gamma.cs.unc.edu/RVO2/
+
+use commons::math::*;
+
+const EPSILON: f32 = 0.00001;
+
+/// Half-plane of permitted velocities, on the left side of the line direction
+#[derive(Clone, Debug)]
+pub struct OrcaLine {
+    pub point: V2,
+    /// normalized
+    pub direction: V2,
+}
+
+/// Other agent that the vehicle must avoid
+#[derive(Clone, Debug)]
+pub struct OrcaNeighbour {
+    pub pos: P2,
+    pub vel: V2,
+    pub radius: f32,
+    /// share of the avoidance taken by the vehicle, 0.5 for agents that also avoid and 1.0
+    /// for ones that don't
+    pub responsibility: f32,
+}
+
+fn det(a: V2, b: V2) -> f32 {
+    a.x * b.y - a.y * b.x
+}
+
+/// Compute the half-planes of velocities that avoid collision with each neighbour during
+/// `time_horizon`
+pub fn compute_orca_lines(
+    pos: P2,
+    vel: V2,
+    radius: f32,
+    neighbours: &[OrcaNeighbour],
+    time_horizon: f32,
+    delta_time: f32,
+) -> Vec<OrcaLine> {
+    let inv_time_horizon = 1.0 / time_horizon;
+
+    neighbours
+        .iter()
+        .map(|other| {
+            let relative_pos = other.pos - pos;
+            let relative_vel = vel - other.vel;
+            let dist_sq = relative_pos.magnitude_squared();
+            let combined_radius = radius + other.radius;
+            let combined_radius_sq = combined_radius * combined_radius;
+
+            let (direction, u) = if dist_sq > combined_radius_sq {
+                // no collision, vector from cutoff center to relative velocity
+                let w = relative_vel - relative_pos * inv_time_horizon;
+                let w_length_sq = w.magnitude_squared();
+                let dot_product_1 = w.dot(&relative_pos);
+
+                if dot_product_1 < 0.0
+                    && dot_product_1 * dot_product_1 > combined_radius_sq * w_length_sq
+                {
+                    // project on cut-off circle
+                    let w_length = w_length_sq.sqrt();
+                    let unit_w = w / w_length;
+                    let direction = v2(unit_w.y, -unit_w.x);
+                    let u = unit_w * (combined_radius * inv_time_horizon - w_length);
+                    (direction, u)
+                } else {
+                    // project on legs
+                    let leg = (dist_sq - combined_radius_sq).sqrt();
+                    let direction = if det(relative_pos, w) > 0.0 {
+                        // left leg
+                        v2(
+                            relative_pos.x * leg - relative_pos.y * combined_radius,
+                            relative_pos.x * combined_radius + relative_pos.y * leg,
+                        ) / dist_sq
+                    } else {
+                        // right leg
+                        -v2(
+                            relative_pos.x * leg + relative_pos.y * combined_radius,
+                            -relative_pos.x * combined_radius + relative_pos.y * leg,
+                        ) / dist_sq
+                    };
+
+                    let u = direction * relative_vel.dot(&direction) - relative_vel;
+                    (direction, u)
+                }
+            } else {
+                // already colliding, resolve it on next step
+                let inv_time_step = 1.0 / delta_time;
+                let w = relative_vel - relative_pos * inv_time_step;
+                let w_length = w.magnitude();
+                let unit_w = if w_length > EPSILON {
+                    w / w_length
+                } else {
+                    v2(1.0, 0.0)
+                };
+                let direction = v2(unit_w.y, -unit_w.x);
+                let u = unit_w * (combined_radius * inv_time_step - w_length);
+                (direction, u)
+            };
+
+            OrcaLine {
+                point: vel + u * other.responsibility,
+                direction,
+            }
+        })
+        .collect()
+}
+
+/// Find the velocity closest to `preferred_vel` that satisfy all lines and is not faster
+/// than `max_speed`. When there is no solution, return the velocity that least violate them.
+pub fn solve_orca(lines: &[OrcaLine], max_speed: f32, preferred_vel: V2) -> V2 {
+    let mut result = v2(0.0, 0.0);
+    let line_fail = linear_program_2(lines, max_speed, preferred_vel, false, &mut result);
+    if line_fail < lines.len() {
+        linear_program_3(lines, line_fail, max_speed, &mut result);
+    }
+    result
+}
+
+/// Same as `solve_orca`, but only search the velocities within `max_delta` of the current
+/// `vel`, the ones that can be reached in a single step. When the whole speed circle is
+/// reachable this is the plain solve.
+pub fn solve_orca_reachable(
+    lines: &[OrcaLine],
+    max_speed: f32,
+    vel: V2,
+    max_delta: f32,
+    preferred_vel: V2,
+) -> V2 {
+    if max_delta >= vel.magnitude() + max_speed {
+        return solve_orca(lines, max_speed, preferred_vel);
+    }
+
+    // solve relative to the current velocity, so the reachable circle is centered at origin
+    let relative_lines: Vec<OrcaLine> = lines
+        .iter()
+        .map(|line| OrcaLine {
+            point: line.point - vel,
+            direction: line.direction,
+        })
+        .collect();
+
+    let result = vel + solve_orca(&relative_lines, max_delta, preferred_vel - vel);
+    if result.magnitude() > max_speed {
+        result.normalize() * max_speed
+    } else {
+        result
+    }
+}
+
+fn linear_program_1(
+    lines: &[OrcaLine],
+    line_no: usize,
+    radius: f32,
+    opt_velocity: V2,
+    direction_opt: bool,
+    result: &mut V2,
+) -> bool {
+    let line = &lines[line_no];
+    let dot_product = line.point.dot(&line.direction);
+    let discriminant = dot_product * dot_product + radius * radius - line.point.magnitude_squared();
+
+    if discriminant < 0.0 {
+        // max speed circle fully invalidates line
+        return false;
+    }
+
+    let sqrt_discriminant = discriminant.sqrt();
+    let mut t_left = -dot_product - sqrt_discriminant;
+    let mut t_right = -dot_product + sqrt_discriminant;
+
+    for other in &lines[0..line_no] {
+        let denominator = det(line.direction, other.direction);
+        let numerator = det(other.direction, line.point - other.point);
+
+        if denominator.abs() <= EPSILON {
+            // lines are parallel
+            if numerator < 0.0 {
+                return false;
+            } else {
+                continue;
+            }
+        }
+
+        let t = numerator / denominator;
+        if denominator >= 0.0 {
+            t_right = t_right.min(t);
+        } else {
+            t_left = t_left.max(t);
+        }
+
+        if t_left > t_right {
+            return false;
+        }
+    }
+
+    let t = if direction_opt {
+        if opt_velocity.dot(&line.direction) > 0.0 {
+            t_right
+        } else {
+            t_left
+        }
+    } else {
+        let t = line.direction.dot(&(opt_velocity - line.point));
+        t.max(t_left).min(t_right)
+    };
+
+    *result = line.point + line.direction * t;
+    true
+}
+
+/// returns the index of the line where it fails or the number of lines on success
+fn linear_program_2(
+    lines: &[OrcaLine],
+    radius: f32,
+    opt_velocity: V2,
+    direction_opt: bool,
+    result: &mut V2,
+) -> usize {
+    *result = if direction_opt {
+        opt_velocity * radius
+    } else if opt_velocity.magnitude_squared() > radius * radius {
+        opt_velocity.normalize() * radius
+    } else {
+        opt_velocity
+    };
+
+    for i in 0..lines.len() {
+        if det(lines[i].direction, lines[i].point - *result) > 0.0 {
+            // result does not satisfy constraint i, compute new optimal result
+            let temp_result = *result;
+            if !linear_program_1(lines, i, radius, opt_velocity, direction_opt, result) {
+                *result = temp_result;
+                return i;
+            }
+        }
+    }
+
+    lines.len()
+}
+
+fn linear_program_3(lines: &[OrcaLine], begin_line: usize, radius: f32, result: &mut V2) {
+    let mut distance = 0.0;
+
+    for i in begin_line..lines.len() {
+        if det(lines[i].direction, lines[i].point - *result) <= distance {
+            continue;
+        }
+
+        // result does not satisfy constraint of line i
+        let mut proj_lines = vec![];
+        for j in 0..i {
+            let determinant = det(lines[i].direction, lines[j].direction);
+
+            let point = if determinant.abs() <= EPSILON {
+                if lines[i].direction.dot(&lines[j].direction) > 0.0 {
+                    // lines point in the same direction
+                    continue;
+                }
+
+                // lines point in opposite direction
+                (lines[i].point + lines[j].point) * 0.5
+            } else {
+                lines[i].point
+                    + lines[i].direction
+                        * (det(lines[j].direction, lines[i].point - lines[j].point) / determinant)
+            };
+
+            proj_lines.push(OrcaLine {
+                point,
+                direction: (lines[j].direction - lines[i].direction).normalize(),
+            });
+        }
+
+        let temp_result = *result;
+        let opt_direction = v2(-lines[i].direction.y, lines[i].direction.x);
+        if linear_program_2(&proj_lines, radius, opt_direction, true, result) < proj_lines.len() {
+            // should not happen, result is by definition already in the feasible region
+            *result = temp_result;
+        }
+
+        distance = det(lines[i].direction, lines[i].point - *result);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_solve_orca_without_neighbours_keep_preferred_velocity() {
+        let lines = compute_orca_lines(p2(0.0, 0.0), v2(10.0, 0.0), 1.0, &[], 2.0, 0.1);
+        assert_eq!(solve_orca(&lines, 20.0, v2(10.0, 0.0)), v2(10.0, 0.0));
+        assert_eq!(solve_orca(&lines, 5.0, v2(10.0, 0.0)), v2(5.0, 0.0));
+    }
+
+    #[test]
+    fn test_solve_orca_deviate_from_head_on() {
+        let neighbour = OrcaNeighbour {
+            pos: p2(10.0, 0.0),
+            vel: v2(-10.0, 0.0),
+            radius: 1.0,
+            responsibility: 0.5,
+        };
+
+        let lines = compute_orca_lines(p2(0.0, 0.0), v2(10.0, 0.0), 1.0, &[neighbour], 2.0, 0.1);
+        let vel = solve_orca(&lines, 20.0, v2(10.0, 0.0));
+        assert!(vel.y.abs() > 0.1, "{:?}", vel);
+    }
+
+    #[test]
+    fn test_solve_orca_reachable_stay_near_current_velocity() {
+        let neighbour = OrcaNeighbour {
+            pos: p2(10.0, 0.0),
+            vel: v2(-10.0, 0.0),
+            radius: 1.0,
+            responsibility: 0.5,
+        };
+
+        let lines = compute_orca_lines(p2(0.0, 0.0), v2(10.0, 0.0), 1.0, &[neighbour], 2.0, 0.1);
+        let vel = solve_orca_reachable(&lines, 20.0, v2(10.0, 0.0), 1.0, v2(10.0, 0.0));
+        assert!(
+            (vel - v2(10.0, 0.0)).magnitude() <= 1.0 + EPSILON,
+            "{:?}",
+            vel
+        );
+        assert!(vel.y.abs() > 0.1, "{:?}", vel);
+    }
+}
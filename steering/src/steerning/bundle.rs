@@ -0,0 +1,198 @@
+//! Register the steering components, resources and systems into a host application
+
+use super::components::*;
+use super::debug::*;
+use super::orders::*;
+use super::systems::*;
+
+use specs::prelude::*;
+use specs::{World, WorldExt};
+
+/// Groups of steering systems, executed in order. Systems added into a stage run after all
+/// systems of previous stages and before any system of next ones.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub enum Stage {
+    /// expire debug primitives, scripts and move orders that change the steering targets
+    PreSteer,
+    /// steering behaviours that collect forces into `Vehicle::forces`
+    Steer,
+    /// combine forces into the desired velocity and avoid collisions
+    Blend,
+    /// move vehicles
+    Integrate,
+    /// world boundary and models
+    Post,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [
+        Stage::PreSteer,
+        Stage::Steer,
+        Stage::Blend,
+        Stage::Integrate,
+        Stage::Post,
+    ];
+}
+
+pub struct SteeringBundle;
+
+impl SteeringBundle {
+    pub fn register_components(world: &mut World) {
+        world.register::<Model>();
+        world.register::<Cfg>();
+        world.register::<Vehicle>();
+        world.register::<MovingArea>();
+        world.register::<SteeringArrival>();
+        world.register::<SteeringSeparation>();
+        world.register::<SteeringVelocity>();
+        world.register::<Wall>();
+        world.register::<SteeringFormationMember>();
+        world.register::<SteeringFormationLeader>();
+        world.register::<SteeringScript>();
+        world.register::<SteeringAvoidance>();
+        world.register::<SteeringMoveOrder>();
+        world.register::<VehiclePhysics>();
+        world.register::<SteeringFlock>();
+        world.register::<Squad>();
+        world.register::<Fleet>();
+    }
+
+    /// Register components and insert the resources required by the systems
+    pub fn setup(world: &mut World, cfg: Cfg) {
+        SteeringBundle::register_components(world);
+
+        world.insert(GameTime { delta_time: 0.01 });
+        world.insert(DebugStuff::new());
+        world.insert(DebugDraw::new());
+        world.insert(GameRandom::new(cfg.seed));
+        world.insert(MoveOrders::default());
+        world.insert(cfg);
+    }
+
+    /// Add the steering systems to the builder. After the systems of each stage are added,
+    /// `hook` is called to add the host systems of that stage.
+    pub fn add_systems<'a, 'b, F>(builder: &mut DispatcherBuilder<'a, 'b>, mut hook: F)
+    where
+        F: FnMut(Stage, &mut DispatcherBuilder<'a, 'b>),
+    {
+        for (i, stage) in Stage::ALL.iter().enumerate() {
+            if i > 0 {
+                builder.add_barrier();
+            }
+
+            SteeringBundle::add_stage_systems(*stage, builder);
+            hook(*stage, builder);
+        }
+    }
+
+    fn add_stage_systems(stage: Stage, builder: &mut DispatcherBuilder) {
+        match stage {
+            Stage::PreSteer => {
+                builder.add(DebugDrawSystem, "debug_draw", &[]);
+                builder.add(SteeringScriptSystem, "steering_script", &[]);
+                builder.add(MoveOrderSystem, "move_orders", &[]);
+            }
+            Stage::Steer => {
+                builder.add(SteerArrivalSystem, "steering_arrival", &[]);
+                builder.add(SteeringSeparationSystem, "steering_separation", &[]);
+                builder.add(SteeringVelocitySystem, "steering_velocity", &[]);
+                builder.add(SteeringWallsSystem, "steering_walls", &[]);
+                builder.add(SteeringFlockSystem, "steering_flock", &[]);
+            }
+            Stage::Blend => {
+                builder.add(SteeringBlendSystem, "steering_blend", &[]);
+                builder.add(
+                    SteeringAvoidanceSystem,
+                    "steering_avoidance",
+                    &["steering_blend"],
+                );
+            }
+            Stage::Integrate => {
+                builder.add(MoveSystem, "move", &[]);
+            }
+            Stage::Post => {
+                builder.add(BoundarySystem, "boundary", &[]);
+                builder.add(UpdateModelPosSystem, "update_model", &["boundary"]);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::*;
+    use super::*;
+
+    #[derive(Default)]
+    struct ExecutedStages(Vec<Stage>);
+
+    struct RecordStageSystem(Stage);
+    impl<'a> System<'a> for RecordStageSystem {
+        type SystemData = (Write<'a, ExecutedStages>, ReadStorage<'a, Vehicle>);
+
+        fn run(&mut self, (mut executed, _vehicles): Self::SystemData) {
+            executed.0.push(self.0);
+        }
+    }
+
+    /// host system that push every vehicle to the right
+    struct PushRightSystem;
+    impl<'a> System<'a> for PushRightSystem {
+        type SystemData = WriteStorage<'a, Vehicle>;
+
+        fn run(&mut self, mut vehicles: Self::SystemData) {
+            for vehicle in (&mut vehicles).join() {
+                vehicle.add_force(SteeringBehaviour::Velocity, v2(10.0, 0.0));
+            }
+        }
+    }
+
+    #[test]
+    fn test_bundle_run_host_systems_in_stages() {
+        let mut world = World::new();
+        SteeringBundle::setup(&mut world, Default::default());
+
+        let entity = world
+            .create_entity()
+            .with(Vehicle {
+                pos: p2(100.0, 100.0),
+                dir: v2(1.0, 0.0),
+                desired_dir: v2(1.0, 0.0),
+                vel_dir: v2(0.0, 0.0),
+                speed: 0.0,
+                max_acc: 100.0,
+                desired_vel: v2(0.0, 0.0),
+                rotation_speed: 1.0,
+                max_speed: 50.0,
+                radius: 1.0,
+                forces: vec![],
+            })
+            .build();
+
+        let mut builder = DispatcherBuilder::new();
+        SteeringBundle::add_systems(&mut builder, |stage, builder| {
+            builder.add(
+                RecordStageSystem(stage),
+                &format!("record_{:?}", stage),
+                &[],
+            );
+            if stage == Stage::Steer {
+                builder.add(PushRightSystem, "push_right", &[]);
+            }
+        });
+
+        let mut dispatcher = builder.build();
+        dispatcher.setup(&mut world);
+        dispatcher.dispatch(&world);
+
+        assert_eq!(
+            world.read_resource::<ExecutedStages>().0,
+            Stage::ALL.to_vec()
+        );
+
+        let vehicles = world.read_storage::<Vehicle>();
+        let vehicle = vehicles.get(entity).unwrap();
+        assert!(vehicle.pos.x > 100.0);
+        assert_eq!(vehicle.pos.y, 100.0);
+    }
+}
@@ -1,3 +1,5 @@
+use super::scenario::ArchetypeDef;
+
 use commons::math::*;
 
 use ggez::graphics::Color;
@@ -10,11 +12,16 @@ use serde::{Deserialize, Serialize};
 use specs::prelude::*;
 use specs::{World, WorldExt};
 use specs_derive::Component;
+use std::collections::BTreeMap;
 
 #[derive(Clone, Debug, Component, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
 pub struct Cfg {
     pub screen_width: f32,
     pub screen_height: f32,
+    pub world_width: f32,
+    pub world_height: f32,
+    pub boundary_mode: BoundaryMode,
     pub seed: u64,
     pub vehicles: usize,
     pub followers: usize,
@@ -24,46 +31,193 @@ pub struct Cfg {
     pub separation_radius: f32,
     pub start_position: [f32; 4],
     pub arrival_distance: f32,
+    pub blend_mode: BlendMode,
+    /// vehicle kinds that scenarios reference by name
+    pub archetypes: BTreeMap<String, ArchetypeDef>,
+}
+
+impl Default for Cfg {
+    fn default() -> Self {
+        Cfg {
+            screen_width: 800.0,
+            screen_height: 600.0,
+            world_width: 800.0,
+            world_height: 600.0,
+            boundary_mode: BoundaryMode::Wrap,
+            seed: 0,
+            vehicles: 200,
+            followers: 20,
+            max_acc: 500.0,
+            max_speed: 50.0,
+            rotation_speed: 420.0,
+            separation_radius: 1.0,
+            start_position: [100.0, 100.0, 700.0, 500.0],
+            arrival_distance: 1.0,
+            blend_mode: BlendMode::WeightedSum,
+            archetypes: Default::default(),
+        }
+    }
 }
 
 impl Cfg {
-    // pub fn new() -> Self {
-    //     Cfg {
-    //         screen_width: 0.0,
-    //         screen_height: 0.0,
-    //         seed: 0,
-    //         vehicles: 0,
-    //         followers: 0,
-    //         max_acc: 0.0,
-    //         max_speed: 0.0,
-    //         rotation_speed: 0.0,
-    //         separation_radius: 0.0,
-    //         start_position: [0.0, 0.0, 0.0, 0.0],
-    //         arrival_distance: 0.0
-    //     }
-    // }
+    pub fn get_bounds(&self) -> WorldBounds {
+        WorldBounds {
+            width: self.world_width,
+            height: self.world_height,
+            mode: self.boundary_mode,
+        }
+    }
+}
+
+/// What happens with vehicles that leave the world area
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BoundaryMode {
+    /// teleport to the opposite side, the world is a torus
+    Wrap,
+    /// stop at the border
+    Clamp,
+    /// reflect the velocity
+    Bounce,
+    /// vehicles can move freely outside of the world area
+    None,
+}
+
+/// World area from (0, 0) to (width, height)
+#[derive(Clone, Copy, Debug)]
+pub struct WorldBounds {
+    pub width: f32,
+    pub height: f32,
+    pub mode: BoundaryMode,
+}
+
+impl WorldBounds {
+    /// shortest vector from a point to other, crossing the borders when wrapping
+    pub fn delta(&self, from: P2, to: P2) -> V2 {
+        let mut delta = to - from;
+
+        if self.mode == BoundaryMode::Wrap {
+            if delta.x > self.width * 0.5 {
+                delta.x -= self.width;
+            } else if delta.x < -self.width * 0.5 {
+                delta.x += self.width;
+            }
+
+            if delta.y > self.height * 0.5 {
+                delta.y -= self.height;
+            } else if delta.y < -self.height * 0.5 {
+                delta.y += self.height;
+            }
+        }
+
+        delta
+    }
+
+    pub fn distance(&self, from: P2, to: P2) -> f32 {
+        self.delta(from, to).magnitude()
+    }
+}
+
+/// How the forces produced by each steering behaviour are combined into the vehicle
+/// desired velocity
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum BlendMode {
+    /// sum of all forces, each one already scaled by its behaviour weight
+    WeightedSum,
+    /// sum forces in priority order until the accumulated magnitude reach `Vehicle::max_acc`
+    PrioritizedTruncatedSum,
+    /// pick the first behaviour in priority order that pass its probability check and
+    /// produce a relevant force
+    PrioritizedDithering,
+}
+
+impl Default for BlendMode {
+    fn default() -> Self {
+        BlendMode::WeightedSum
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub enum SteeringBehaviour {
+    Walls,
+    Separation,
+    Arrival,
+    Flocking,
+    Velocity,
+}
+
+impl SteeringBehaviour {
+    /// lower values are resolved first by the prioritized blend modes
+    pub fn priority(&self) -> u32 {
+        match self {
+            SteeringBehaviour::Walls => 0,
+            SteeringBehaviour::Separation => 1,
+            SteeringBehaviour::Arrival => 2,
+            SteeringBehaviour::Flocking => 3,
+            SteeringBehaviour::Velocity => 4,
+        }
+    }
+
+    /// chance of the behaviour be evaluated when using dithering
+    pub fn dithering_probability(&self) -> f32 {
+        match self {
+            SteeringBehaviour::Walls => 0.9,
+            SteeringBehaviour::Separation => 0.8,
+            SteeringBehaviour::Arrival => 0.8,
+            SteeringBehaviour::Flocking => 0.8,
+            SteeringBehaviour::Velocity => 1.0,
+        }
+    }
+
+    pub fn debug_color(&self) -> Color {
+        match self {
+            SteeringBehaviour::Walls => Color::new(0.0, 1.0, 0.5, 1.0),
+            SteeringBehaviour::Separation => Color::new(1.0, 0.0, 1.0, 1.0),
+            SteeringBehaviour::Arrival => Color::new(1.0, 1.0, 0.0, 1.0),
+            SteeringBehaviour::Flocking => Color::new(1.0, 0.5, 0.0, 1.0),
+            SteeringBehaviour::Velocity => Color::new(0.0, 0.5, 1.0, 1.0),
+        }
+    }
+}
+
+/// Force added by a single behaviour into a vehicle during the current tick
+#[derive(Clone, Debug)]
+pub struct SteeringForce {
+    pub behaviour: SteeringBehaviour,
+    pub force: V2,
 }
 
 #[derive(Clone, Debug, Component)]
 pub struct DebugStuff {
-    pub lines: Vec<(P2, P2, Color)>,
-    pub circles: Vec<(P2, f32, Color)>,
+    /// forces of each behaviour and if it was used by the blend on last tick
+    pub contributions: Vec<(Entity, SteeringForce, bool)>,
 }
 
 impl DebugStuff {
     pub fn new() -> Self {
         DebugStuff {
-            lines: Default::default(),
-            circles: Default::default(),
+            contributions: Default::default(),
         }
     }
 
-    pub fn push_line(&mut self, a: P2, b: P2, color: Color) {
-        self.lines.push((a, b, color));
+    pub fn push_contribution(&mut self, entity: Entity, force: SteeringForce, used: bool) {
+        self.contributions.push((entity, force, used));
+    }
+
+    pub fn take_contributions(&mut self) -> Vec<(Entity, SteeringForce, bool)> {
+        std::mem::replace(&mut self.contributions, vec![])
     }
+}
 
-    pub fn take_lines(&mut self) -> Vec<(P2, P2, Color)> {
-        std::mem::replace(&mut self.lines, vec![])
+#[derive(Clone, Debug)]
+pub struct GameRandom {
+    pub rng: StdRng,
+}
+
+impl GameRandom {
+    pub fn new(seed: u64) -> Self {
+        GameRandom {
+            rng: SeedableRng::seed_from_u64(seed),
+        }
     }
 }
 
@@ -104,6 +258,9 @@ pub struct Vehicle {
     pub desired_vel: V2,
     pub rotation_speed: f32,
     pub max_speed: f32,
+    pub radius: f32,
+    /// forces collected from steering behaviours, consumed by the blend
+    pub forces: Vec<SteeringForce>,
 }
 
 impl Vehicle {
@@ -111,11 +268,50 @@ impl Vehicle {
         self.vel_dir * self.speed
     }
 
+    pub fn set_velocity(&mut self, vel: V2) {
+        self.speed = vel.magnitude();
+        self.vel_dir = if self.speed > 0.0 {
+            vel / self.speed
+        } else {
+            Vector2::zeros()
+        };
+    }
+
+    pub fn add_force(&mut self, behaviour: SteeringBehaviour, force: V2) {
+        self.forces.push(SteeringForce { behaviour, force });
+    }
+
     pub fn rotate_towards_vec(&mut self, vec: V2, delta_time: f32) {
         self.dir = rotate_towards(self.dir, vec, self.rotation_speed * delta_time);
     }
 }
 
+/// Optional physical model of a vehicle, when present the steering desired velocity is reached
+/// by firing thrusters relative to `Vehicle::dir` instead of changing the velocity directly.
+///
+/// Forward thrust is only fired when the vehicle faces the desired acceleration within
+/// `burn_angle`, so it turns before burn.
+#[derive(Clone, Debug, Component)]
+pub struct VehiclePhysics {
+    pub mass: f32,
+    /// max force along `dir`
+    pub thrust: f32,
+    /// max force against `dir`
+    pub reverse_thrust: f32,
+    /// max force perpendicular to `dir`
+    pub lateral_thrust: f32,
+    /// fraction of the velocity lost per second
+    pub linear_drag: f32,
+    /// fraction of the angular velocity lost per second
+    pub angular_drag: f32,
+    /// radians per second squared
+    pub angular_acc: f32,
+    /// radians
+    pub burn_angle: f32,
+    /// radians per second, positive is counter clockwise
+    pub angular_vel: f32,
+}
+
 #[derive(Clone, Debug, Component)]
 pub struct SteeringSeparation {
     pub enabled: bool,
@@ -123,6 +319,16 @@ pub struct SteeringSeparation {
     pub weight: f32,
 }
 
+/// Replace the blended desired velocity by the closest one that is collision free with
+/// other vehicles, see `avoidance` module. Only velocities reachable with `Vehicle::max_acc`
+/// are considered, so a low acceleration needs a longer `time_horizon` to avoid in time.
+#[derive(Clone, Debug, Component)]
+pub struct SteeringAvoidance {
+    pub enabled: bool,
+    /// how many seconds ahead collisions are avoided
+    pub time_horizon: f32,
+}
+
 #[derive(Clone, Debug, Component)]
 pub struct SteeringVelocity {
     pub enabled: bool,
@@ -139,12 +345,76 @@ pub struct SteeringArrival {
     pub arrived: bool,
 }
 
+/// Sequence of targets that are set into the `SteeringArrival` one after another
+#[derive(Clone, Debug, Component)]
+pub struct SteeringScript {
+    pub targets: Vec<P2>,
+    pub index: usize,
+    pub looping: bool,
+    /// distance from the target to consider it reached
+    pub distance: f32,
+}
+
+impl SteeringScript {
+    pub fn current(&self) -> Option<P2> {
+        self.targets.get(self.index).cloned()
+    }
+
+    /// move to next target, return false when there is no next target
+    pub fn next(&mut self) -> bool {
+        if self.index + 1 < self.targets.len() {
+            self.index += 1;
+            true
+        } else if self.looping && !self.targets.is_empty() {
+            self.index = 0;
+            true
+        } else {
+            false
+        }
+    }
+}
+
 // #[derive(Clone, Debug, Component)]
 // struct SteeringKeepPosition {
 //     enable: bool,
 //     target_pos: P2,
 // }
 
+/// Membership of the vehicle into a group move order, see `orders::MoveOrders`
+#[derive(Clone, Debug, Component)]
+pub struct SteeringMoveOrder {
+    pub order: u32,
+}
+
+/// Vehicle member of a squad
+#[derive(Clone, Debug, Component)]
+pub struct SteeringFlock {
+    /// entity with the `Squad`
+    pub squad: Entity,
+}
+
+/// Group of vehicles that keep together, optionally part of a fleet. It is an entity by itself,
+/// without `Vehicle`.
+#[derive(Clone, Debug, Component)]
+pub struct Squad {
+    /// entity with the `Fleet`
+    pub fleet: Option<Entity>,
+    pub cohesion: f32,
+    pub alignment: f32,
+    /// slot of the squad in the fleet formation, 0 is the fleet anchor
+    pub formation_index: Option<usize>,
+    pub formation_weight: f32,
+}
+
+/// Group of squads. The anchor of the formation is a member that is a
+/// `SteeringFormationLeader`, or the center of the fleet if there is none.
+#[derive(Clone, Debug, Component)]
+pub struct Fleet {
+    pub cohesion: f32,
+    pub alignment: f32,
+    pub formation: FormationType,
+}
+
 #[derive(Clone, Debug, Component)]
 pub struct SteeringFormationLeader {
     pub formation: FormationType,
@@ -155,7 +425,7 @@ pub struct SteeringFormationMember {
     pub index: usize,
 }
 
-#[derive(Clone, Debug, Copy)]
+#[derive(Clone, Debug, Copy, PartialEq, Serialize, Deserialize)]
 pub enum FormationType {
     Circle,
     Bar,
@@ -211,22 +481,238 @@ pub struct Model {
     pub color: Color,
 }
 
+/// Side of a wall segment, relative to its direction, that vehicles are pushed to
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum WallSide {
+    Both,
+    Left,
+    Right,
+    /// inside of a closed polygon, like a room
+    Inside,
+    /// outside of a closed polygon, like an obstacle
+    Outside,
+}
+
+impl Default for WallSide {
+    fn default() -> Self {
+        WallSide::Both
+    }
+}
+
+/// distance a vehicle is kept from a wall it would cross during a move
+pub const WALL_SWEEP_MARGIN: f32 = 0.1;
+
 #[derive(Clone, Debug, Component)]
 pub struct Wall {
     pub pos: P2,
     pub vec: V2,
-    pub force: V2,
+    /// unit vector perpendicular to the segment pointing to the side where vehicles are pushed,
+    /// none when both sides are pushed away
+    pub normal: Option<V2>,
+    /// max intensity of the push, applied when touching the segment
+    pub force: f32,
     pub min_distance: f32,
 }
 
 impl Wall {
-    pub fn new_from_points(p0: P2, p1: P2, min_distance: f32, force: V2) -> Self {
+    pub fn new_from_points(p0: P2, p1: P2, min_distance: f32, force: f32) -> Self {
         let vec = p1.coords - p0.clone().coords;
         Wall {
             pos: p0,
             vec: vec,
+            normal: None,
             force: force,
             min_distance,
         }
     }
+
+    /// Create the walls of each segment of the polyline, when `closed` the last point is
+    /// connected to the first one. `Inside` and `Outside` sides require a closed polyline.
+    pub fn from_polyline(
+        points: &[P2],
+        closed: bool,
+        side: WallSide,
+        min_distance: f32,
+        force: f32,
+    ) -> Result<Vec<Wall>, String> {
+        if points.len() < 2 {
+            return Err(format!(
+                "wall requires at least 2 points, found {}",
+                points.len()
+            ));
+        }
+
+        let side = match side {
+            WallSide::Inside | WallSide::Outside if !closed => {
+                return Err(format!("wall side {:?} requires a closed polygon", side));
+            }
+            WallSide::Inside | WallSide::Outside => {
+                // positive area means that interior is at left of each segment
+                let ccw = polygon_signed_area(points) > 0.0;
+                if ccw == (side == WallSide::Inside) {
+                    WallSide::Left
+                } else {
+                    WallSide::Right
+                }
+            }
+            other => other,
+        };
+
+        let mut segments: Vec<(P2, P2)> = points.windows(2).map(|w| (w[0], w[1])).collect();
+        if closed && points.len() > 2 {
+            segments.push((points[points.len() - 1], points[0]));
+        }
+
+        let walls = segments
+            .into_iter()
+            .filter(|(p0, p1)| (p1 - p0).magnitude() > 0.0)
+            .map(|(p0, p1)| {
+                let mut wall = Wall::new_from_points(p0, p1, min_distance, force);
+                let left = v2(-wall.vec.y, wall.vec.x).normalize();
+                wall.normal = match side {
+                    WallSide::Left => Some(left),
+                    WallSide::Right => Some(-left),
+                    _ => None,
+                };
+                wall
+            })
+            .collect();
+
+        Ok(walls)
+    }
+
+    pub fn closest_point(&self, point: P2) -> P2 {
+        let percent = line_segment_project_percent(self.pos, self.vec, point);
+        self.pos + self.vec * clamp01(percent)
+    }
+
+    /// Direction the point is pushed and its distance to the wall. The distance is computed
+    /// to the closest point of the segment, so endpoints work like a capsule.
+    pub fn push_direction(&self, point: P2) -> (V2, f32) {
+        let vector = point - self.closest_point(point);
+        let distance = vector.magnitude();
+
+        match self.normal {
+            // at the back side, push back to the front
+            Some(normal) if vector.dot(&normal) < 0.0 => (normal, distance),
+            Some(normal) if distance < 0.0001 => (normal, distance),
+            None if distance < 0.0001 => (v2(-self.vec.y, self.vec.x).normalize(), distance),
+            _ => (vector / distance, distance),
+        }
+    }
+
+    /// Check if moving from `from` by `movement` cross the wall. Return the percent of the
+    /// movement until the hit and the normal pointing to the side of `from`.
+    ///
+    /// One sided walls only block movements coming from its front.
+    pub fn sweep(&self, from: P2, movement: V2) -> Option<(f32, V2)> {
+        let cross = |a: V2, b: V2| a.x * b.y - a.y * b.x;
+
+        let denominator = cross(movement, self.vec);
+        if denominator.abs() < 0.000001 {
+            return None;
+        }
+
+        let delta = self.pos - from;
+        let t = cross(delta, self.vec) / denominator;
+        let u = cross(delta, movement) / denominator;
+        if t < 0.0 || t > 1.0 || u < 0.0 || u > 1.0 {
+            return None;
+        }
+
+        let left = v2(-self.vec.y, self.vec.x).normalize();
+        let side = -delta.dot(&left);
+        let side_normal = if side > 0.0 || (side == 0.0 && movement.dot(&left) < 0.0) {
+            left
+        } else {
+            -left
+        };
+
+        match self.normal {
+            Some(normal) if normal.dot(&side_normal) < 0.0 => None,
+            _ => Some((t, side_normal)),
+        }
+    }
+}
+
+/// Shoelace formula, positive when points are counter clockwise
+fn polygon_signed_area(points: &[P2]) -> f32 {
+    let mut area = 0.0;
+    for i in 0..points.len() {
+        let a = points[i];
+        let b = points[(i + 1) % points.len()];
+        area += a.x * b.y - b.x * a.y;
+    }
+    area * 0.5
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_world_bounds_delta_across_borders_when_wrapping() {
+        let mut bounds = WorldBounds {
+            width: 100.0,
+            height: 50.0,
+            mode: BoundaryMode::Wrap,
+        };
+
+        assert_eq!(bounds.delta(p2(5.0, 25.0), p2(95.0, 25.0)), v2(-10.0, 0.0));
+        assert_eq!(bounds.delta(p2(50.0, 45.0), p2(50.0, 5.0)), v2(0.0, 10.0));
+        assert_eq!(bounds.delta(p2(10.0, 10.0), p2(30.0, 20.0)), v2(20.0, 10.0));
+
+        bounds.mode = BoundaryMode::Bounce;
+        assert_eq!(bounds.delta(p2(5.0, 25.0), p2(95.0, 25.0)), v2(90.0, 0.0));
+    }
+
+    #[test]
+    fn test_wall_from_polygon_normals() {
+        // counter clockwise square
+        let points = vec![p2(0.0, 0.0), p2(10.0, 0.0), p2(10.0, 10.0), p2(0.0, 10.0)];
+
+        let walls = Wall::from_polyline(&points, true, WallSide::Inside, 2.0, 100.0).unwrap();
+        assert_eq!(walls.len(), 4);
+        assert_eq!(walls[0].normal, Some(v2(0.0, 1.0)));
+        assert_eq!(walls[1].normal, Some(v2(-1.0, 0.0)));
+
+        // same result with the points in the other order
+        let reversed: Vec<P2> = points.iter().rev().cloned().collect();
+        let walls = Wall::from_polyline(&reversed, true, WallSide::Outside, 2.0, 100.0).unwrap();
+        assert!(walls.iter().any(|wall| wall.normal == Some(v2(0.0, -1.0))));
+
+        assert!(Wall::from_polyline(&points, false, WallSide::Inside, 2.0, 100.0).is_err());
+        assert!(Wall::from_polyline(&points[0..1], false, WallSide::Both, 2.0, 100.0).is_err());
+    }
+
+    #[test]
+    fn test_wall_push_direction_with_capsule_endpoints() {
+        let wall = Wall::new_from_points(p2(0.0, 0.0), p2(10.0, 0.0), 2.0, 100.0);
+
+        assert_eq!(wall.push_direction(p2(5.0, 1.0)), (v2(0.0, 1.0), 1.0));
+        assert_eq!(wall.push_direction(p2(5.0, -1.0)), (v2(0.0, -1.0), 1.0));
+        // past the endpoint
+        assert_eq!(wall.push_direction(p2(11.0, 0.0)), (v2(1.0, 0.0), 1.0));
+
+        let mut one_sided = wall.clone();
+        one_sided.normal = Some(v2(0.0, 1.0));
+        assert_eq!(one_sided.push_direction(p2(5.0, -1.0)), (v2(0.0, 1.0), 1.0));
+    }
+
+    #[test]
+    fn test_wall_sweep() {
+        let wall = Wall::new_from_points(p2(0.0, 0.0), p2(10.0, 0.0), 2.0, 100.0);
+
+        let (t, normal) = wall.sweep(p2(5.0, 5.0), v2(0.0, -10.0)).unwrap();
+        assert_eq!(t, 0.5);
+        assert_eq!(normal, v2(0.0, 1.0));
+
+        assert!(wall.sweep(p2(5.0, 5.0), v2(0.0, -4.0)).is_none());
+        assert!(wall.sweep(p2(15.0, 5.0), v2(0.0, -10.0)).is_none());
+
+        let mut one_sided = wall.clone();
+        one_sided.normal = Some(v2(0.0, -1.0));
+        assert!(one_sided.sweep(p2(5.0, 5.0), v2(0.0, -10.0)).is_none());
+        assert!(one_sided.sweep(p2(5.0, -5.0), v2(0.0, 10.0)).is_some());
+    }
 }
@@ -0,0 +1,307 @@
+use super::components::*;
+
+use ggez::{GameError, GameResult};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+pub const DEFAULT_CFG_PATH: &str = "steering/resources/config.json";
+
+/// Prefix of environment variables that override config fields, `STEERING_MAX_SPEED=80`
+pub const ENV_PREFIX: &str = "STEERING_";
+
+/// Key in the config file that hold the named profiles
+const PROFILES_KEY: &str = "profiles";
+
+/// Build a `Cfg` by merging, in order, the defaults, the file, the selected profile of the
+/// file, environment variables and explicit overrides.
+///
+/// The config file can be partial, missing fields keep the default value. Profiles are
+/// partial configs under the "profiles" key that are applied over the file values:
+///
+/// ```json
+/// { "vehicles": 200, "profiles": { "crowd": { "vehicles": 500 } } }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct CfgLoader {
+    path: Option<PathBuf>,
+    profile: Option<String>,
+    env: bool,
+    overrides: Vec<(String, String)>,
+}
+
+impl CfgLoader {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    pub fn path<P: AsRef<Path>>(mut self, path: P) -> Self {
+        self.path = Some(path.as_ref().to_path_buf());
+        self
+    }
+
+    pub fn profile(mut self, name: &str) -> Self {
+        self.profile = Some(name.to_string());
+        self
+    }
+
+    /// read overrides from environment variables with `ENV_PREFIX`
+    pub fn env(mut self, enabled: bool) -> Self {
+        self.env = enabled;
+        self
+    }
+
+    /// override a field with a json value, plain strings are accepted without quotes
+    pub fn override_value(mut self, field: &str, value: &str) -> Self {
+        self.overrides.push((field.to_string(), value.to_string()));
+        self
+    }
+
+    /// parse a command line override in the format `field=value`
+    pub fn override_arg(self, arg: &str) -> GameResult<Self> {
+        let mut parts = arg.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(field), Some(value)) if !field.is_empty() => {
+                Ok(self.override_value(field.trim(), value.trim()))
+            }
+            _ => Err(GameError::ConfigError(format!(
+                "invalid override '{}', expected field=value",
+                arg
+            ))),
+        }
+    }
+
+    pub fn load(&self) -> GameResult<Cfg> {
+        let file = match &self.path {
+            Some(path) => {
+                let content = std::fs::read_to_string(path).map_err(|e| {
+                    GameError::ConfigError(format!(
+                        "fail to read config file {}: {}",
+                        path.display(),
+                        e
+                    ))
+                })?;
+                Some((path.display().to_string(), content))
+            }
+            None => None,
+        };
+
+        self.load_from(
+            file.as_ref()
+                .map(|(source, content)| (source.as_str(), content.as_str())),
+        )
+    }
+
+    /// same as `load` but using the given content instead of reading the config file
+    pub fn load_from_str(&self, content: &str) -> GameResult<Cfg> {
+        self.load_from(Some(("<string>", content)))
+    }
+
+    fn load_from(&self, file: Option<(&str, &str)>) -> GameResult<Cfg> {
+        let mut layers = Layers::new()?;
+
+        if let Some((source, content)) = file {
+            let mut value: Value = serde_json::from_str(content).map_err(|e| {
+                GameError::ConfigError(format!("invalid config file {}: {}", source, e))
+            })?;
+
+            let mut file_values = match value.as_object_mut() {
+                Some(map) => std::mem::replace(map, Map::new()),
+                None => {
+                    return Err(GameError::ConfigError(format!(
+                        "invalid config file {}: expected a json object",
+                        source
+                    )))
+                }
+            };
+
+            let profiles = file_values.remove(PROFILES_KEY);
+            layers.merge(source, file_values);
+
+            if let Some(name) = &self.profile {
+                let profile = profiles
+                    .as_ref()
+                    .and_then(|profiles| profiles.get(name))
+                    .and_then(|profile| profile.as_object())
+                    .ok_or_else(|| {
+                        let available: Vec<&String> = profiles
+                            .as_ref()
+                            .and_then(|profiles| profiles.as_object())
+                            .map(|profiles| profiles.keys().collect())
+                            .unwrap_or_default();
+
+                        GameError::ConfigError(format!(
+                            "profile '{}' not found in {}, available {:?}",
+                            name, source, available
+                        ))
+                    })?;
+
+                layers.merge(&format!("{} profile '{}'", source, name), profile.clone());
+            }
+        } else if let Some(name) = &self.profile {
+            return Err(GameError::ConfigError(format!(
+                "profile '{}' requires a config file",
+                name
+            )));
+        }
+
+        if self.env {
+            let fields: Vec<String> = layers.values.keys().cloned().collect();
+            for field in fields {
+                let key = format!("{}{}", ENV_PREFIX, field.to_uppercase());
+                if let Ok(value) = std::env::var(&key) {
+                    layers.set(&key, &field, &value)?;
+                }
+            }
+        }
+
+        for (field, value) in &self.overrides {
+            layers.set("override", field, value)?;
+        }
+
+        layers.build()
+    }
+}
+
+/// Config values and from where each one came from, used to report errors
+struct Layers {
+    values: Map<String, Value>,
+    sources: HashMap<String, String>,
+}
+
+impl Layers {
+    fn new() -> GameResult<Self> {
+        let values = match serde_json::to_value(Cfg::default()) {
+            Ok(Value::Object(map)) => map,
+            _ => {
+                return Err(GameError::ConfigError(
+                    "fail to serialize default config".to_string(),
+                ))
+            }
+        };
+
+        Ok(Layers {
+            values,
+            sources: HashMap::new(),
+        })
+    }
+
+    fn merge(&mut self, source: &str, values: Map<String, Value>) {
+        for (field, value) in values {
+            self.sources.insert(field.clone(), source.to_string());
+            self.values.insert(field, value);
+        }
+    }
+
+    fn set(&mut self, source: &str, field: &str, raw_value: &str) -> GameResult<()> {
+        if !self.values.contains_key(field) {
+            return Err(GameError::ConfigError(format!(
+                "unknown config field '{}' from {}",
+                field, source
+            )));
+        }
+
+        let value = serde_json::from_str(raw_value)
+            .unwrap_or_else(|_| Value::String(raw_value.to_string()));
+
+        self.sources.insert(field.to_string(), source.to_string());
+        self.values.insert(field.to_string(), value);
+        Ok(())
+    }
+
+    fn build(self) -> GameResult<Cfg> {
+        match serde_json::from_value(Value::Object(self.values.clone())) {
+            Ok(cfg) => Ok(cfg),
+            Err(e) => Err(self.find_error(e)),
+        }
+    }
+
+    /// deserialize each field isolated to find which one is invalid
+    fn find_error(&self, error: serde_json::Error) -> GameError {
+        for (field, value) in &self.values {
+            let mut single = Map::new();
+            single.insert(field.clone(), value.clone());
+
+            if let Err(e) = serde_json::from_value::<Cfg>(Value::Object(single)) {
+                let source = self
+                    .sources
+                    .get(field)
+                    .map(|source| source.as_str())
+                    .unwrap_or("default");
+
+                return GameError::ConfigError(format!(
+                    "invalid config field '{}' from {}: {}",
+                    field, source, e
+                ));
+            }
+        }
+
+        GameError::ConfigError(format!("invalid config: {}", error))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_load_partial_config_with_defaults() {
+        let cfg = CfgLoader::new()
+            .load_from_str(r#"{ "vehicles": 10, "max_speed": 80.0 }"#)
+            .unwrap();
+
+        assert_eq!(cfg.vehicles, 10);
+        assert_eq!(cfg.max_speed, 80.0);
+        assert_eq!(cfg.max_acc, Cfg::default().max_acc);
+    }
+
+    #[test]
+    fn test_load_profile_and_overrides() {
+        let content = r#"{
+            "vehicles": 10,
+            "followers": 2,
+            "profiles": {
+                "crowd": { "vehicles": 500, "blend_mode": "PrioritizedTruncatedSum" }
+            }
+        }"#;
+
+        let cfg = CfgLoader::new()
+            .profile("crowd")
+            .override_arg("followers=5")
+            .unwrap()
+            .load_from_str(content)
+            .unwrap();
+
+        assert_eq!(cfg.vehicles, 500);
+        assert_eq!(cfg.followers, 5);
+        assert_eq!(cfg.blend_mode, BlendMode::PrioritizedTruncatedSum);
+    }
+
+    #[test]
+    fn test_load_report_invalid_field() {
+        let result = CfgLoader::new().load_from_str(r#"{ "max_speed": "fast" }"#);
+        match result {
+            Err(GameError::ConfigError(msg)) => assert!(msg.contains("max_speed"), "{}", msg),
+            other => panic!("unexpected result {:?}", other),
+        }
+
+        let result = CfgLoader::new()
+            .override_value("blend_mode", "Unknown")
+            .load_from_str("{}");
+        match result {
+            Err(GameError::ConfigError(msg)) => {
+                assert!(msg.contains("blend_mode"), "{}", msg);
+                assert!(msg.contains("override"), "{}", msg);
+            }
+            other => panic!("unexpected result {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_load_unknown_profile() {
+        let result = CfgLoader::new()
+            .profile("missing")
+            .load_from_str(r#"{ "profiles": { "crowd": {} } }"#);
+        assert!(result.is_err());
+    }
+}
@@ -0,0 +1,354 @@
+//! Debug geometry produced by the systems, drawn by the caller or exported to SVG
+
+use commons::math::*;
+use ggez::graphics::Color;
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// Max number of primitives kept, new ones are dropped when full
+pub const MAX_PRIMITIVES: usize = 100_000;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Serialize, Deserialize)]
+pub enum DebugCategory {
+    /// forces used by the steering blend
+    Forces,
+    Avoidance,
+    Walls,
+    Orders,
+    Other,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum DebugShape {
+    Line { from: P2, to: P2 },
+    Circle { center: P2, radius: f32 },
+    Arrow { from: P2, to: P2 },
+    Text { pos: P2, text: String },
+    Polygon { points: Vec<P2> },
+}
+
+#[derive(Clone, Debug)]
+pub struct DebugPrimitive {
+    pub shape: DebugShape,
+    pub color: Color,
+    pub category: DebugCategory,
+    /// number of ticks it still will be visible, including the current one
+    pub ticks_left: u32,
+}
+
+/// Debug geometry of current tick. Primitives are kept for its lifetime in ticks, so it is
+/// safe to read them any number of times or none.
+#[derive(Clone, Debug, Default)]
+pub struct DebugDraw {
+    primitives: Vec<DebugPrimitive>,
+    disabled: BTreeSet<DebugCategory>,
+}
+
+impl DebugDraw {
+    pub fn new() -> Self {
+        Default::default()
+    }
+
+    /// Add the primitive visible for `lifetime` ticks, a lifetime of 0 is considered 1
+    pub fn push(
+        &mut self,
+        category: DebugCategory,
+        shape: DebugShape,
+        color: Color,
+        lifetime: u32,
+    ) {
+        if !self.is_enabled(category) || self.primitives.len() >= MAX_PRIMITIVES {
+            return;
+        }
+
+        self.primitives.push(DebugPrimitive {
+            shape,
+            color,
+            category,
+            ticks_left: lifetime.max(1),
+        });
+    }
+
+    pub fn line(&mut self, category: DebugCategory, from: P2, to: P2, color: Color) {
+        self.push(category, DebugShape::Line { from, to }, color, 1);
+    }
+
+    pub fn circle(&mut self, category: DebugCategory, center: P2, radius: f32, color: Color) {
+        self.push(category, DebugShape::Circle { center, radius }, color, 1);
+    }
+
+    pub fn arrow(&mut self, category: DebugCategory, from: P2, to: P2, color: Color) {
+        self.push(category, DebugShape::Arrow { from, to }, color, 1);
+    }
+
+    pub fn text(&mut self, category: DebugCategory, pos: P2, text: &str, color: Color) {
+        let shape = DebugShape::Text {
+            pos,
+            text: text.to_string(),
+        };
+        self.push(category, shape, color, 1);
+    }
+
+    pub fn polygon(&mut self, category: DebugCategory, points: Vec<P2>, color: Color) {
+        self.push(category, DebugShape::Polygon { points }, color, 1);
+    }
+
+    pub fn is_enabled(&self, category: DebugCategory) -> bool {
+        !self.disabled.contains(&category)
+    }
+
+    /// Disabled categories are not collected and existing primitives are removed
+    pub fn set_enabled(&mut self, category: DebugCategory, enabled: bool) {
+        if enabled {
+            self.disabled.remove(&category);
+        } else {
+            self.disabled.insert(category);
+            self.primitives.retain(|p| p.category != category);
+        }
+    }
+
+    pub fn get_primitives(&self) -> &[DebugPrimitive] {
+        &self.primitives
+    }
+
+    /// Remove all primitives, independent of its lifetime
+    pub fn take(&mut self) -> Vec<DebugPrimitive> {
+        std::mem::replace(&mut self.primitives, vec![])
+    }
+
+    /// Advance one tick, removing primitives that expired
+    pub fn tick(&mut self) {
+        self.primitives.retain(|p| p.ticks_left > 1);
+        for p in &mut self.primitives {
+            p.ticks_left -= 1;
+        }
+    }
+}
+
+/// Expire debug primitives of previous tick, must run before any system that draws
+pub struct DebugDrawSystem;
+impl<'a> System<'a> for DebugDrawSystem {
+    type SystemData = WriteExpect<'a, DebugDraw>;
+
+    fn run(&mut self, mut debug_draw: Self::SystemData) {
+        debug_draw.tick();
+    }
+}
+
+/// SVG attributes to paint with the color, `attribute` is either `stroke` or `fill`
+fn svg_paint(attribute: &str, color: Color) -> String {
+    format!(
+        "{a}=\"rgb({},{},{})\" {a}-opacity=\"{}\"",
+        (color.r * 255.0).round() as u8,
+        (color.g * 255.0).round() as u8,
+        (color.b * 255.0).round() as u8,
+        color.a,
+        a = attribute
+    )
+}
+
+fn svg_escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+}
+
+/// Render the primitives as a SVG document of the given size
+pub fn to_svg(primitives: &[DebugPrimitive], width: f32, height: f32) -> String {
+    let mut out = String::new();
+
+    // writing into a String never fails
+    let _ = writeln!(
+        out,
+        "<svg xmlns=\"http://www.w3.org/2000/svg\" width=\"{w}\" height=\"{h}\" viewBox=\"0 0 {w} {h}\">",
+        w = width,
+        h = height
+    );
+
+    for p in primitives {
+        let stroke = svg_paint("stroke", p.color);
+        let _ = match &p.shape {
+            DebugShape::Line { from, to } => writeln!(
+                out,
+                "  <line x1=\"{}\" y1=\"{}\" x2=\"{}\" y2=\"{}\" {}/>",
+                from.x, from.y, to.x, to.y, stroke
+            ),
+            DebugShape::Circle { center, radius } => writeln!(
+                out,
+                "  <circle cx=\"{}\" cy=\"{}\" r=\"{}\" fill=\"none\" {}/>",
+                center.x, center.y, radius, stroke
+            ),
+            DebugShape::Arrow { from, to } => {
+                let head = arrow_head(*from, *to);
+                writeln!(
+                    out,
+                    "  <polyline points=\"{},{} {},{} {},{} {},{} {},{}\" fill=\"none\" {}/>",
+                    from.x,
+                    from.y,
+                    to.x,
+                    to.y,
+                    head[0].x,
+                    head[0].y,
+                    to.x,
+                    to.y,
+                    head[1].x,
+                    head[1].y,
+                    stroke
+                )
+            }
+            DebugShape::Text { pos, text } => writeln!(
+                out,
+                "  <text x=\"{}\" y=\"{}\" {}>{}</text>",
+                pos.x,
+                pos.y,
+                svg_paint("fill", p.color),
+                svg_escape(text)
+            ),
+            DebugShape::Polygon { points } => {
+                let points: Vec<String> =
+                    points.iter().map(|p| format!("{},{}", p.x, p.y)).collect();
+                writeln!(
+                    out,
+                    "  <polygon points=\"{}\" fill=\"none\" {}/>",
+                    points.join(" "),
+                    stroke
+                )
+            }
+        };
+    }
+
+    out.push_str("</svg>\n");
+    out
+}
+
+/// The two points of the arrow head at `to`
+pub fn arrow_head(from: P2, to: P2) -> [P2; 2] {
+    let vec = to - from;
+    let length = vec.magnitude();
+    if length < 0.0001 {
+        return [to, to];
+    }
+
+    let size = (length * 0.25).min(5.0);
+    let back = -vec / length * size;
+    let side = v2(-back.y, back.x) * 0.5;
+    [to + back + side, to + back - side]
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_debug_draw_lifetime_and_categories() {
+        let mut debug = DebugDraw::new();
+        debug.line(
+            DebugCategory::Forces,
+            p2(0.0, 0.0),
+            p2(1.0, 0.0),
+            ggez::graphics::WHITE,
+        );
+        debug.push(
+            DebugCategory::Walls,
+            DebugShape::Circle {
+                center: p2(0.0, 0.0),
+                radius: 2.0,
+            },
+            ggez::graphics::WHITE,
+            3,
+        );
+        assert_eq!(debug.get_primitives().len(), 2);
+
+        debug.tick();
+        assert_eq!(debug.get_primitives().len(), 1);
+        debug.tick();
+        assert_eq!(debug.get_primitives().len(), 1);
+        debug.tick();
+        assert_eq!(debug.get_primitives().len(), 0);
+
+        debug.set_enabled(DebugCategory::Forces, false);
+        debug.line(
+            DebugCategory::Forces,
+            p2(0.0, 0.0),
+            p2(1.0, 0.0),
+            ggez::graphics::WHITE,
+        );
+        assert!(debug.get_primitives().is_empty());
+
+        debug.set_enabled(DebugCategory::Forces, true);
+        debug.line(
+            DebugCategory::Forces,
+            p2(0.0, 0.0),
+            p2(1.0, 0.0),
+            ggez::graphics::WHITE,
+        );
+        assert_eq!(debug.take().len(), 1);
+        assert!(debug.get_primitives().is_empty());
+    }
+
+    #[test]
+    fn test_to_svg() {
+        let mut debug = DebugDraw::new();
+        let red = Color::new(1.0, 0.0, 0.0, 1.0);
+        debug.line(DebugCategory::Other, p2(1.0, 2.0), p2(3.0, 4.0), red);
+        debug.circle(DebugCategory::Other, p2(5.0, 5.0), 2.0, red);
+        debug.arrow(DebugCategory::Other, p2(0.0, 0.0), p2(10.0, 0.0), red);
+        debug.text(DebugCategory::Other, p2(1.0, 1.0), "a < b", red);
+        debug.polygon(
+            DebugCategory::Other,
+            vec![p2(0.0, 0.0), p2(1.0, 0.0), p2(1.0, 1.0)],
+            red,
+        );
+
+        let svg = to_svg(debug.get_primitives(), 100.0, 50.0);
+        assert!(svg.starts_with("<svg"));
+        assert!(svg.contains("viewBox=\"0 0 100 50\""));
+        assert!(svg.contains("<line x1=\"1\" y1=\"2\" x2=\"3\" y2=\"4\" stroke=\"rgb(255,0,0)\" stroke-opacity=\"1\"/>"));
+        assert!(svg.contains("<circle cx=\"5\" cy=\"5\" r=\"2\""));
+        assert!(svg.contains("<polyline points=\"0,0 10,0"));
+        assert!(svg.contains(">a &lt; b</text>"));
+        assert!(svg.contains("<polygon points=\"0,0 1,0 1,1\""));
+        assert!(svg.trim_end().ends_with("</svg>"));
+    }
+
+    #[test]
+    fn test_world_debug_svg_expire_forces() {
+        use super::super::*;
+
+        let cfg = Cfg {
+            vehicles: 5,
+            followers: 0,
+            ..Default::default()
+        };
+        let mut world = create_world(cfg).unwrap();
+        initialize_world(&mut world).unwrap();
+
+        let mut dispatcher = create_dispatcher();
+        dispatcher.setup(&mut world);
+        dispatcher.dispatch(&world);
+
+        assert!(!get_debug_primitives(&world).is_empty());
+        assert!(debug_svg(&world).contains("<polyline"));
+
+        // primitives of the previous tick expire
+        world.write_resource::<DebugDraw>().text(
+            DebugCategory::Other,
+            p2(0.0, 0.0),
+            "tick",
+            ggez::graphics::WHITE,
+        );
+        dispatcher.dispatch(&world);
+        assert!(get_debug_primitives(&world)
+            .iter()
+            .all(|p| p.category == DebugCategory::Forces));
+
+        world
+            .write_resource::<DebugDraw>()
+            .set_enabled(DebugCategory::Forces, false);
+        dispatcher.dispatch(&world);
+        assert!(get_debug_primitives(&world).is_empty());
+    }
+}
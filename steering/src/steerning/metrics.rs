@@ -0,0 +1,175 @@
+use super::components::*;
+
+use commons::math::*;
+use serde::Serialize;
+use specs::prelude::*;
+use specs::{World, WorldExt};
+use std::io::Write;
+
+/// Snapshot of the steering world used to tune parameters without rendering
+#[derive(Clone, Debug, Serialize)]
+pub struct TickMetrics {
+    pub tick: u64,
+    pub average_speed: f32,
+    /// vehicles with enabled arrival that reached the target
+    pub arrived: usize,
+    /// smallest distance between the borders of two vehicles, negative when overlapping. None
+    /// when there are less than two vehicles
+    pub min_separation: Option<f32>,
+    /// vehicles which body is crossing a wall segment
+    pub wall_penetrations: usize,
+}
+
+pub fn compute_metrics(tick: u64, world: &World) -> TickMetrics {
+    let vehicles = world.read_storage::<Vehicle>();
+    let arrivals = world.read_storage::<SteeringArrival>();
+    let walls = world.read_storage::<Wall>();
+    let bounds = world.read_resource::<Cfg>().get_bounds();
+
+    let bodies: Vec<(P2, f32, f32)> = (&vehicles)
+        .join()
+        .map(|vehicle| (vehicle.pos, vehicle.radius, vehicle.speed))
+        .collect();
+
+    let average_speed = if bodies.is_empty() {
+        0.0
+    } else {
+        bodies.iter().map(|(_, _, speed)| speed).sum::<f32>() / bodies.len() as f32
+    };
+
+    let arrived = (&arrivals)
+        .join()
+        .filter(|arrival| arrival.enabled && arrival.arrived)
+        .count();
+
+    let mut min_separation: Option<f32> = None;
+    for (i, (pos_a, size_a, _)) in bodies.iter().enumerate() {
+        for (pos_b, size_b, _) in bodies.iter().skip(i + 1) {
+            let distance = bounds.distance(*pos_a, *pos_b) - size_a - size_b;
+            min_separation = Some(match min_separation {
+                Some(current) => current.min(distance),
+                None => distance,
+            });
+        }
+    }
+
+    let mut wall_penetrations = 0;
+    for (pos, size, _) in &bodies {
+        let penetrating = (&walls)
+            .join()
+            .any(|wall| (wall.closest_point(*pos) - pos).magnitude() < *size);
+
+        if penetrating {
+            wall_penetrations += 1;
+        }
+    }
+
+    TickMetrics {
+        tick,
+        average_speed,
+        arrived,
+        min_separation,
+        wall_penetrations,
+    }
+}
+
+pub fn write_metrics_csv<W: Write>(writer: &mut W, metrics: &[TickMetrics]) -> std::io::Result<()> {
+    writeln!(
+        writer,
+        "tick,average_speed,arrived,min_separation,wall_penetrations"
+    )?;
+
+    for m in metrics {
+        let min_separation = m
+            .min_separation
+            .map(|value| value.to_string())
+            .unwrap_or_default();
+
+        writeln!(
+            writer,
+            "{},{},{},{},{}",
+            m.tick, m.average_speed, m.arrived, min_separation, m.wall_penetrations
+        )?;
+    }
+
+    Ok(())
+}
+
+pub fn write_metrics_json<W: Write>(
+    writer: &mut W,
+    metrics: &[TickMetrics],
+) -> std::io::Result<()> {
+    serde_json::to_writer_pretty(&mut *writer, metrics)?;
+    writeln!(writer)
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::scenario::*;
+    use super::super::*;
+    use super::*;
+
+    fn create_test_world() -> World {
+        let scenario: Scenario = serde_json::from_str(
+            r#"{
+                "walls": [{ "points": [[0, 0], [100, 0]], "distance": 10, "force": 100 }],
+                "vehicles": [
+                    { "pos": [10, 50], "radius": 2, "color": [1, 1, 1, 1], "steering": { "arrival": {} } },
+                    { "pos": [20, 50], "radius": 2, "color": [1, 1, 1, 1], "steering": { "arrival": {} } },
+                    { "pos": [50, 1], "radius": 2, "color": [1, 1, 1, 1] }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let mut world = create_world(Default::default()).unwrap();
+        build_scenario(&mut world, &scenario).unwrap();
+        world
+    }
+
+    #[test]
+    fn test_compute_metrics() {
+        let world = create_test_world();
+        {
+            let mut vehicles = world.write_storage::<Vehicle>();
+            for (i, vehicle) in (&mut vehicles).join().enumerate() {
+                vehicle.speed = i as f32 * 3.0;
+            }
+
+            let mut arrivals = world.write_storage::<SteeringArrival>();
+            let arrival = (&mut arrivals).join().next().unwrap();
+            arrival.enabled = true;
+            arrival.arrived = true;
+        }
+
+        let metrics = compute_metrics(5, &world);
+        assert_eq!(metrics.tick, 5);
+        assert_eq!(metrics.average_speed, 3.0);
+        assert_eq!(metrics.arrived, 1);
+        assert_eq!(metrics.min_separation, Some(6.0));
+        assert_eq!(metrics.wall_penetrations, 1);
+    }
+
+    #[test]
+    fn test_metrics_without_min_separation() {
+        let mut world = create_world(Default::default()).unwrap();
+        let scenario: Scenario = serde_json::from_str(
+            r#"{ "vehicles": [{ "pos": [10, 10], "radius": 2, "color": [1, 1, 1, 1] }] }"#,
+        )
+        .unwrap();
+        build_scenario(&mut world, &scenario).unwrap();
+
+        let metrics = vec![compute_metrics(0, &world)];
+        assert_eq!(metrics[0].min_separation, None);
+
+        let mut csv = vec![];
+        write_metrics_csv(&mut csv, &metrics).unwrap();
+        assert!(String::from_utf8(csv).unwrap().ends_with("\n0,0,0,,0\n"));
+
+        let mut json = vec![];
+        write_metrics_json(&mut json, &metrics).unwrap();
+        assert!(String::from_utf8(json)
+            .unwrap()
+            .contains("\"min_separation\": null"));
+    }
+}
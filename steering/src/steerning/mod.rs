@@ -1,246 +1,116 @@
+pub mod avoidance;
+pub mod bundle;
 pub mod components;
+pub mod config;
+pub mod debug;
+pub mod metrics;
+pub mod orders;
+pub mod prediction;
+pub mod scenario;
+pub mod simulation;
 mod systems;
 
+use bundle::*;
 use components::*;
+use debug::*;
+use orders::*;
+use scenario::*;
 use systems::*;
 
 use commons::math::*;
 
-use ggez::graphics::Color;
-use ggez::{GameError, GameResult};
-use nalgebra::Vector2;
-use rand::prelude::StdRng;
-use rand::{Rng, SeedableRng};
+use ggez::GameResult;
 use specs::prelude::*;
 use specs::{World, WorldExt};
 
-// #[derive(Debug, Clone)]
-// enum Error {
-//     Other(String),
-// }
-//
-// type Result<T> = std::result::Result<T, Error>;
+#[derive(Debug, Clone, PartialEq)]
+pub enum Error {
+    /// command issued without any entity
+    EmptySelection,
+    /// entity is not alive or is not a vehicle
+    InvalidEntity(Entity),
+    UnknownOrder(u32),
+}
 
-pub fn load_cfg() -> GameResult<Cfg> {
-    let reader =
-        std::io::BufReader::new(std::fs::File::open("steering/resources/config.json").unwrap());
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Error::EmptySelection => write!(f, "empty selection"),
+            Error::InvalidEntity(entity) => write!(f, "invalid entity {:?}", entity),
+            Error::UnknownOrder(id) => write!(f, "unknown move order {}", id),
+        }
+    }
+}
 
-    let cfg: serde_json::Result<Cfg> = serde_json::from_reader(reader);
+impl std::error::Error for Error {}
 
-    match cfg {
-        Ok(cfg) => Ok(cfg),
-        Err(e) => Err(GameError::ConfigError("invalid config file".to_string())),
-    }
+/// Load the default config file with environment overrides, see `config::CfgLoader`
+pub fn load_cfg() -> GameResult<Cfg> {
+    config::CfgLoader::new()
+        .path(config::DEFAULT_CFG_PATH)
+        .env(true)
+        .load()
 }
 
 pub fn create_world(cfg: Cfg) -> GameResult<World> {
-    // create world
     let mut world = World::new();
-    world.register::<Model>();
-    world.register::<Cfg>();
-    world.register::<Vehicle>();
-    world.register::<MovingArea>();
-    world.register::<SteeringArrival>();
-    world.register::<SteeringSeparation>();
-    world.register::<SteeringVelocity>();
-    world.register::<Wall>();
-    world.register::<SteeringFormationMember>();
-    world.register::<SteeringFormationLeader>();
-
-    world.insert(GameTime { delta_time: 0.01 });
-    world.insert(DebugStuff::new());
-    world.insert(cfg);
+    SteeringBundle::setup(&mut world, cfg);
 
     Ok(world)
 }
 
 pub fn initialize_world(world: &mut World) -> GameResult<()> {
-    let cfg = {
+    let scenario = {
         let cfg: &Cfg = &world.read_resource::<Cfg>();
-        cfg.clone()
+        Scenario::from_cfg(cfg)
     };
 
-    let mut rng: StdRng = SeedableRng::seed_from_u64(cfg.seed);
-
-    {
-        let (width, height) = (cfg.screen_width, cfg.screen_height);
-        let border = 80.0;
-        let points: Vec<P2> = vec![
-            p2(border, border),
-            p2(width - border, border),
-            p2(width - border, height - border),
-            p2(border, height - border),
-        ];
-
-        // world.insert(MovingArea {
-        //     polygons: vec![Polygon::try_new(points.clone()).unwrap()],
-        // });
-
-        let wall_width = 15.0;
-        let wall_force = 200.0;
-
-        for (p0, p1, distance, force) in vec![
-            (points[0], points[1], wall_width, v2(0.0, 1.0) * wall_force),
-            (points[1], points[2], wall_width, v2(-1.0, 0.0) * wall_force),
-            (points[2], points[3], wall_width, v2(0.0, -1.0) * wall_force),
-            (points[3], points[0], wall_width, v2(1.0, 0.0) * wall_force),
-        ] {
-            let p0 = p2(p0.x as f32, p0.y as f32);
-            let p1 = p2(p1.x as f32, p1.y as f32);
-
-            world
-                .create_entity()
-                .with(Wall::new_from_points(p0, p1, distance, force))
-                .build();
-        }
-    }
-
-    let max_acc = cfg.max_acc;
-    let max_speed = cfg.max_speed;
-    let separation_mut = cfg.separation_radius;
-
-    let mut formation_index = 0;
-
-    for i in 0..cfg.vehicles {
-        let pos = p2(
-            rng.gen_range(cfg.start_position[0], cfg.start_position[2]),
-            rng.gen_range(cfg.start_position[1], cfg.start_position[3]),
-        );
-
-        let vel = v2(
-            rng.gen_range(-max_speed, max_speed),
-            rng.gen_range(-max_speed, max_speed),
-        );
-
-        let radius = if rng.gen::<f32>() <= 0.1f32 { 6.0 } else { 3.0 };
-        let follow = i < cfg.followers;
-
-        let color = if follow {
-            if formation_index == 0 {
-                Color::new(0.0, 1.0, 1.0, 1.0)
-            } else {
-                Color::new(1.0, 1.0, 0.0, 1.0)
-            }
-        } else {
-            Color::new(1.0, 0.0, 0.0, 1.0)
-        };
-
-        let dir = Vector2::new(rng.gen(), rng.gen()).normalize();
-
-        let mut builder = world
-            .create_entity()
-            .with(Vehicle {
-                pos,
-                dir: dir,
-                desired_dir: dir,
-                vel_dir: Vector2::zeros(),
-                speed: 0.0,
-                max_acc: max_acc,
-                rotation_speed: deg2rad(cfg.rotation_speed),
-                desired_vel: Vector2::zeros(),
-                max_speed,
-            })
-            .with(Model {
-                size: radius,
-                pos: p2(0.0, 0.0),
-                dir,
-                color,
-            })
-            .with(SteeringArrival {
-                enabled: follow,
-                target_pos: p2(300.0, 300.0),
-                distance: cfg.arrival_distance,
-                weight: 1.0,
-                arrived: false,
-            })
-            .with(SteeringSeparation {
-                enabled: true,
-                distance: radius * separation_mut,
-                weight: 2.0,
-            })
-            .with(SteeringVelocity {
-                enabled: !follow,
-                vel: vel,
-                weight: 1.0,
-            });
-
-        if follow {
-            if formation_index == 0 {
-                builder = builder.with(SteeringFormationLeader {
-                    formation: FormationType::Line,
-                });
-            }
-
-            builder = builder.with(SteeringFormationMember {
-                index: formation_index,
-            });
-
-            formation_index += 1;
-        }
-
-        builder.build();
-    }
-
-    Ok(())
+    build_scenario(world, &scenario)
 }
 
-pub fn run(delta: f32, world: &mut World) {
+/// Advance the world by `delta` with a dispatcher from `create_dispatcher`, already set up
+pub fn run(delta: f32, world: &mut World, dispatcher: &mut Dispatcher) {
     world.insert(GameTime { delta_time: delta });
-
-    let mut dispatcher = DispatcherBuilder::new()
-        .with(SteerArrivalSystem, "steering_arrival", &[])
-        .with(SteeringSeparationSystem, "steering_separation", &[])
-        .with(SteeringVelocitySystem, "steering_velocity", &[])
-        .with(SteeringWallsSystem, "steering_walls", &[])
-        // .with(SteeringFormationSystem, "steering_formation", &[])
-        .with(
-            MoveSystem,
-            "move",
-            &[
-                "steering_arrival",
-                "steering_separation",
-                "steering_velocity",
-                "steering_walls",
-                // "steering_formation",
-            ],
-        )
-        .with(BordersTeleportSystem, "border_teleport", &["move"])
-        .with(UpdateModelPosSystem, "update_model", &["border_teleport"])
-        .build();
-
-    dispatcher.run_now(world);
+    dispatcher.dispatch(world);
+    world.maintain();
 }
 
-pub fn move_to(world: &mut World, target_pos: P2) -> GameResult<()> {
-    let mut arrivals = world.write_component::<SteeringArrival>();
-    let formations = world.read_component::<SteeringFormationMember>();
-    let leaders = world.read_component::<SteeringFormationLeader>();
-    let vehicles = world.read_component::<Vehicle>();
-
-    let mut total = formations.count();
-    let mut formation: Option<(P2, FormationType)> = None;
-
-    // get leader position
-    for (leader, vehicle) in (&leaders, &vehicles).join() {
-        let pos = vehicle.pos;
-        formation = Some((pos, leader.formation));
-    }
-
-    let (leader_pos, leader_formation) = formation.unwrap();
+/// Dispatcher with only the steering systems, see `bundle::SteeringBundle` to add others
+pub fn create_dispatcher<'a, 'b>() -> Dispatcher<'a, 'b> {
+    let mut builder = DispatcherBuilder::new();
+    SteeringBundle::add_systems(&mut builder, |_, _| {});
+    builder.build()
+}
 
-    let dir = (target_pos - leader_pos).normalize();
+/// Move the formation members to the target, see `orders::move_group`
+pub fn move_to(world: &mut World, target_pos: P2) -> Result<u32, Error> {
+    let selection: Vec<Entity> = {
+        let entities = world.entities();
+        let formations = world.read_storage::<SteeringFormationMember>();
+        (&entities, &formations)
+            .join()
+            .map(|(entity, _)| entity)
+            .collect()
+    };
 
-    // update target positions
-    for (formation, arrival) in (&formations, &mut arrivals).join() {
-        let index = formation.index;
-        let pos = leader_formation.get_pos(dir, target_pos, total, index);
-        arrival.target_pos = pos;
-    }
+    move_group(world, &selection, target_pos)
+}
 
-    Ok(())
+/// Debug primitives visible on current tick
+pub fn get_debug_primitives(world: &World) -> Vec<DebugPrimitive> {
+    world.read_resource::<DebugDraw>().get_primitives().to_vec()
 }
 
-pub fn take_debug_lines(world: &mut World) -> Vec<(P2, P2, Color)> {
-    let stuff = &mut world.write_resource::<DebugStuff>();
-    stuff.take_lines()
+/// Render the debug primitives of current tick as SVG with the world size
+pub fn debug_svg(world: &World) -> String {
+    let (width, height) = {
+        let cfg = world.read_resource::<Cfg>();
+        (cfg.world_width, cfg.world_height)
+    };
+
+    to_svg(
+        world.read_resource::<DebugDraw>().get_primitives(),
+        width,
+        height,
+    )
 }
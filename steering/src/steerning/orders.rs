@@ -0,0 +1,382 @@
+use super::components::*;
+use super::systems::MoveOrderSystem;
+use super::Error;
+
+use commons::math::*;
+use specs::prelude::*;
+use specs::{World, WorldExt};
+use std::collections::BTreeMap;
+
+/// spacing between arrival points, relative to the biggest vehicle diameter
+const SLOT_SPACING: f32 = 1.5;
+
+/// Target shared by a group of vehicles, members are the vehicles with a `SteeringMoveOrder`
+/// pointing to it. The order is complete when all members arrived, then their
+/// `SteeringMoveOrder` is removed and they hold position at their arrival points.
+#[derive(Clone, Debug)]
+pub struct MoveOrder {
+    pub target_pos: P2,
+    /// members that already got an arrival point, when it changes the points are recomputed
+    pub assigned: Vec<Entity>,
+    /// assigned members that reached their arrival point
+    pub arrived: Vec<Entity>,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct MoveOrders {
+    pub next_id: u32,
+    pub orders: BTreeMap<u32, MoveOrder>,
+}
+
+/// Member of a group used to compute its arrival point
+#[derive(Clone, Debug)]
+pub struct SlotMember {
+    pub entity: Entity,
+    pub pos: P2,
+    pub radius: f32,
+    pub formation_index: Option<usize>,
+    pub leader_formation: Option<FormationType>,
+}
+
+/// Create a new move order for the selection, return the order id.
+///
+/// Selected vehicles leave any previous order, stop to wander and arrive at spread out points
+/// around the target, or keep the formation if the leader is selected.
+pub fn move_group(world: &mut World, selection: &[Entity], target_pos: P2) -> Result<u32, Error> {
+    if selection.is_empty() {
+        return Err(Error::EmptySelection);
+    }
+
+    {
+        let entities = world.entities();
+        let vehicles = world.read_storage::<Vehicle>();
+        for &entity in selection {
+            if !entities.is_alive(entity) || !vehicles.contains(entity) {
+                return Err(Error::InvalidEntity(entity));
+            }
+        }
+    }
+
+    let id = {
+        let orders = &mut world.write_resource::<MoveOrders>();
+        let id = orders.next_id;
+        orders.next_id += 1;
+        orders.orders.insert(
+            id,
+            MoveOrder {
+                target_pos,
+                assigned: vec![],
+                arrived: vec![],
+            },
+        );
+        id
+    };
+
+    for &entity in selection {
+        join_move_order(world, id, entity)?;
+    }
+
+    update_move_orders(world);
+
+    Ok(id)
+}
+
+/// Add the vehicle into an existing order, leaving its previous one. The arrival points of all
+/// members are recomputed on next tick.
+pub fn join_move_order(world: &mut World, order_id: u32, entity: Entity) -> Result<(), Error> {
+    if !world
+        .read_resource::<MoveOrders>()
+        .orders
+        .contains_key(&order_id)
+    {
+        return Err(Error::UnknownOrder(order_id));
+    }
+
+    if !world.read_storage::<Vehicle>().contains(entity) {
+        return Err(Error::InvalidEntity(entity));
+    }
+
+    let cfg_arrival_distance = world.read_resource::<Cfg>().arrival_distance;
+
+    let mut move_orders = world.write_storage::<SteeringMoveOrder>();
+    let mut arrivals = world.write_storage::<SteeringArrival>();
+    let mut velocities = world.write_storage::<SteeringVelocity>();
+    let mut scripts = world.write_storage::<SteeringScript>();
+
+    move_orders
+        .insert(entity, SteeringMoveOrder { order: order_id })
+        .map_err(|_| Error::InvalidEntity(entity))?;
+
+    if !arrivals.contains(entity) {
+        arrivals
+            .insert(
+                entity,
+                SteeringArrival {
+                    enabled: true,
+                    target_pos: P2::origin(),
+                    distance: cfg_arrival_distance,
+                    weight: 1.0,
+                    arrived: false,
+                },
+            )
+            .map_err(|_| Error::InvalidEntity(entity))?;
+    }
+
+    if let Some(velocity) = velocities.get_mut(entity) {
+        velocity.enabled = false;
+    }
+
+    // the order replaces any scripted path
+    scripts.remove(entity);
+
+    Ok(())
+}
+
+/// Recompute arrival points of orders which members changed and remove orders without members
+/// or with all members arrived, it is also done every tick by the `MoveOrderSystem`
+pub fn update_move_orders(world: &mut World) {
+    MoveOrderSystem.run_now(world);
+}
+
+/// Compute the arrival point of each member. Groups with a formation leader keep the
+/// formation facing the target, others are spread in rings around the target.
+pub fn compute_slots(target_pos: P2, members: &[SlotMember]) -> Vec<(Entity, P2)> {
+    if members.is_empty() {
+        return vec![];
+    }
+
+    let centroid = members
+        .iter()
+        .fold(V2::zeros(), |acc, member| acc + member.pos.coords)
+        / members.len() as f32;
+
+    let leader = members
+        .iter()
+        .find(|member| member.leader_formation.is_some());
+
+    if let Some(leader) = leader {
+        let formation = leader.leader_formation.unwrap();
+        let look_dir = direction_or_default(target_pos - leader.pos);
+
+        // formation members first, ordered by index, and everybody else after
+        let mut ordered: Vec<&SlotMember> = members.iter().collect();
+        ordered.sort_by_key(|member| member.formation_index.unwrap_or(usize::MAX));
+
+        let total = ordered.len();
+        return ordered
+            .into_iter()
+            .enumerate()
+            .map(|(index, member)| {
+                let pos = formation.get_pos(look_dir, target_pos, total, index);
+                (member.entity, pos)
+            })
+            .collect();
+    }
+
+    let max_radius = members
+        .iter()
+        .map(|member| member.radius)
+        .fold(0.0f32, f32::max);
+    let spacing = (max_radius * 2.0 * SLOT_SPACING).max(1.0);
+    let look_dir = direction_or_default(target_pos - P2::from(centroid));
+    let mut slots = ring_slots(target_pos, look_dir, spacing, members.len());
+
+    // closest members choose first to avoid crossing paths
+    let mut ordered: Vec<&SlotMember> = members.iter().collect();
+    ordered.sort_by(|a, b| {
+        let da = (a.pos - target_pos).magnitude_squared();
+        let db = (b.pos - target_pos).magnitude_squared();
+        da.partial_cmp(&db).unwrap_or(std::cmp::Ordering::Equal)
+    });
+
+    ordered
+        .into_iter()
+        .map(|member| {
+            let (index, _) = slots
+                .iter()
+                .enumerate()
+                .min_by(|(_, a), (_, b)| {
+                    let da = (**a - member.pos).magnitude_squared();
+                    let db = (**b - member.pos).magnitude_squared();
+                    da.partial_cmp(&db).unwrap_or(std::cmp::Ordering::Equal)
+                })
+                .unwrap();
+
+            (member.entity, slots.swap_remove(index))
+        })
+        .collect()
+}
+
+fn direction_or_default(vector: V2) -> V2 {
+    let length = vector.magnitude();
+    if length > 0.001 {
+        vector / length
+    } else {
+        v2(1.0, 0.0)
+    }
+}
+
+/// Points in concentric rings, first one at the center
+fn ring_slots(center: P2, look_dir: V2, spacing: f32, count: usize) -> Vec<P2> {
+    let mut slots = vec![center];
+    let mut ring = 1;
+
+    while slots.len() < count {
+        let radius = spacing * ring as f32;
+        let ring_count = ((TWO_PI * radius) / spacing).floor().max(1.0) as usize;
+
+        for i in 0..ring_count.min(count - slots.len()) {
+            let angle = angle_vector(look_dir) + TWO_PI * i as f32 / ring_count as f32;
+            slots.push(center + v2(angle.cos(), angle.sin()) * radius);
+        }
+
+        ring += 1;
+    }
+
+    slots
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::*;
+    use super::*;
+
+    fn cfg() -> Cfg {
+        Cfg {
+            vehicles: 10,
+            followers: 0,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_move_group_without_leader_spread_arrival_points() {
+        let mut world = create_world(cfg()).unwrap();
+        initialize_world(&mut world).unwrap();
+
+        let selection: Vec<Entity> = {
+            let entities = world.entities();
+            let vehicles = world.read_storage::<Vehicle>();
+            (&*entities, &vehicles)
+                .join()
+                .map(|(e, _)| e)
+                .take(5)
+                .collect()
+        };
+
+        let target = p2(400.0, 300.0);
+        move_group(&mut world, &selection, target).unwrap();
+
+        let arrivals = world.read_storage::<SteeringArrival>();
+        let points: Vec<P2> = selection
+            .iter()
+            .map(|e| arrivals.get(*e).unwrap().target_pos)
+            .collect();
+
+        for (i, a) in points.iter().enumerate() {
+            for b in points.iter().skip(i + 1) {
+                assert!((b - a).magnitude() > 1.0, "{:?} {:?}", a, b);
+            }
+            assert!((a - target).magnitude() < 50.0);
+        }
+
+        let velocities = world.read_storage::<SteeringVelocity>();
+        assert!(selection
+            .iter()
+            .all(|e| !velocities.get(*e).unwrap().enabled));
+    }
+
+    #[test]
+    fn test_move_group_recompute_points_when_member_join() {
+        let mut world = create_world(cfg()).unwrap();
+        initialize_world(&mut world).unwrap();
+
+        let all: Vec<Entity> = {
+            let entities = world.entities();
+            let vehicles = world.read_storage::<Vehicle>();
+            (&*entities, &vehicles).join().map(|(e, _)| e).collect()
+        };
+
+        let target = p2(400.0, 300.0);
+        let order = move_group(&mut world, &all[0..1], target).unwrap();
+        assert_eq!(
+            world
+                .read_storage::<SteeringArrival>()
+                .get(all[0])
+                .unwrap()
+                .target_pos,
+            target
+        );
+
+        join_move_order(&mut world, order, all[1]).unwrap();
+        update_move_orders(&mut world);
+
+        let arrivals = world.read_storage::<SteeringArrival>();
+        let a = arrivals.get(all[0]).unwrap().target_pos;
+        let b = arrivals.get(all[1]).unwrap().target_pos;
+        assert!((a - b).magnitude() > 1.0);
+    }
+
+    #[test]
+    fn test_move_order_complete_when_all_members_arrive() {
+        let mut world = create_world(cfg()).unwrap();
+        initialize_world(&mut world).unwrap();
+
+        let selection: Vec<Entity> = {
+            let entities = world.entities();
+            let vehicles = world.read_storage::<Vehicle>();
+            (&*entities, &vehicles)
+                .join()
+                .map(|(e, _)| e)
+                .take(3)
+                .collect()
+        };
+
+        let order = move_group(&mut world, &selection, p2(400.0, 300.0)).unwrap();
+
+        let mut dispatcher = create_dispatcher();
+        dispatcher.setup(&mut world);
+        for _ in 0..(60 * 60) {
+            if !world
+                .read_resource::<MoveOrders>()
+                .orders
+                .contains_key(&order)
+            {
+                break;
+            }
+            run(1.0 / 60.0, &mut world, &mut dispatcher);
+        }
+
+        assert!(world.read_resource::<MoveOrders>().orders.is_empty());
+        let move_orders = world.read_storage::<SteeringMoveOrder>();
+        assert!(selection.iter().all(|e| !move_orders.contains(*e)));
+    }
+
+    #[test]
+    fn test_move_group_errors() {
+        let mut world = create_world(cfg()).unwrap();
+        initialize_world(&mut world).unwrap();
+
+        assert_eq!(
+            move_group(&mut world, &[], p2(0.0, 0.0)),
+            Err(Error::EmptySelection)
+        );
+
+        let wall = {
+            let entities = world.entities();
+            let walls = world.read_storage::<Wall>();
+            (&*entities, &walls).join().map(|(e, _)| e).next().unwrap()
+        };
+
+        assert_eq!(
+            move_group(&mut world, &[wall], p2(0.0, 0.0)),
+            Err(Error::InvalidEntity(wall))
+        );
+
+        // there is no formation leader
+        assert_eq!(
+            move_to(&mut world, p2(0.0, 0.0)),
+            Err(Error::EmptySelection)
+        );
+    }
+}
@@ -0,0 +1,299 @@
+//! Predict the movement of a vehicle by simulating its own steering forward in time
+
+use super::components::*;
+use super::systems::*;
+use super::Error;
+
+use commons::math::*;
+use rand::prelude::StdRng;
+use rand::SeedableRng;
+use specs::prelude::*;
+use specs::{World, WorldExt};
+
+/// smallest step accepted, avoid to simulate forever
+const MIN_STEP: f32 = 0.001;
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrajectoryPoint {
+    /// seconds from now
+    pub time: f32,
+    pub pos: P2,
+    pub vel: V2,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Trajectory {
+    pub points: Vec<TrajectoryPoint>,
+    /// seconds until reach the final arrival target, none when it is not reached during the
+    /// prediction or the vehicle has no final target
+    pub arrival_time: Option<f32>,
+}
+
+/// Predict the vehicle movement for the next `duration` seconds in steps of `step` seconds.
+///
+/// A copy of the vehicle steering components is simulated with the same functions used by the
+/// systems, the `World` is not changed. Walls are considered, the world boundary is ignored.
+/// Other vehicles are not simulated, so separation and avoidance are ignored. Flocking steers to
+/// the squad and fleet centers as they are now, moving at their current velocity. The dithering
+/// blend is replaced by the prioritized truncated sum to keep the prediction deterministic.
+pub fn predict_trajectory(
+    world: &World,
+    entity: Entity,
+    duration: f32,
+    step: f32,
+) -> Result<Trajectory, Error> {
+    let cfg = world.read_resource::<Cfg>();
+    let bounds = cfg.get_bounds();
+    let vehicles = world.read_storage::<Vehicle>();
+    let walls = world.read_storage::<Wall>();
+
+    let mut vehicle = vehicles
+        .get(entity)
+        .cloned()
+        .ok_or(Error::InvalidEntity(entity))?;
+    let mut physics = world.read_storage::<VehiclePhysics>().get(entity).cloned();
+    let mut arrival = world.read_storage::<SteeringArrival>().get(entity).cloned();
+    let mut script = world.read_storage::<SteeringScript>().get(entity).cloned();
+    let velocity = world
+        .read_storage::<SteeringVelocity>()
+        .get(entity)
+        .cloned();
+    let flock_target = flock_target(world, entity, &bounds);
+
+    let blend_mode = match cfg.blend_mode {
+        BlendMode::PrioritizedDithering => BlendMode::PrioritizedTruncatedSum,
+        other => other,
+    };
+    let mut rng: StdRng = SeedableRng::seed_from_u64(0);
+
+    let destination = final_target(arrival.as_ref(), script.as_ref());
+    let arrival_tolerance = vehicle.radius.max(1.0);
+
+    let step = step.max(MIN_STEP);
+    let steps = (duration / step).ceil() as usize;
+
+    let mut trajectory = Trajectory::default();
+
+    for i in 1..=steps {
+        let time = i as f32 * step;
+
+        if let (Some(script), Some(arrival)) = (&mut script, &mut arrival) {
+            update_script(&vehicle, script, arrival, &bounds);
+        }
+
+        if let Some(arrival) = &mut arrival {
+            steer_arrival(&mut vehicle, arrival, &bounds);
+        }
+
+        if let Some(velocity) = &velocity {
+            steer_velocity(&mut vehicle, velocity);
+        }
+
+        if let Some(target) = &flock_target {
+            steer_flock(&mut vehicle, &target.advance(time - step), &bounds);
+        }
+
+        steer_walls(&mut vehicle, (&walls).join());
+
+        let mut forces = std::mem::replace(&mut vehicle.forces, vec![]);
+        forces.sort_by_key(|force| force.behaviour.priority());
+        let (desired_vel, _) = blend_forces(blend_mode, &forces, vehicle.max_acc, &mut rng);
+        vehicle.desired_vel += desired_vel;
+
+        integrate(&mut vehicle, physics.as_mut(), (&walls).join(), step);
+
+        trajectory.points.push(TrajectoryPoint {
+            time,
+            pos: vehicle.pos,
+            vel: vehicle.get_velocity(),
+        });
+
+        if trajectory.arrival_time.is_none() {
+            let reached = destination
+                .map(|target| bounds.distance(vehicle.pos, target) <= arrival_tolerance)
+                .unwrap_or(false);
+
+            if reached {
+                trajectory.arrival_time = Some(time);
+            }
+        }
+    }
+
+    Ok(trajectory)
+}
+
+/// Flocking target of the vehicle squad, computed from all vehicles of the world
+fn flock_target(world: &World, entity: Entity, bounds: &WorldBounds) -> Option<FlockTarget> {
+    let vehicles = world.read_storage::<Vehicle>();
+    let flocks = world.read_storage::<SteeringFlock>();
+    let leaders = world.read_storage::<SteeringFormationLeader>();
+    let squad = flocks.get(entity)?.squad;
+
+    let members: Vec<FlockMember> = (&vehicles, &flocks, leaders.maybe())
+        .join()
+        .map(|(vehicle, flock, leader)| FlockMember::new(vehicle, flock.squad, leader.is_some()))
+        .collect();
+
+    let targets = compute_flock_targets(
+        bounds,
+        &members,
+        &world.read_storage::<Squad>(),
+        &world.read_storage::<Fleet>(),
+    );
+    targets.get(&squad).cloned()
+}
+
+/// Last target of a non looping script, or the arrival target
+fn final_target(arrival: Option<&SteeringArrival>, script: Option<&SteeringScript>) -> Option<P2> {
+    match (arrival, script) {
+        (_, Some(script)) if script.looping => None,
+        (_, Some(script)) => script.targets.last().cloned(),
+        (Some(arrival), None) if arrival.enabled => Some(arrival.target_pos),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::scenario::*;
+    use super::super::*;
+    use super::*;
+
+    fn world_with_vehicle(def: VehicleDef) -> (World, Entity) {
+        let scenario = Scenario {
+            vehicles: vec![def],
+            ..Default::default()
+        };
+
+        let cfg = Cfg {
+            boundary_mode: BoundaryMode::None,
+            ..Default::default()
+        };
+        let mut world = create_world(cfg).unwrap();
+        build_scenario(&mut world, &scenario).unwrap();
+
+        let entity = {
+            let entities = world.entities();
+            let vehicles = world.read_storage::<Vehicle>();
+            (&entities, &vehicles).join().next().unwrap().0
+        };
+
+        (world, entity)
+    }
+
+    fn arriving_vehicle(target: [f32; 2]) -> VehicleDef {
+        VehicleDef {
+            pos: [100.0, 100.0],
+            dir: Some([1.0, 0.0]),
+            archetype: None,
+            radius: Some(3.0),
+            color: Some([1.0, 1.0, 1.0, 1.0]),
+            steering: SteeringDef {
+                arrival: Some(ArrivalDef {
+                    enabled: true,
+                    target,
+                    distance: None,
+                    weight: 1.0,
+                }),
+                ..Default::default()
+            },
+            formation: None,
+            squad: None,
+            script: None,
+            physics: None,
+        }
+    }
+
+    #[test]
+    fn test_predict_trajectory_match_simulation() {
+        let (mut world, entity) = world_with_vehicle(arriving_vehicle([300.0, 100.0]));
+
+        let before = world.read_storage::<Vehicle>().get(entity).unwrap().clone();
+        let trajectory = predict_trajectory(&world, entity, 20.0, 0.1).unwrap();
+
+        // world is not changed
+        let after = world.read_storage::<Vehicle>().get(entity).unwrap().clone();
+        assert_eq!(before.pos, after.pos);
+        assert_eq!(before.speed, after.speed);
+
+        assert_eq!(trajectory.points.len(), 200);
+        let arrival_time = trajectory.arrival_time.unwrap();
+        assert!(arrival_time > 8.0 && arrival_time < 20.0, "{}", arrival_time);
+
+        // without other vehicles the prediction is exact
+        let mut dispatcher = create_dispatcher();
+        dispatcher.setup(&mut world);
+        world.insert(GameTime { delta_time: 0.1 });
+        for _ in 0..30 {
+            dispatcher.dispatch(&world);
+        }
+
+        let pos = world.read_storage::<Vehicle>().get(entity).unwrap().pos;
+        assert!((trajectory.points[29].pos - pos).magnitude() < 0.001);
+    }
+
+    #[test]
+    fn test_predict_trajectory_with_flocking() {
+        let scenario: Scenario = serde_json::from_str(
+            r#"{
+                "squads": [{ "name": "squad", "cohesion": 0.5 }],
+                "vehicles": [
+                    { "pos": [100, 100], "dir": [1, 0], "radius": 3, "color": [1, 1, 1, 1], "squad": "squad" },
+                    { "pos": [200, 100], "dir": [-1, 0], "radius": 3, "color": [1, 1, 1, 1], "squad": "squad" }
+                ]
+            }"#,
+        )
+        .unwrap();
+
+        let cfg = Cfg {
+            boundary_mode: BoundaryMode::None,
+            ..Default::default()
+        };
+        let mut world = create_world(cfg).unwrap();
+        build_scenario(&mut world, &scenario).unwrap();
+        let entity = {
+            let entities = world.entities();
+            let vehicles = world.read_storage::<Vehicle>();
+            (&entities, &vehicles).join().next().unwrap().0
+        };
+
+        let trajectory = predict_trajectory(&world, entity, 1.0, 0.1).unwrap();
+        assert!(trajectory.points[9].pos.x > 101.0);
+
+        // both vehicles move to the squad center, which stays in place
+        let mut dispatcher = create_dispatcher();
+        dispatcher.setup(&mut world);
+        world.insert(GameTime { delta_time: 0.1 });
+        for _ in 0..10 {
+            dispatcher.dispatch(&world);
+        }
+
+        let pos = world.read_storage::<Vehicle>().get(entity).unwrap().pos;
+        assert!((trajectory.points[9].pos - pos).magnitude() < 0.01);
+    }
+
+    #[test]
+    fn test_predict_trajectory_without_target() {
+        let mut def = arriving_vehicle([0.0, 0.0]);
+        def.steering = SteeringDef {
+            velocity: Some(VelocityDef {
+                enabled: true,
+                vel: Some([10.0, 0.0]),
+                weight: 1.0,
+            }),
+            ..Default::default()
+        };
+        let (world, entity) = world_with_vehicle(def);
+
+        let trajectory = predict_trajectory(&world, entity, 1.0, 0.25).unwrap();
+        assert_eq!(trajectory.points.len(), 4);
+        assert_eq!(trajectory.arrival_time, None);
+        assert!(trajectory.points[3].pos.x > 100.0);
+
+        let other = world.create_entity_unchecked().build();
+        assert_eq!(
+            predict_trajectory(&world, other, 1.0, 0.25).unwrap_err(),
+            Error::InvalidEntity(other)
+        );
+    }
+}
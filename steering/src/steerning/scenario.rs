@@ -0,0 +1,974 @@
+use super::components::*;
+
+use commons::math::*;
+use ggez::graphics::Color;
+use ggez::{GameError, GameResult};
+use nalgebra::Vector2;
+use rand::prelude::StdRng;
+use rand::{Rng, SeedableRng};
+use serde::{Deserialize, Serialize};
+use specs::prelude::*;
+use specs::{World, WorldExt};
+use std::collections::{BTreeMap, HashMap};
+use std::path::Path;
+
+/// Declarative description of a steering world: walls, vehicles and how they move.
+///
+/// Vehicle parameters not defined by the scenario, like `max_speed`, come from `Cfg`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Scenario {
+    /// added to the `Cfg::archetypes`, replacing the ones with same name
+    pub archetypes: BTreeMap<String, ArchetypeDef>,
+    pub walls: Vec<WallDef>,
+    pub formations: Vec<FormationDef>,
+    pub fleets: Vec<FleetDef>,
+    pub squads: Vec<SquadDef>,
+    pub vehicles: Vec<VehicleDef>,
+    pub spawns: Vec<SpawnDef>,
+}
+
+/// Polyline or polygon of walls, normals are computed from the points order and `side`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct WallDef {
+    pub points: Vec<[f32; 2]>,
+    /// connect the last point to the first one
+    #[serde(default)]
+    pub closed: bool,
+    #[serde(default)]
+    pub side: WallSide,
+    pub distance: f32,
+    pub force: f32,
+}
+
+impl WallDef {
+    pub fn build_walls(&self) -> GameResult<Vec<Wall>> {
+        let points: Vec<P2> = self.points.iter().cloned().map(to_p2).collect();
+        Wall::from_polyline(&points, self.closed, self.side, self.distance, self.force)
+            .map_err(GameError::ConfigError)
+    }
+}
+
+/// Named formation, vehicles join it by name and the first one become the leader
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FormationDef {
+    pub name: String,
+    pub formation: FormationType,
+}
+
+/// Named group of squads, see `Fleet`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct FleetDef {
+    pub name: String,
+    #[serde(default)]
+    pub cohesion: f32,
+    #[serde(default)]
+    pub alignment: f32,
+    #[serde(default = "default_fleet_formation")]
+    pub formation: FormationType,
+}
+
+/// Named group of vehicles, vehicles join it by name. See `Squad`
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SquadDef {
+    pub name: String,
+    #[serde(default)]
+    pub fleet: Option<String>,
+    #[serde(default)]
+    pub cohesion: f32,
+    #[serde(default)]
+    pub alignment: f32,
+    #[serde(default)]
+    pub formation_index: Option<usize>,
+    #[serde(default = "default_one")]
+    pub formation_weight: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VehicleDef {
+    pub pos: [f32; 2],
+    /// random when not defined
+    #[serde(default)]
+    pub dir: Option<[f32; 2]>,
+    #[serde(default)]
+    pub archetype: Option<String>,
+    /// required when not defined by the archetype
+    #[serde(default)]
+    pub radius: Option<f32>,
+    /// required when not defined by the archetype
+    #[serde(default)]
+    pub color: Option<[f32; 4]>,
+    /// behaviours not defined come from the archetype
+    #[serde(default)]
+    pub steering: SteeringDef,
+    #[serde(default)]
+    pub formation: Option<String>,
+    #[serde(default)]
+    pub squad: Option<String>,
+    #[serde(default)]
+    pub physics: Option<PhysicsDef>,
+    #[serde(default)]
+    pub script: Option<ScriptDef>,
+}
+
+/// Group of vehicles created in random positions inside of an area
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SpawnDef {
+    pub count: usize,
+    /// min x, min y, max x, max y
+    pub area: [f32; 4],
+    #[serde(default)]
+    pub archetype: Option<String>,
+    /// required when not defined by the archetype
+    #[serde(default)]
+    pub radius: Option<f32>,
+    /// chance of a vehicle be created with `big_radius`
+    #[serde(default)]
+    pub big_chance: f32,
+    #[serde(default)]
+    pub big_radius: f32,
+    /// required when not defined by the archetype
+    #[serde(default)]
+    pub color: Option<[f32; 4]>,
+    /// color of the formation leader, if any
+    #[serde(default)]
+    pub leader_color: Option<[f32; 4]>,
+    #[serde(default)]
+    pub steering: SteeringDef,
+    #[serde(default)]
+    pub formation: Option<String>,
+    #[serde(default)]
+    pub squad: Option<String>,
+    #[serde(default)]
+    pub physics: Option<PhysicsDef>,
+    #[serde(default)]
+    pub script: Option<ScriptDef>,
+}
+
+/// Steering components of a vehicle, missing behaviours are not added
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SteeringDef {
+    pub arrival: Option<ArrivalDef>,
+    pub separation: Option<SeparationDef>,
+    pub velocity: Option<VelocityDef>,
+    pub avoidance: Option<AvoidanceDef>,
+}
+
+impl SteeringDef {
+    /// Behaviours of self, or of `other` when not defined
+    pub fn or(&self, other: &SteeringDef) -> SteeringDef {
+        SteeringDef {
+            arrival: self.arrival.clone().or_else(|| other.arrival.clone()),
+            separation: self.separation.clone().or_else(|| other.separation.clone()),
+            velocity: self.velocity.clone().or_else(|| other.velocity.clone()),
+            avoidance: self.avoidance.clone().or_else(|| other.avoidance.clone()),
+        }
+    }
+}
+
+/// Named kind of vehicle shared by many vehicle and spawn definitions, like a fast scout or
+/// a slow freighter. Parameters not defined come from `Cfg`.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ArchetypeDef {
+    pub max_acc: Option<f32>,
+    pub max_speed: Option<f32>,
+    /// degrees per second
+    pub rotation_speed: Option<f32>,
+    pub radius: Option<f32>,
+    pub color: Option<[f32; 4]>,
+    pub steering: SteeringDef,
+    pub physics: Option<PhysicsDef>,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ArrivalDef {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default)]
+    pub target: [f32; 2],
+    /// `Cfg::arrival_distance` when not defined
+    #[serde(default)]
+    pub distance: Option<f32>,
+    #[serde(default = "default_one")]
+    pub weight: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SeparationDef {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// multiplier of the vehicle radius, `Cfg::separation_radius` when not defined
+    #[serde(default)]
+    pub radius: Option<f32>,
+    #[serde(default = "default_one")]
+    pub weight: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct VelocityDef {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    /// random velocity up to max speed when not defined
+    #[serde(default)]
+    pub vel: Option<[f32; 2]>,
+    #[serde(default = "default_one")]
+    pub weight: f32,
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct AvoidanceDef {
+    #[serde(default = "default_true")]
+    pub enabled: bool,
+    #[serde(default = "default_time_horizon")]
+    pub time_horizon: f32,
+}
+
+/// Physical model of the vehicle, see `VehiclePhysics`. Angles are in degrees.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct PhysicsDef {
+    pub mass: f32,
+    pub thrust: f32,
+    pub reverse_thrust: f32,
+    pub lateral_thrust: f32,
+    pub linear_drag: f32,
+    pub angular_drag: f32,
+    pub angular_acc: f32,
+    pub burn_angle: f32,
+}
+
+impl Default for PhysicsDef {
+    fn default() -> Self {
+        PhysicsDef {
+            mass: 1.0,
+            thrust: 100.0,
+            reverse_thrust: 30.0,
+            lateral_thrust: 20.0,
+            linear_drag: 0.1,
+            angular_drag: 2.0,
+            angular_acc: 720.0,
+            burn_angle: 30.0,
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct ScriptDef {
+    pub targets: Vec<[f32; 2]>,
+    #[serde(default)]
+    pub looping: bool,
+    #[serde(default = "default_script_distance")]
+    pub distance: f32,
+}
+
+fn default_true() -> bool {
+    true
+}
+
+fn default_one() -> f32 {
+    1.0
+}
+
+fn default_script_distance() -> f32 {
+    5.0
+}
+
+fn default_time_horizon() -> f32 {
+    2.0
+}
+
+fn default_fleet_formation() -> FormationType {
+    FormationType::Line
+}
+
+fn to_p2(value: [f32; 2]) -> P2 {
+    p2(value[0], value[1])
+}
+
+fn to_v2(value: [f32; 2]) -> V2 {
+    v2(value[0], value[1])
+}
+
+fn to_color(value: [f32; 4]) -> Color {
+    Color::new(value[0], value[1], value[2], value[3])
+}
+
+impl Scenario {
+    /// Rectangular walls around the screen and `Cfg::vehicles` wandering vehicles, where the
+    /// first `Cfg::followers` are in a line formation.
+    pub fn from_cfg(cfg: &Cfg) -> Self {
+        let (width, height) = (cfg.world_width, cfg.world_height);
+        let border = 80.0;
+        let points: Vec<[f32; 2]> = vec![
+            [border, border],
+            [width - border, border],
+            [width - border, height - border],
+            [border, height - border],
+        ];
+
+        let wall_width = 15.0;
+        let wall_force = 200.0;
+
+        let walls = vec![WallDef {
+            points,
+            closed: true,
+            side: WallSide::Inside,
+            distance: wall_width,
+            force: wall_force,
+        }];
+
+        let spawn = |count: usize, follow: bool| SpawnDef {
+            count,
+            area: cfg.start_position,
+            archetype: None,
+            radius: Some(3.0),
+            big_chance: 0.1,
+            big_radius: 6.0,
+            color: Some(if follow {
+                [1.0, 1.0, 0.0, 1.0]
+            } else {
+                [1.0, 0.0, 0.0, 1.0]
+            }),
+            leader_color: if follow {
+                Some([0.0, 1.0, 1.0, 1.0])
+            } else {
+                None
+            },
+            steering: SteeringDef {
+                arrival: Some(ArrivalDef {
+                    enabled: follow,
+                    target: [300.0, 300.0],
+                    distance: None,
+                    weight: 1.0,
+                }),
+                separation: Some(SeparationDef {
+                    enabled: true,
+                    radius: None,
+                    weight: 2.0,
+                }),
+                velocity: Some(VelocityDef {
+                    enabled: !follow,
+                    vel: None,
+                    weight: 1.0,
+                }),
+                avoidance: None,
+            },
+            formation: if follow {
+                Some("followers".to_string())
+            } else {
+                None
+            },
+            squad: None,
+            script: None,
+            physics: None,
+        };
+
+        let followers = cfg.followers.min(cfg.vehicles);
+
+        Scenario {
+            archetypes: Default::default(),
+            walls,
+            formations: vec![FormationDef {
+                name: "followers".to_string(),
+                formation: FormationType::Line,
+            }],
+            fleets: vec![],
+            squads: vec![],
+            vehicles: vec![],
+            spawns: vec![
+                spawn(followers, true),
+                spawn(cfg.vehicles - followers, false),
+            ],
+        }
+    }
+}
+
+pub fn load_scenario<P: AsRef<Path>>(path: P) -> GameResult<Scenario> {
+    let path = path.as_ref();
+    let content = std::fs::read_to_string(path).map_err(|e| {
+        GameError::ConfigError(format!(
+            "fail to read scenario file {}: {}",
+            path.display(),
+            e
+        ))
+    })?;
+
+    serde_json::from_str(&content).map_err(|e| {
+        GameError::ConfigError(format!("invalid scenario file {}: {}", path.display(), e))
+    })
+}
+
+/// Parameters of a vehicle definition merged with its archetype and `Cfg`
+struct VehicleParams {
+    radius: f32,
+    color: Color,
+    max_acc: f32,
+    max_speed: f32,
+    /// degrees per second
+    rotation_speed: f32,
+    steering: SteeringDef,
+    physics: Option<PhysicsDef>,
+}
+
+impl VehicleParams {
+    fn resolve(
+        cfg: &Cfg,
+        archetypes: &BTreeMap<String, ArchetypeDef>,
+        archetype: Option<&String>,
+        radius: Option<f32>,
+        color: Option<[f32; 4]>,
+        steering: &SteeringDef,
+        physics: Option<&PhysicsDef>,
+    ) -> GameResult<Self> {
+        let default_archetype = ArchetypeDef::default();
+        let archetype = match archetype {
+            Some(name) => archetypes
+                .get(name)
+                .ok_or_else(|| GameError::ConfigError(format!("unknown archetype '{}'", name)))?,
+            None => &default_archetype,
+        };
+
+        let radius = radius
+            .or(archetype.radius)
+            .ok_or_else(|| GameError::ConfigError("vehicle without radius".to_string()))?;
+
+        let color = color
+            .or(archetype.color)
+            .ok_or_else(|| GameError::ConfigError("vehicle without color".to_string()))?;
+
+        let max_speed = archetype.max_speed.unwrap_or(cfg.max_speed);
+        if max_speed < 0.0 {
+            return Err(GameError::ConfigError(format!(
+                "invalid negative max_speed {}",
+                max_speed
+            )));
+        }
+
+        Ok(VehicleParams {
+            radius,
+            color: to_color(color),
+            max_acc: archetype.max_acc.unwrap_or(cfg.max_acc),
+            max_speed,
+            rotation_speed: archetype.rotation_speed.unwrap_or(cfg.rotation_speed),
+            steering: steering.or(&archetype.steering),
+            physics: physics.or(archetype.physics.as_ref()).cloned(),
+        })
+    }
+}
+
+/// Vehicle ready to be created, with all random values already resolved
+struct VehicleSpawn<'s> {
+    pos: P2,
+    dir: V2,
+    /// random velocity used when the velocity steering don't define one
+    random_vel: V2,
+    radius: f32,
+    params: &'s VehicleParams,
+    leader_color: Option<Color>,
+    formation: Option<&'s String>,
+    squad: Option<Entity>,
+    script: Option<&'s ScriptDef>,
+}
+
+/// Create the scenario entities in the world, the `Cfg` resource must be already inserted
+pub fn build_scenario(world: &mut World, scenario: &Scenario) -> GameResult<()> {
+    let cfg = {
+        let cfg: &Cfg = &world.read_resource::<Cfg>();
+        cfg.clone()
+    };
+
+    let mut rng: StdRng = SeedableRng::seed_from_u64(cfg.seed);
+
+    for def in &scenario.walls {
+        for wall in def.build_walls()? {
+            world.create_entity().with(wall).build();
+        }
+    }
+
+    // formation type and next member index
+    let mut formations: HashMap<String, (FormationType, usize)> = scenario
+        .formations
+        .iter()
+        .map(|def| (def.name.clone(), (def.formation, 0)))
+        .collect();
+
+    let mut fleets: HashMap<String, Entity> = HashMap::new();
+    for def in &scenario.fleets {
+        let entity = world
+            .create_entity()
+            .with(Fleet {
+                cohesion: def.cohesion,
+                alignment: def.alignment,
+                formation: def.formation,
+            })
+            .build();
+        fleets.insert(def.name.clone(), entity);
+    }
+
+    let mut squads: HashMap<String, Entity> = HashMap::new();
+    for def in &scenario.squads {
+        let fleet = match &def.fleet {
+            Some(name) => Some(
+                *fleets
+                    .get(name)
+                    .ok_or_else(|| GameError::ConfigError(format!("unknown fleet '{}'", name)))?,
+            ),
+            None => None,
+        };
+
+        let entity = world
+            .create_entity()
+            .with(Squad {
+                fleet,
+                cohesion: def.cohesion,
+                alignment: def.alignment,
+                formation_index: def.formation_index,
+                formation_weight: def.formation_weight,
+            })
+            .build();
+        squads.insert(def.name.clone(), entity);
+    }
+
+    let find_squad = |name: Option<&String>| -> GameResult<Option<Entity>> {
+        match name {
+            Some(name) => squads
+                .get(name)
+                .cloned()
+                .map(Some)
+                .ok_or_else(|| GameError::ConfigError(format!("unknown squad '{}'", name))),
+            None => Ok(None),
+        }
+    };
+
+    let mut archetypes = cfg.archetypes.clone();
+    archetypes.extend(
+        scenario
+            .archetypes
+            .iter()
+            .map(|(name, def)| (name.clone(), def.clone())),
+    );
+
+    for def in &scenario.vehicles {
+        let params = VehicleParams::resolve(
+            &cfg,
+            &archetypes,
+            def.archetype.as_ref(),
+            def.radius,
+            def.color,
+            &def.steering,
+            def.physics.as_ref(),
+        )?;
+        let max_speed = params.max_speed;
+
+        let dir = match def.dir {
+            Some(dir) if to_v2(dir).magnitude() == 0.0 => {
+                return Err(GameError::ConfigError(format!(
+                    "invalid zero dir at {:?}",
+                    def.pos
+                )));
+            }
+            Some(dir) => to_v2(dir).normalize(),
+            None => Vector2::new(rng.gen(), rng.gen()).normalize(),
+        };
+
+        let random_vel = v2(
+            gen_range(&mut rng, -max_speed, max_speed),
+            gen_range(&mut rng, -max_speed, max_speed),
+        );
+
+        let spawn = VehicleSpawn {
+            pos: to_p2(def.pos),
+            dir,
+            random_vel,
+            radius: params.radius,
+            params: &params,
+            leader_color: None,
+            formation: def.formation.as_ref(),
+            squad: find_squad(def.squad.as_ref())?,
+            script: def.script.as_ref(),
+        };
+
+        create_vehicle(world, &cfg, &mut formations, spawn)?;
+    }
+
+    for def in &scenario.spawns {
+        let params = VehicleParams::resolve(
+            &cfg,
+            &archetypes,
+            def.archetype.as_ref(),
+            def.radius,
+            def.color,
+            &def.steering,
+            def.physics.as_ref(),
+        )?;
+        let max_speed = params.max_speed;
+
+        if def.area[2] < def.area[0] || def.area[3] < def.area[1] {
+            return Err(GameError::ConfigError(format!(
+                "invalid spawn area {:?}, expected min x, min y, max x, max y",
+                def.area
+            )));
+        }
+
+        for _ in 0..def.count {
+            let pos = p2(
+                gen_range(&mut rng, def.area[0], def.area[2]),
+                gen_range(&mut rng, def.area[1], def.area[3]),
+            );
+
+            let random_vel = v2(
+                gen_range(&mut rng, -max_speed, max_speed),
+                gen_range(&mut rng, -max_speed, max_speed),
+            );
+
+            let radius = if rng.gen::<f32>() <= def.big_chance {
+                def.big_radius
+            } else {
+                params.radius
+            };
+
+            let dir = Vector2::new(rng.gen(), rng.gen()).normalize();
+
+            let spawn = VehicleSpawn {
+                pos,
+                dir,
+                random_vel,
+                radius,
+                params: &params,
+                leader_color: def.leader_color.map(to_color),
+                formation: def.formation.as_ref(),
+                squad: find_squad(def.squad.as_ref())?,
+                script: def.script.as_ref(),
+            };
+
+            create_vehicle(world, &cfg, &mut formations, spawn)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Random value in `[low, high)`, or `low` when the range is empty
+fn gen_range(rng: &mut StdRng, low: f32, high: f32) -> f32 {
+    if low < high {
+        rng.gen_range(low, high)
+    } else {
+        low
+    }
+}
+
+fn create_vehicle(
+    world: &mut World,
+    cfg: &Cfg,
+    formations: &mut HashMap<String, (FormationType, usize)>,
+    spawn: VehicleSpawn,
+) -> GameResult<Entity> {
+    let formation = match spawn.formation {
+        Some(name) => match formations.get_mut(name) {
+            Some((formation, next_index)) => {
+                let index = *next_index;
+                *next_index += 1;
+                Some((*formation, index))
+            }
+            None => {
+                return Err(GameError::ConfigError(format!(
+                    "unknown formation '{}'",
+                    name
+                )))
+            }
+        },
+        None => None,
+    };
+
+    let color = match (formation, spawn.leader_color) {
+        (Some((_, 0)), Some(color)) => color,
+        _ => spawn.params.color,
+    };
+
+    let mut builder = world
+        .create_entity()
+        .with(Vehicle {
+            pos: spawn.pos,
+            dir: spawn.dir,
+            desired_dir: spawn.dir,
+            vel_dir: Vector2::zeros(),
+            speed: 0.0,
+            max_acc: spawn.params.max_acc,
+            rotation_speed: deg2rad(spawn.params.rotation_speed),
+            desired_vel: Vector2::zeros(),
+            max_speed: spawn.params.max_speed,
+            radius: spawn.radius,
+            forces: vec![],
+        })
+        .with(Model {
+            size: spawn.radius,
+            pos: p2(0.0, 0.0),
+            dir: spawn.dir,
+            color,
+        });
+
+    if let Some(arrival) = &spawn.params.steering.arrival {
+        builder = builder.with(SteeringArrival {
+            enabled: arrival.enabled,
+            target_pos: to_p2(arrival.target),
+            distance: arrival.distance.unwrap_or(cfg.arrival_distance),
+            weight: arrival.weight,
+            arrived: false,
+        });
+    }
+
+    if let Some(separation) = &spawn.params.steering.separation {
+        builder = builder.with(SteeringSeparation {
+            enabled: separation.enabled,
+            distance: spawn.radius * separation.radius.unwrap_or(cfg.separation_radius),
+            weight: separation.weight,
+        });
+    }
+
+    if let Some(velocity) = &spawn.params.steering.velocity {
+        builder = builder.with(SteeringVelocity {
+            enabled: velocity.enabled,
+            vel: velocity.vel.map(to_v2).unwrap_or(spawn.random_vel),
+            weight: velocity.weight,
+        });
+    }
+
+    if let Some(avoidance) = &spawn.params.steering.avoidance {
+        builder = builder.with(SteeringAvoidance {
+            enabled: avoidance.enabled,
+            time_horizon: avoidance.time_horizon,
+        });
+    }
+
+    if let Some(physics) = &spawn.params.physics {
+        builder = builder.with(VehiclePhysics {
+            mass: physics.mass,
+            thrust: physics.thrust,
+            reverse_thrust: physics.reverse_thrust,
+            lateral_thrust: physics.lateral_thrust,
+            linear_drag: physics.linear_drag,
+            angular_drag: physics.angular_drag,
+            angular_acc: deg2rad(physics.angular_acc),
+            burn_angle: deg2rad(physics.burn_angle),
+            angular_vel: 0.0,
+        });
+    }
+
+    if let Some(script) = spawn.script {
+        builder = builder.with(SteeringScript {
+            targets: script.targets.iter().cloned().map(to_p2).collect(),
+            index: 0,
+            looping: script.looping,
+            distance: script.distance,
+        });
+
+        // script require arrival to move
+        if spawn.params.steering.arrival.is_none() {
+            builder = builder.with(SteeringArrival {
+                enabled: true,
+                target_pos: spawn.pos,
+                distance: cfg.arrival_distance,
+                weight: 1.0,
+                arrived: false,
+            });
+        }
+    }
+
+    if let Some((formation, index)) = formation {
+        if index == 0 {
+            builder = builder.with(SteeringFormationLeader { formation });
+        }
+
+        builder = builder.with(SteeringFormationMember { index });
+    }
+
+    if let Some(squad) = spawn.squad {
+        builder = builder.with(SteeringFlock { squad });
+    }
+
+    Ok(builder.build())
+}
+
+#[cfg(test)]
+mod test {
+    use super::super::*;
+    use super::*;
+
+    #[test]
+    fn test_build_scenario_from_json() {
+        let scenario: Scenario = serde_json::from_str(
+            r#"{
+                "walls": [{ "points": [[0, 0], [100, 0]], "distance": 10, "force": 100 }],
+                "formations": [{ "name": "squad", "formation": "Line" }],
+                "vehicles": [{
+                    "pos": [50, 50],
+                    "radius": 3,
+                    "color": [1, 1, 1, 1],
+                    "script": { "targets": [[10, 10], [90, 10]], "looping": true },
+                    "physics": { "mass": 2, "burn_angle": 45 }
+                }],
+                "spawns": [{
+                    "count": 4,
+                    "area": [0, 0, 100, 100],
+                    "radius": 2,
+                    "color": [1, 0, 0, 1],
+                    "steering": { "arrival": {}, "separation": {} },
+                    "formation": "squad"
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let mut world = create_world(Default::default()).unwrap();
+        build_scenario(&mut world, &scenario).unwrap();
+
+        assert_eq!(world.read_storage::<Wall>().count(), 1);
+        assert_eq!(world.read_storage::<Vehicle>().count(), 5);
+        assert_eq!(world.read_storage::<SteeringScript>().count(), 1);
+        assert_eq!(world.read_storage::<SteeringArrival>().count(), 5);
+        assert_eq!(world.read_storage::<SteeringVelocity>().count(), 0);
+        assert_eq!(world.read_storage::<SteeringFormationMember>().count(), 4);
+        assert_eq!(world.read_storage::<SteeringFormationLeader>().count(), 1);
+        assert_eq!(world.read_storage::<VehiclePhysics>().count(), 1);
+    }
+
+    #[test]
+    fn test_load_bundled_scenarios() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/resources/scenarios/walls.json"
+        );
+        let scenario = load_scenario(path).unwrap();
+
+        let mut world = create_world(Default::default()).unwrap();
+        build_scenario(&mut world, &scenario).unwrap();
+        assert_eq!(world.read_storage::<SteeringFormationLeader>().count(), 1);
+    }
+
+    #[test]
+    fn test_build_scenario_fail_on_unknown_formation() {
+        let scenario = Scenario {
+            vehicles: vec![VehicleDef {
+                pos: [0.0, 0.0],
+                dir: None,
+                archetype: None,
+                radius: Some(1.0),
+                color: Some([1.0, 1.0, 1.0, 1.0]),
+                steering: Default::default(),
+                formation: Some("unknown".to_string()),
+                squad: None,
+                script: None,
+                physics: None,
+            }],
+            ..Default::default()
+        };
+
+        let mut world = create_world(Default::default()).unwrap();
+        assert!(build_scenario(&mut world, &scenario).is_err());
+    }
+
+    #[test]
+    fn test_build_scenario_with_archetypes() {
+        let cfg_path = concat!(env!("CARGO_MANIFEST_DIR"), "/resources/config.json");
+        let cfg = super::super::config::CfgLoader::new()
+            .path(cfg_path)
+            .load()
+            .unwrap();
+
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/resources/scenarios/fleet.json"
+        );
+        let scenario = load_scenario(path).unwrap();
+
+        let mut world = create_world(cfg).unwrap();
+        build_scenario(&mut world, &scenario).unwrap();
+
+        let vehicles = world.read_storage::<Vehicle>();
+        let freighters = (&vehicles).join().filter(|v| v.max_speed == 25.0).count();
+        let scouts = (&vehicles).join().filter(|v| v.max_speed == 90.0).count();
+        assert_eq!(freighters, 5);
+        assert_eq!(scouts, 60);
+        assert_eq!(world.read_storage::<VehiclePhysics>().count(), 5);
+
+        // vehicle color override the archetype one
+        let models = world.read_storage::<Model>();
+        assert!((&models)
+            .join()
+            .any(|model| model.color == Color::new(1.0, 0.5, 0.0, 1.0)));
+    }
+
+    #[test]
+    fn test_build_scenario_with_empty_random_ranges() {
+        let scenario: Scenario = serde_json::from_str(
+            r#"{
+                "vehicles": [{ "pos": [10, 10], "radius": 1, "color": [1, 1, 1, 1] }],
+                "spawns": [{ "count": 3, "area": [50, 0, 50, 0], "radius": 1, "color": [1, 1, 1, 1] }]
+            }"#,
+        )
+        .unwrap();
+
+        let cfg = Cfg {
+            max_speed: 0.0,
+            ..Default::default()
+        };
+        let mut world = create_world(cfg).unwrap();
+        build_scenario(&mut world, &scenario).unwrap();
+
+        let vehicles = world.read_storage::<Vehicle>();
+        assert_eq!(vehicles.count(), 4);
+        assert!((&vehicles).join().skip(1).all(|v| v.pos == p2(50.0, 0.0)));
+    }
+
+    #[test]
+    fn test_build_scenario_fail_on_invalid_ranges() {
+        let scenario: Scenario = serde_json::from_str(
+            r#"{ "spawns": [{ "count": 1, "area": [10, 0, 0, 10], "radius": 1, "color": [1, 1, 1, 1] }] }"#,
+        )
+        .unwrap();
+        let mut world = create_world(Default::default()).unwrap();
+        assert!(build_scenario(&mut world, &scenario).is_err());
+
+        let scenario: Scenario = serde_json::from_str(
+            r#"{ "vehicles": [{ "pos": [0, 0], "radius": 1, "color": [1, 1, 1, 1] }] }"#,
+        )
+        .unwrap();
+        let cfg = Cfg {
+            max_speed: -1.0,
+            ..Default::default()
+        };
+        let mut world = create_world(cfg).unwrap();
+        assert!(build_scenario(&mut world, &scenario).is_err());
+
+        let scenario: Scenario = serde_json::from_str(
+            r#"{ "vehicles": [{ "pos": [0, 0], "dir": [0, 0], "radius": 1, "color": [1, 1, 1, 1] }] }"#,
+        )
+        .unwrap();
+        let mut world = create_world(Default::default()).unwrap();
+        assert!(build_scenario(&mut world, &scenario).is_err());
+    }
+
+    #[test]
+    fn test_build_scenario_fail_on_unknown_archetype() {
+        let scenario: Scenario = serde_json::from_str(
+            r#"{ "spawns": [{ "count": 1, "area": [0, 0, 10, 10], "archetype": "unknown" }] }"#,
+        )
+        .unwrap();
+
+        let mut world = create_world(Default::default()).unwrap();
+        assert!(build_scenario(&mut world, &scenario).is_err());
+    }
+}
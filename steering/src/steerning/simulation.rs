@@ -0,0 +1,281 @@
+use super::components::*;
+use super::scenario::*;
+use super::*;
+
+use ggez::{GameError, GameResult};
+use specs::prelude::*;
+use specs::{World, WorldExt};
+
+/// Max number of fixed steps executed by a single update, avoid to spiral when the host
+/// can not keep up with the simulation
+const MAX_STEPS_PER_UPDATE: u32 = 10;
+
+/// Input that change the world, it is recorded with the tick it was applied to be replayed
+#[derive(Clone, Debug, PartialEq)]
+pub enum Command {
+    /// move the formation
+    MoveTo(P2),
+    MoveGroup(Vec<Entity>, P2),
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct Recording {
+    /// world the commands were applied to, when none is the one created from `Cfg`
+    pub scenario: Option<Scenario>,
+    /// commands sorted by the tick they were applied
+    pub commands: Vec<(u64, Command)>,
+}
+
+/// Owns the steering world and dispatcher and advance it in fixed timesteps.
+///
+/// Given the same `Cfg` and commands, the simulation is deterministic.
+pub struct Simulation<'a, 'b> {
+    world: World,
+    dispatcher: Dispatcher<'a, 'b>,
+    fixed_delta: f32,
+    accumulator: f32,
+    tick: u64,
+    pending: Vec<Command>,
+    recording: Recording,
+    replay: Option<Recording>,
+}
+
+impl<'a, 'b> Simulation<'a, 'b> {
+    pub fn new(cfg: Cfg, fixed_delta: f32) -> GameResult<Self> {
+        let scenario = Scenario::from_cfg(&cfg);
+        Simulation::new_with_scenario(cfg, fixed_delta, &scenario)
+    }
+
+    pub fn new_with_scenario(cfg: Cfg, fixed_delta: f32, scenario: &Scenario) -> GameResult<Self> {
+        let mut world = create_world(cfg)?;
+        build_scenario(&mut world, scenario)?;
+
+        let mut dispatcher = create_dispatcher();
+        dispatcher.setup(&mut world);
+
+        world.insert(GameTime {
+            delta_time: fixed_delta,
+        });
+
+        Ok(Simulation {
+            world,
+            dispatcher,
+            fixed_delta,
+            accumulator: 0.0,
+            tick: 0,
+            pending: vec![],
+            recording: Recording {
+                scenario: Some(scenario.clone()),
+                commands: vec![],
+            },
+            replay: None,
+        })
+    }
+
+    /// Create a new simulation with the recorded scenario that apply the recorded commands
+    /// instead of pushed ones
+    pub fn new_replay(cfg: Cfg, fixed_delta: f32, recording: Recording) -> GameResult<Self> {
+        let scenario = match &recording.scenario {
+            Some(scenario) => scenario.clone(),
+            None => Scenario::from_cfg(&cfg),
+        };
+        let mut simulation = Simulation::new_with_scenario(cfg, fixed_delta, &scenario)?;
+        simulation.replay = Some(recording);
+        Ok(simulation)
+    }
+
+    pub fn get_world(&self) -> &World {
+        &self.world
+    }
+
+    pub fn get_world_mut(&mut self) -> &mut World {
+        &mut self.world
+    }
+
+    pub fn get_tick(&self) -> u64 {
+        self.tick
+    }
+
+    pub fn get_fixed_delta(&self) -> f32 {
+        self.fixed_delta
+    }
+
+    pub fn get_recording(&self) -> &Recording {
+        &self.recording
+    }
+
+    /// Schedule the command to be applied on next tick. Ignored while replaying.
+    pub fn push_command(&mut self, command: Command) {
+        if self.replay.is_none() {
+            self.pending.push(command);
+        }
+    }
+
+    /// Accumulate the real delta time and execute as many fixed steps as fit on it, return
+    /// the number of executed steps
+    pub fn update(&mut self, delta: f32) -> GameResult<u32> {
+        self.accumulator += delta;
+
+        let mut steps = 0;
+        while self.accumulator >= self.fixed_delta {
+            if steps >= MAX_STEPS_PER_UPDATE {
+                self.accumulator = 0.0;
+                break;
+            }
+
+            self.accumulator -= self.fixed_delta;
+            self.step()?;
+            steps += 1;
+        }
+
+        Ok(steps)
+    }
+
+    /// Execute a single fixed step
+    pub fn step(&mut self) -> GameResult<()> {
+        let commands: Vec<Command> = match &self.replay {
+            Some(recording) => recording
+                .commands
+                .iter()
+                .filter(|(tick, _)| *tick == self.tick)
+                .map(|(_, command)| command.clone())
+                .collect(),
+            None => std::mem::replace(&mut self.pending, vec![]),
+        };
+
+        for command in commands {
+            apply_command(&mut self.world, &command)?;
+
+            if self.replay.is_none() {
+                self.recording.commands.push((self.tick, command));
+            }
+        }
+
+        run(self.fixed_delta, &mut self.world, &mut self.dispatcher);
+        self.tick += 1;
+
+        Ok(())
+    }
+}
+
+fn apply_command(world: &mut World, command: &Command) -> GameResult<()> {
+    let result = match command {
+        Command::MoveTo(target_pos) => move_to(world, *target_pos),
+        Command::MoveGroup(selection, target_pos) => move_group(world, selection, *target_pos),
+    };
+
+    result
+        .map(|_| ())
+        .map_err(|e| GameError::EventLoopError(format!("command {:?} failed: {}", command, e)))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn test_cfg() -> Cfg {
+        Cfg {
+            seed: 3,
+            vehicles: 30,
+            followers: 5,
+            blend_mode: BlendMode::PrioritizedDithering,
+            ..Default::default()
+        }
+    }
+
+    fn vehicles_state(world: &World) -> Vec<[u32; 7]> {
+        let vehicles = world.read_storage::<Vehicle>();
+        (&vehicles)
+            .join()
+            .map(|v| {
+                [
+                    v.pos.x.to_bits(),
+                    v.pos.y.to_bits(),
+                    v.dir.x.to_bits(),
+                    v.dir.y.to_bits(),
+                    v.vel_dir.x.to_bits(),
+                    v.vel_dir.y.to_bits(),
+                    v.speed.to_bits(),
+                ]
+            })
+            .collect()
+    }
+
+    fn run_with_commands(simulation: &mut Simulation) {
+        for i in 0..300 {
+            if i == 10 {
+                simulation.push_command(Command::MoveTo(p2(500.0, 200.0)));
+            }
+            if i == 150 {
+                simulation.push_command(Command::MoveTo(p2(200.0, 400.0)));
+            }
+            simulation.step().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_simulation_is_deterministic() {
+        let mut sim_a = Simulation::new(test_cfg(), 1.0 / 60.0).unwrap();
+        let mut sim_b = Simulation::new(test_cfg(), 1.0 / 60.0).unwrap();
+
+        run_with_commands(&mut sim_a);
+        run_with_commands(&mut sim_b);
+
+        assert_eq!(sim_a.get_tick(), 300);
+        assert_eq!(
+            vehicles_state(sim_a.get_world()),
+            vehicles_state(sim_b.get_world())
+        );
+    }
+
+    #[test]
+    fn test_simulation_replay() {
+        let mut sim = Simulation::new(test_cfg(), 1.0 / 60.0).unwrap();
+        run_with_commands(&mut sim);
+        assert_eq!(sim.get_recording().commands.len(), 2);
+
+        let mut replay =
+            Simulation::new_replay(test_cfg(), 1.0 / 60.0, sim.get_recording().clone()).unwrap();
+        for _ in 0..300 {
+            replay.step().unwrap();
+        }
+
+        assert_eq!(
+            vehicles_state(sim.get_world()),
+            vehicles_state(replay.get_world())
+        );
+    }
+
+    #[test]
+    fn test_simulation_replay_recorded_scenario() {
+        let path = concat!(
+            env!("CARGO_MANIFEST_DIR"),
+            "/resources/scenarios/walls.json"
+        );
+        let scenario = load_scenario(path).unwrap();
+
+        let mut sim = Simulation::new_with_scenario(test_cfg(), 1.0 / 60.0, &scenario).unwrap();
+        run_with_commands(&mut sim);
+
+        // the cfg would create a different world, the recording one must be used
+        let mut replay =
+            Simulation::new_replay(test_cfg(), 1.0 / 60.0, sim.get_recording().clone()).unwrap();
+        for _ in 0..300 {
+            replay.step().unwrap();
+        }
+
+        assert_eq!(
+            vehicles_state(sim.get_world()),
+            vehicles_state(replay.get_world())
+        );
+    }
+
+    #[test]
+    fn test_simulation_update_accumulate_fixed_steps() {
+        let mut sim = Simulation::new(test_cfg(), 0.25).unwrap();
+        assert_eq!(sim.update(0.1).unwrap(), 0);
+        assert_eq!(sim.update(0.2).unwrap(), 1);
+        assert_eq!(sim.update(0.5).unwrap(), 2);
+        assert_eq!(sim.get_tick(), 3);
+    }
+}
@@ -1,7 +1,9 @@
+use super::avoidance::*;
 use super::components::*;
+use super::debug::*;
+use super::orders::*;
 
 use commons::math::*;
-use ggez::graphics::Color;
 use ggez::{GameError, GameResult};
 use myelin_geometry::Polygon;
 use nalgebra::{Point2, Vector2};
@@ -11,28 +13,90 @@ use serde::{Deserialize, Serialize};
 use specs::prelude::*;
 use specs::{World, WorldExt};
 use specs_derive::Component;
+use std::collections::BTreeMap;
 
-pub struct BordersTeleportSystem;
-impl<'a> System<'a> for BordersTeleportSystem {
-    type SystemData = (ReadExpect<'a, Cfg>, WriteStorage<'a, Vehicle>);
+/// Apply the `Cfg::boundary_mode` to vehicles outside of the world area
+pub struct BoundarySystem;
+impl<'a> System<'a> for BoundarySystem {
+    type SystemData = (
+        ReadExpect<'a, Cfg>,
+        WriteStorage<'a, Vehicle>,
+        WriteStorage<'a, SteeringVelocity>,
+    );
 
-    fn run(&mut self, (cfg, mut mobs): Self::SystemData) {
+    fn run(&mut self, (cfg, mut mobs, mut velocities): Self::SystemData) {
         use specs::Join;
 
-        let (width, height) = (cfg.screen_width, cfg.screen_height);
+        let (width, height) = (cfg.world_width, cfg.world_height);
 
-        for (vehicle) in (&mut mobs).join() {
-            if vehicle.pos.x > width as f32 {
-                vehicle.pos.x -= width as f32;
-            }
-            if vehicle.pos.x < 0.0 {
-                vehicle.pos.x += width as f32;
+        match cfg.boundary_mode {
+            BoundaryMode::None => {}
+
+            BoundaryMode::Wrap => {
+                for vehicle in (&mut mobs).join() {
+                    if vehicle.pos.x > width {
+                        vehicle.pos.x -= width;
+                    }
+                    if vehicle.pos.x < 0.0 {
+                        vehicle.pos.x += width;
+                    }
+                    if vehicle.pos.y > height {
+                        vehicle.pos.y -= height;
+                    }
+                    if vehicle.pos.y < 0.0 {
+                        vehicle.pos.y += height;
+                    }
+                }
             }
-            if vehicle.pos.y > height as f32 {
-                vehicle.pos.y -= height as f32;
+
+            BoundaryMode::Clamp => {
+                for vehicle in (&mut mobs).join() {
+                    let mut vel = vehicle.get_velocity();
+
+                    if vehicle.pos.x < 0.0 || vehicle.pos.x > width {
+                        vehicle.pos.x = vehicle.pos.x.max(0.0).min(width);
+                        vel.x = 0.0;
+                    }
+                    if vehicle.pos.y < 0.0 || vehicle.pos.y > height {
+                        vehicle.pos.y = vehicle.pos.y.max(0.0).min(height);
+                        vel.y = 0.0;
+                    }
+
+                    vehicle.set_velocity(vel);
+                }
             }
-            if vehicle.pos.y < 0.0 {
-                vehicle.pos.y += height as f32;
+
+            BoundaryMode::Bounce => {
+                for (vehicle, velocity) in (&mut mobs, (&mut velocities).maybe()).join() {
+                    let mut vel = vehicle.get_velocity();
+                    let mut flip = v2(1.0, 1.0);
+
+                    if vehicle.pos.x < 0.0 {
+                        vehicle.pos.x = -vehicle.pos.x;
+                        flip.x = -1.0;
+                    } else if vehicle.pos.x > width {
+                        vehicle.pos.x = 2.0 * width - vehicle.pos.x;
+                        flip.x = -1.0;
+                    }
+
+                    if vehicle.pos.y < 0.0 {
+                        vehicle.pos.y = -vehicle.pos.y;
+                        flip.y = -1.0;
+                    } else if vehicle.pos.y > height {
+                        vehicle.pos.y = 2.0 * height - vehicle.pos.y;
+                        flip.y = -1.0;
+                    }
+
+                    if flip.x < 0.0 || flip.y < 0.0 {
+                        vel.component_mul_assign(&flip);
+                        vehicle.set_velocity(vel);
+
+                        // wander velocity would push it back against the border
+                        if let Some(velocity) = velocity {
+                            velocity.vel.component_mul_assign(&flip);
+                        }
+                    }
+                }
             }
         }
     }
@@ -42,13 +106,15 @@ pub struct SteeringSeparationSystem;
 impl<'a> System<'a> for SteeringSeparationSystem {
     type SystemData = (
         Entities<'a>,
+        ReadExpect<'a, Cfg>,
         WriteStorage<'a, Vehicle>,
         ReadStorage<'a, SteeringSeparation>,
     );
 
-    fn run(&mut self, (entities, mut vehicles, separations): Self::SystemData) {
+    fn run(&mut self, (entities, cfg, mut vehicles, separations): Self::SystemData) {
         use specs::Join;
 
+        let bounds = cfg.get_bounds();
         let mut changes = vec![];
 
         for (entity_a, vehicle_a, separation_a) in (&*entities, &vehicles, &separations).join() {
@@ -63,7 +129,7 @@ impl<'a> System<'a> for SteeringSeparationSystem {
                 }
 
                 let min_distance = separation_a.distance + separation_b.distance;
-                let vector = (vehicle_b.pos - vehicle_a.pos);
+                let vector = bounds.delta(vehicle_a.pos, vehicle_b.pos);
                 let distance = vector.magnitude();
 
                 if distance < min_distance {
@@ -84,7 +150,7 @@ impl<'a> System<'a> for SteeringSeparationSystem {
         let vehicles = &mut vehicles;
         for (entity, desired_vel) in changes {
             let vehicle = vehicles.get_mut(entity).unwrap();
-            vehicle.desired_vel += desired_vel;
+            vehicle.add_force(SteeringBehaviour::Separation, desired_vel);
         }
     }
 }
@@ -97,18 +163,21 @@ impl<'a> System<'a> for SteeringVelocitySystem {
         use specs::Join;
 
         for (velocity, vehicle) in (&velocity, &mut vehicles).join() {
-            if !velocity.enabled {
-                continue;
-            }
-
-            let vehicle: &mut Vehicle = vehicle;
-            // TODO: change vel?
-            vehicle.desired_vel += velocity.vel * velocity.weight;
-            vehicle.desired_dir = velocity.vel.normalize();
+            steer_velocity(vehicle, velocity);
         }
     }
 }
 
+pub fn steer_velocity(vehicle: &mut Vehicle, velocity: &SteeringVelocity) {
+    if !velocity.enabled {
+        return;
+    }
+
+    // TODO: change vel?
+    vehicle.add_force(SteeringBehaviour::Velocity, velocity.vel * velocity.weight);
+    vehicle.desired_dir = velocity.vel.normalize();
+}
+
 pub struct SteeringWallsSystem;
 impl<'a> System<'a> for SteeringWallsSystem {
     type SystemData = (WriteStorage<'a, Vehicle>, ReadStorage<'a, Wall>);
@@ -117,129 +186,850 @@ impl<'a> System<'a> for SteeringWallsSystem {
         use specs::Join;
 
         for (vehicle) in (&mut vehicles).join() {
-            for (wall) in (&walls).join() {
-                if let Some(vector) =
-                    compute_vector_from_point_to_segment(wall.pos, wall.vec, vehicle.pos)
-                {
-                    let distance = vector.magnitude();
-                    if distance < wall.min_distance {
-                        // let dir = vector.normalize() * -1.0;
-                        let force_intensity = lerp_2(0.0, 1.0, wall.min_distance, 0.0, distance);
-                        let desired_vel = wall.force * force_intensity;
-
-                        // println!(
-                        //     "wall collision pos {:?} vec {:?} dir {:?} distance {:?}",
-                        //     vehicle.pos, vector, dir, distance
-                        // );
-
-                        // TODO: change vel?
-                        vehicle.desired_vel += desired_vel;
+            steer_walls(vehicle, (&walls).join());
+        }
+    }
+}
+
+pub fn steer_walls<'w, I: Iterator<Item = &'w Wall>>(vehicle: &mut Vehicle, walls: I) {
+    for wall in walls {
+        let (dir, distance) = wall.push_direction(vehicle.pos);
+        if distance < wall.min_distance {
+            let force_intensity = lerp_2(0.0, 1.0, wall.min_distance, 0.0, distance);
+            let desired_vel = dir * wall.force * force_intensity;
+            vehicle.add_force(SteeringBehaviour::Walls, desired_vel);
+        }
+    }
+}
+
+/// Accumulated position and velocity of a group of vehicles, positions are relative to the
+/// first member to be valid across wrapping borders
+#[derive(Clone, Debug)]
+struct GroupCenter {
+    reference: P2,
+    delta_sum: V2,
+    vel_sum: V2,
+    count: usize,
+}
+
+impl Default for GroupCenter {
+    fn default() -> Self {
+        GroupCenter {
+            reference: P2::origin(),
+            delta_sum: V2::zeros(),
+            vel_sum: V2::zeros(),
+            count: 0,
+        }
+    }
+}
+
+impl GroupCenter {
+    fn add(&mut self, bounds: &WorldBounds, pos: P2, vel: V2) {
+        if self.count == 0 {
+            self.reference = pos;
+        }
+
+        self.delta_sum += bounds.delta(self.reference, pos);
+        self.vel_sum += vel;
+        self.count += 1;
+    }
+
+    fn centroid(&self) -> P2 {
+        self.reference + self.delta_sum / self.count.max(1) as f32
+    }
+
+    fn velocity(&self) -> V2 {
+        self.vel_sum / self.count.max(1) as f32
+    }
+}
+
+/// Vehicle of a squad as seen by the flocking
+#[derive(Clone, Debug)]
+pub struct FlockMember {
+    pub pos: P2,
+    pub vel: V2,
+    pub dir: V2,
+    pub squad: Entity,
+    pub leader: bool,
+}
+
+impl FlockMember {
+    pub fn new(vehicle: &Vehicle, squad: Entity, leader: bool) -> Self {
+        FlockMember {
+            pos: vehicle.pos,
+            vel: vehicle.get_velocity(),
+            dir: vehicle.dir,
+            squad,
+            leader,
+        }
+    }
+}
+
+/// Where the members of a squad steer to by flocking
+#[derive(Clone, Debug)]
+pub struct FlockTarget {
+    pub squad_centroid: P2,
+    pub squad_vel: V2,
+    pub cohesion: f32,
+    pub alignment: f32,
+    /// velocity that moves the squad towards its fleet formation slot
+    pub formation_vel: Option<V2>,
+    /// centroid, velocity, cohesion and alignment of the fleet
+    pub fleet: Option<(P2, V2, f32, f32)>,
+}
+
+impl FlockTarget {
+    /// Target after `time` seconds, considering the squad and fleet keep their velocity
+    pub fn advance(&self, time: f32) -> FlockTarget {
+        FlockTarget {
+            squad_centroid: self.squad_centroid + self.squad_vel * time,
+            fleet: self.fleet.map(|(centroid, vel, cohesion, alignment)| {
+                (centroid + vel * time, vel, cohesion, alignment)
+            }),
+            ..self.clone()
+        }
+    }
+}
+
+/// Flocking target of each squad, computed from all its members
+pub fn compute_flock_targets(
+    bounds: &WorldBounds,
+    members: &[FlockMember],
+    squads: &ReadStorage<Squad>,
+    fleets: &ReadStorage<Fleet>,
+) -> BTreeMap<Entity, FlockTarget> {
+    use specs::Join;
+
+    let mut squad_centers: BTreeMap<Entity, GroupCenter> = BTreeMap::new();
+    let mut fleet_centers: BTreeMap<Entity, GroupCenter> = BTreeMap::new();
+    // position and look direction of the fleet leader
+    let mut fleet_anchors: BTreeMap<Entity, (P2, V2)> = BTreeMap::new();
+
+    for member in members {
+        let squad = match squads.get(member.squad) {
+            Some(squad) => squad,
+            None => continue,
+        };
+
+        squad_centers
+            .entry(member.squad)
+            .or_default()
+            .add(bounds, member.pos, member.vel);
+
+        if let Some(fleet) = squad.fleet.filter(|fleet| fleets.contains(*fleet)) {
+            fleet_centers
+                .entry(fleet)
+                .or_default()
+                .add(bounds, member.pos, member.vel);
+
+            if member.leader {
+                fleet_anchors
+                    .entry(fleet)
+                    .or_insert((member.pos, member.dir));
+            }
+        }
+    }
+
+    // fleets without leader are anchored at its center, looking where it moves
+    for (fleet, center) in &fleet_centers {
+        fleet_anchors.entry(*fleet).or_insert_with(|| {
+            let vel = center.velocity();
+            let dir = if vel.magnitude() > 0.001 {
+                vel.normalize()
+            } else {
+                v2(1.0, 0.0)
+            };
+            (center.centroid(), dir)
+        });
+    }
+
+    let mut formation_sizes: BTreeMap<Entity, usize> = BTreeMap::new();
+    for squad in squads.join() {
+        if let (Some(fleet), Some(_)) = (squad.fleet, squad.formation_index) {
+            *formation_sizes.entry(fleet).or_default() += 1;
+        }
+    }
+
+    squad_centers
+        .iter()
+        .map(|(squad_entity, center)| {
+            let squad = squads.get(*squad_entity).unwrap();
+
+            // velocity that moves the squad towards its formation slot
+            let formation_vel = match (squad.fleet, squad.formation_index) {
+                (Some(fleet), Some(index)) => {
+                    match (fleets.get(fleet), fleet_anchors.get(&fleet)) {
+                        (Some(fleet_def), Some((anchor_pos, anchor_dir))) => {
+                            let total = formation_sizes.get(&fleet).cloned().unwrap_or(0);
+                            let slot =
+                                fleet_def
+                                    .formation
+                                    .get_pos(*anchor_dir, *anchor_pos, total, index);
+                            Some(bounds.delta(center.centroid(), slot) * squad.formation_weight)
+                        }
+                        _ => None,
                     }
                 }
+                _ => None,
+            };
+
+            let fleet = squad.fleet.and_then(|fleet_entity| {
+                fleets.get(fleet_entity).map(|fleet| {
+                    let center = &fleet_centers[&fleet_entity];
+                    (
+                        center.centroid(),
+                        center.velocity(),
+                        fleet.cohesion,
+                        fleet.alignment,
+                    )
+                })
+            });
+
+            let target = FlockTarget {
+                squad_centroid: center.centroid(),
+                squad_vel: center.velocity(),
+                cohesion: squad.cohesion,
+                alignment: squad.alignment,
+                formation_vel,
+                fleet,
+            };
+
+            (*squad_entity, target)
+        })
+        .collect()
+}
+
+/// Cohesion and alignment of the squad and fleet, and the squad formation slot, are summed
+/// into a single force
+pub fn steer_flock(vehicle: &mut Vehicle, target: &FlockTarget, bounds: &WorldBounds) {
+    let mut desired_vel = bounds.delta(vehicle.pos, target.squad_centroid) * target.cohesion
+        + target.squad_vel * target.alignment;
+
+    if let Some(vel) = target.formation_vel {
+        desired_vel += vel;
+    }
+
+    if let Some((centroid, vel, cohesion, alignment)) = target.fleet {
+        desired_vel += bounds.delta(vehicle.pos, centroid) * cohesion + vel * alignment;
+    }
+
+    let speed = desired_vel.magnitude();
+    if speed > vehicle.max_speed {
+        desired_vel = desired_vel / speed * vehicle.max_speed;
+    }
+
+    let current_vel = vehicle.get_velocity();
+    vehicle.add_force(SteeringBehaviour::Flocking, desired_vel - current_vel);
+}
+
+/// Cohesion and alignment of squads and fleets, and squads keeping its slot in the fleet
+/// formation. The desired velocity of each level is summed and produce a single force.
+pub struct SteeringFlockSystem;
+impl<'a> System<'a> for SteeringFlockSystem {
+    type SystemData = (
+        ReadExpect<'a, Cfg>,
+        WriteStorage<'a, Vehicle>,
+        ReadStorage<'a, SteeringFlock>,
+        ReadStorage<'a, Squad>,
+        ReadStorage<'a, Fleet>,
+        ReadStorage<'a, SteeringFormationLeader>,
+    );
+
+    fn run(&mut self, (cfg, mut vehicles, flocks, squads, fleets, leaders): Self::SystemData) {
+        use specs::Join;
+
+        let bounds = cfg.get_bounds();
+
+        let members: Vec<FlockMember> = (&vehicles, &flocks, leaders.maybe())
+            .join()
+            .map(|(vehicle, flock, leader)| {
+                FlockMember::new(vehicle, flock.squad, leader.is_some())
+            })
+            .collect();
+
+        let targets = compute_flock_targets(&bounds, &members, &squads, &fleets);
+
+        for (vehicle, flock) in (&mut vehicles, &flocks).join() {
+            if let Some(target) = targets.get(&flock.squad) {
+                steer_flock(vehicle, target, &bounds);
             }
         }
     }
 }
 
-pub struct MoveSystem;
-impl<'a> System<'a> for MoveSystem {
-    type SystemData = (ReadExpect<'a, GameTime>, WriteStorage<'a, Vehicle>);
+/// Combine all forces collected by the steering systems into the vehicle desired velocity
+pub struct SteeringBlendSystem;
+impl<'a> System<'a> for SteeringBlendSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadExpect<'a, Cfg>,
+        WriteExpect<'a, GameRandom>,
+        WriteExpect<'a, DebugStuff>,
+        WriteExpect<'a, DebugDraw>,
+        WriteStorage<'a, Vehicle>,
+    );
 
-    fn run(&mut self, (game_time, mut vehicles): Self::SystemData) {
+    fn run(
+        &mut self,
+        (entities, cfg, mut random, mut debug_stuff, mut debug_draw, mut vehicles): Self::SystemData,
+    ) {
         use specs::Join;
 
-        let delta_time = game_time.delta_time;
+        debug_stuff.contributions.clear();
 
-        for (vehicle) in (&mut vehicles).join() {
+        for (entity, vehicle) in (&*entities, &mut vehicles).join() {
             let vehicle: &mut Vehicle = vehicle;
 
-            // apply steerning
-            {
-                // compute new velocity
-                let desired_velocity = vehicle.desired_vel;
-                vehicle.desired_vel = Vector2::zeros();
-
-                let current_vel = vehicle.vel_dir * vehicle.speed;
-                let mut delta_velocity = desired_velocity - current_vel;
-                if delta_velocity.magnitude() > vehicle.max_acc {
-                    delta_velocity = delta_velocity.normalize() * vehicle.max_acc;
+            let mut forces = std::mem::replace(&mut vehicle.forces, vec![]);
+            forces.sort_by_key(|force| force.behaviour.priority());
+
+            let (desired_vel, used) =
+                blend_forces(cfg.blend_mode, &forces, vehicle.max_acc, &mut random.rng);
+
+            for (force, used) in forces.into_iter().zip(used.into_iter()) {
+                if used {
+                    debug_draw.arrow(
+                        DebugCategory::Forces,
+                        vehicle.pos,
+                        vehicle.pos + force.force,
+                        force.behaviour.debug_color(),
+                    );
                 }
+                debug_stuff.push_contribution(entity, force, used);
+            }
+
+            vehicle.desired_vel += desired_vel;
+        }
+    }
+}
+
+/// forces smaller that it are ignored by the prioritized blend modes
+const BLEND_EPSILON: f32 = 0.01;
 
-                let new_vel = current_vel + delta_velocity * delta_time;
+/// Blend the forces, that must be already sorted by priority, returning the resulting vector
+/// and which forces were used.
+pub fn blend_forces<R: Rng>(
+    mode: BlendMode,
+    forces: &[SteeringForce],
+    max_acc: f32,
+    rng: &mut R,
+) -> (V2, Vec<bool>) {
+    let mut used = vec![false; forces.len()];
+    let mut total: V2 = Vector2::zeros();
 
-                // normalize velocity
-                let mut new_speed = new_vel.magnitude();
-                vehicle.vel_dir = new_vel / new_speed;
+    match mode {
+        BlendMode::WeightedSum => {
+            for (i, force) in forces.iter().enumerate() {
+                total += force.force;
+                used[i] = true;
+            }
+        }
+
+        BlendMode::PrioritizedTruncatedSum => {
+            let mut budget = max_acc;
+
+            for (i, force) in forces.iter().enumerate() {
+                if budget <= BLEND_EPSILON {
+                    break;
+                }
+
+                let magnitude = force.force.magnitude();
+                if magnitude < BLEND_EPSILON {
+                    continue;
+                }
 
-                if new_speed > vehicle.max_speed {
-                    new_speed = vehicle.max_speed;
+                if magnitude > budget {
+                    total += force.force * (budget / magnitude);
+                    budget = 0.0;
+                } else {
+                    total += force.force;
+                    budget -= magnitude;
                 }
-                vehicle.speed = new_speed;
+
+                used[i] = true;
             }
+        }
 
-            // move
-            {
-                vehicle.pos += vehicle.vel_dir * vehicle.speed * delta_time;
+        BlendMode::PrioritizedDithering => {
+            for (i, force) in forces.iter().enumerate() {
+                if rng.gen::<f32>() > force.behaviour.dithering_probability() {
+                    continue;
+                }
+
+                if force.force.magnitude() < BLEND_EPSILON {
+                    continue;
+                }
+
+                total = force.force;
+                used[i] = true;
+                break;
             }
+        }
+    }
 
-            // update direction
-            {
-                vehicle.dir = rotate_towards(
-                    vehicle.dir,
-                    vehicle.desired_dir,
-                    vehicle.rotation_speed * delta_time,
-                );
+    (total, used)
+}
+
+/// Change the desired velocity of vehicles with `SteeringAvoidance` to be collision free.
+///
+/// It must run after the blend, the desired velocity is set to the acceleration required by
+/// the `MoveSystem` to reach the new velocity on next step.
+pub struct SteeringAvoidanceSystem;
+impl<'a> System<'a> for SteeringAvoidanceSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadExpect<'a, Cfg>,
+        ReadExpect<'a, GameTime>,
+        ReadStorage<'a, SteeringAvoidance>,
+        WriteStorage<'a, Vehicle>,
+    );
+
+    fn run(&mut self, (entities, cfg, game_time, avoidances, mut vehicles): Self::SystemData) {
+        use specs::Join;
+
+        let bounds = cfg.get_bounds();
+
+        let delta_time = game_time.delta_time;
+        if delta_time <= 0.0 {
+            return;
+        }
+
+        let agents: Vec<(Entity, OrcaNeighbour)> = (&*entities, &vehicles, avoidances.maybe())
+            .join()
+            .map(|(entity, vehicle, avoidance)| {
+                // agents that also avoid take half of the responsibility
+                let avoids = avoidance.map(|a| a.enabled).unwrap_or(false);
+                let neighbour = OrcaNeighbour {
+                    pos: vehicle.pos,
+                    vel: vehicle.get_velocity(),
+                    radius: vehicle.radius,
+                    responsibility: if avoids { 0.5 } else { 1.0 },
+                };
+                (entity, neighbour)
+            })
+            .collect();
+
+        let mut changes = vec![];
+
+        for (entity, vehicle, avoidance) in (&*entities, &vehicles, &avoidances).join() {
+            if !avoidance.enabled {
+                continue;
+            }
+
+            let vel = vehicle.get_velocity();
+
+            // velocity that the vehicle would reach without avoidance
+            let mut acc = vehicle.desired_vel - vel;
+            if acc.magnitude() > vehicle.max_acc {
+                acc = acc.normalize() * vehicle.max_acc;
+            }
+            let preferred_vel = vel + acc * delta_time;
+
+            let range = vehicle.max_speed * 2.0 * avoidance.time_horizon;
+            let neighbours: Vec<OrcaNeighbour> = agents
+                .iter()
+                .filter(|(other, neighbour)| {
+                    *other != entity
+                        && bounds.distance(vehicle.pos, neighbour.pos)
+                            < range + vehicle.radius + neighbour.radius
+                })
+                .map(|(_, neighbour)| OrcaNeighbour {
+                    // closest position, that can be across the world border
+                    pos: vehicle.pos + bounds.delta(vehicle.pos, neighbour.pos),
+                    ..neighbour.clone()
+                })
+                .collect();
+
+            let lines = compute_orca_lines(
+                vehicle.pos,
+                vel,
+                vehicle.radius,
+                &neighbours,
+                avoidance.time_horizon,
+                delta_time,
+            );
+
+            // only velocities reachable this step, otherwise the max_acc clamp on integration
+            // could bring it back inside a forbidden region
+            let new_vel = solve_orca_reachable(
+                &lines,
+                vehicle.max_speed,
+                vel,
+                vehicle.max_acc * delta_time,
+                preferred_vel,
+            );
+            changes.push((entity, vel + (new_vel - vel) / delta_time));
+        }
+
+        for (entity, desired_vel) in changes {
+            if let Some(vehicle) = vehicles.get_mut(entity) {
+                vehicle.desired_vel = desired_vel;
+            }
+        }
+    }
+}
+
+pub struct MoveSystem;
+impl<'a> System<'a> for MoveSystem {
+    type SystemData = (
+        ReadExpect<'a, GameTime>,
+        WriteStorage<'a, Vehicle>,
+        WriteStorage<'a, VehiclePhysics>,
+        ReadStorage<'a, Wall>,
+    );
+
+    fn run(&mut self, (game_time, mut vehicles, mut physics, walls): Self::SystemData) {
+        use specs::Join;
+
+        let delta_time = game_time.delta_time;
+
+        for (vehicle, physics) in (&mut vehicles, (&mut physics).maybe()).join() {
+            let vehicle: &mut Vehicle = vehicle;
+
+            integrate(vehicle, physics, (&walls).join(), delta_time);
+        }
+    }
+}
+
+/// Apply the desired velocity and move the vehicle, stopping at the first wall crossed
+pub fn integrate<'w, I: Iterator<Item = &'w Wall>>(
+    vehicle: &mut Vehicle,
+    physics: Option<&mut VehiclePhysics>,
+    walls: I,
+    delta_time: f32,
+) {
+    match physics {
+        Some(physics) => apply_physics(vehicle, physics, delta_time),
+        None => apply_kinematic(vehicle, delta_time),
+    }
+
+    let movement = vehicle.vel_dir * vehicle.speed * delta_time;
+
+    let hit = walls
+        .filter_map(|wall| wall.sweep(vehicle.pos, movement))
+        .min_by(|(a, _), (b, _)| a.partial_cmp(b).unwrap());
+
+    match hit {
+        Some((t, normal)) => {
+            vehicle.pos += movement * t + normal * WALL_SWEEP_MARGIN;
+
+            // remove velocity towards the wall
+            let vel = vehicle.get_velocity();
+            let into_wall = vel.dot(&normal);
+            if into_wall < 0.0 {
+                vehicle.set_velocity(vel - normal * into_wall);
             }
         }
+        None => {
+            vehicle.pos += movement;
+        }
+    }
+}
+
+/// Change the velocity directly towards the desired one and rotate independently of it
+fn apply_kinematic(vehicle: &mut Vehicle, delta_time: f32) {
+    // compute new velocity
+    {
+        let desired_velocity = vehicle.desired_vel;
+        vehicle.desired_vel = Vector2::zeros();
+
+        let current_vel = vehicle.vel_dir * vehicle.speed;
+        let mut delta_velocity = desired_velocity - current_vel;
+        if delta_velocity.magnitude() > vehicle.max_acc {
+            delta_velocity = delta_velocity.normalize() * vehicle.max_acc;
+        }
+
+        let new_vel = current_vel + delta_velocity * delta_time;
+
+        // normalize velocity
+        let mut new_speed = new_vel.magnitude();
+        vehicle.vel_dir = if new_speed > 0.0 {
+            new_vel / new_speed
+        } else {
+            Vector2::zeros()
+        };
+
+        if new_speed > vehicle.max_speed {
+            new_speed = vehicle.max_speed;
+        }
+        vehicle.speed = new_speed;
     }
+
+    // update direction
+    {
+        vehicle.dir = rotate_towards(
+            vehicle.dir,
+            vehicle.desired_dir,
+            vehicle.rotation_speed * delta_time,
+        );
+    }
+}
+
+/// desired accelerations smaller that it are ignored by the physics model
+const PHYSICS_EPSILON: f32 = 0.001;
+
+fn signed_angle(from: V2, to: V2) -> f32 {
+    let cross = from.x * to.y - from.y * to.x;
+    cross.atan2(from.dot(&to))
+}
+
+/// Turn towards the desired acceleration and fire the thrusters to reach it
+fn apply_physics(vehicle: &mut Vehicle, physics: &mut VehiclePhysics, delta_time: f32) {
+    let desired_velocity = vehicle.desired_vel;
+    vehicle.desired_vel = Vector2::zeros();
+
+    let current_vel = vehicle.get_velocity();
+    let mut desired_acc = desired_velocity - current_vel;
+    if desired_acc.magnitude() > vehicle.max_acc {
+        desired_acc = desired_acc.normalize() * vehicle.max_acc;
+    }
+
+    // thrust also compensate the drag to hold the desired velocity
+    desired_acc += current_vel * physics.linear_drag;
+
+    let has_acc = desired_acc.magnitude() > PHYSICS_EPSILON;
+
+    // rotate, accelerating as much as possible while still able to stop at the target angle
+    {
+        let target_dir = if has_acc {
+            desired_acc.normalize()
+        } else {
+            vehicle.desired_dir
+        };
+
+        let angle = signed_angle(vehicle.dir, target_dir);
+        let desired_angular_vel = angle.signum()
+            * (2.0 * physics.angular_acc * angle.abs())
+                .sqrt()
+                .min(vehicle.rotation_speed);
+
+        let max_angular_delta = physics.angular_acc * delta_time;
+        let angular_delta = (desired_angular_vel - physics.angular_vel)
+            .max(-max_angular_delta)
+            .min(max_angular_delta);
+
+        physics.angular_vel += angular_delta;
+        physics.angular_vel *= (1.0 - physics.angular_drag * delta_time).max(0.0);
+
+        let mut rotation = physics.angular_vel * delta_time;
+        if rotation.signum() == angle.signum() && rotation.abs() > angle.abs() {
+            // do not overshoot
+            rotation = angle;
+            physics.angular_vel = 0.0;
+        }
+
+        let new_angle = angle_vector(vehicle.dir) + rotation;
+        vehicle.dir = v2(new_angle.cos(), new_angle.sin());
+    }
+
+    // thrust
+    let mut new_vel = current_vel;
+    if has_acc {
+        let force = desired_acc * physics.mass;
+        let right = v2(-vehicle.dir.y, vehicle.dir.x);
+
+        let facing = signed_angle(vehicle.dir, desired_acc).abs() <= physics.burn_angle;
+        let max_forward = if facing { physics.thrust } else { 0.0 };
+        let forward = force
+            .dot(&vehicle.dir)
+            .max(-physics.reverse_thrust)
+            .min(max_forward);
+        let lateral = force
+            .dot(&right)
+            .max(-physics.lateral_thrust)
+            .min(physics.lateral_thrust);
+
+        let acc = (vehicle.dir * forward + right * lateral) / physics.mass;
+        new_vel += acc * delta_time;
+    }
+
+    // drag
+    new_vel *= (1.0 - physics.linear_drag * delta_time).max(0.0);
+
+    if new_vel.magnitude() > vehicle.max_speed {
+        new_vel = new_vel.normalize() * vehicle.max_speed;
+    }
+
+    vehicle.set_velocity(new_vel);
 }
 
 pub struct SteerArrivalSystem;
 impl<'a> System<'a> for SteerArrivalSystem {
     type SystemData = (
+        ReadExpect<'a, Cfg>,
         WriteStorage<'a, Vehicle>,
         WriteStorage<'a, SteeringArrival>,
-        WriteExpect<'a, DebugStuff>,
     );
 
-    fn run(&mut self, (mut mobs, mut steering_arrival, mut debug_stuff): Self::SystemData) {
+    fn run(&mut self, (cfg, mut mobs, mut steering_arrival): Self::SystemData) {
         use specs::Join;
-        let min_distance = 0.1;
+        let bounds = cfg.get_bounds();
 
         for (vehicle, arrival) in (&mut mobs, &mut steering_arrival).join() {
-            let arrival: &mut SteeringArrival = arrival;
-            if !arrival.enabled {
-                continue;
-            }
+            steer_arrival(vehicle, arrival, &bounds);
+        }
+    }
+}
 
-            let vehicle: &mut Vehicle = vehicle;
-            let delta = arrival.target_pos - vehicle.pos;
-            let distance: f32 = delta.magnitude();
-            if !distance.is_normal() || distance < min_distance {
-                // arrival
-                arrival.arrived = true;
+pub fn steer_arrival(vehicle: &mut Vehicle, arrival: &mut SteeringArrival, bounds: &WorldBounds) {
+    let min_distance = 0.1;
+
+    if !arrival.enabled {
+        return;
+    }
+
+    let delta = bounds.delta(vehicle.pos, arrival.target_pos);
+    let distance: f32 = delta.magnitude();
+    if !distance.is_normal() || distance < min_distance {
+        // arrival
+        arrival.arrived = true;
+        return;
+    }
+    arrival.arrived = false;
+
+    let dir = delta / distance;
+
+    let slowdown_distance = vehicle.max_speed * arrival.distance;
+
+    let speed = if distance > slowdown_distance {
+        vehicle.desired_dir = dir;
+        vehicle.max_speed
+    } else {
+        lerp_2(0.0, vehicle.max_speed, 0.0, slowdown_distance, distance)
+    };
+
+    let desired_vel = dir * speed * arrival.weight;
+    let current_vel = vehicle.get_velocity();
+    vehicle.add_force(SteeringBehaviour::Arrival, desired_vel - current_vel);
+}
+
+pub struct SteeringScriptSystem;
+impl<'a> System<'a> for SteeringScriptSystem {
+    type SystemData = (
+        ReadExpect<'a, Cfg>,
+        ReadStorage<'a, Vehicle>,
+        WriteStorage<'a, SteeringScript>,
+        WriteStorage<'a, SteeringArrival>,
+    );
+
+    fn run(&mut self, (cfg, vehicles, mut scripts, mut arrivals): Self::SystemData) {
+        use specs::Join;
+
+        let bounds = cfg.get_bounds();
+
+        for (vehicle, script, arrival) in (&vehicles, &mut scripts, &mut arrivals).join() {
+            update_script(vehicle, script, arrival, &bounds);
+        }
+    }
+}
+
+/// Set the current script target into the arrival, advancing when it is reached
+pub fn update_script(
+    vehicle: &Vehicle,
+    script: &mut SteeringScript,
+    arrival: &mut SteeringArrival,
+    bounds: &WorldBounds,
+) {
+    let target_pos = match script.current() {
+        Some(pos) => pos,
+        None => return,
+    };
+
+    let reached = bounds.distance(vehicle.pos, target_pos) < script.distance;
+    if arrival.target_pos == target_pos && (reached || arrival.arrived) {
+        script.next();
+    }
+
+    arrival.enabled = true;
+    arrival.target_pos = script.current().unwrap_or(target_pos);
+}
+
+/// Keep the arrival points of move orders up to date with their members
+pub struct MoveOrderSystem;
+impl<'a> System<'a> for MoveOrderSystem {
+    type SystemData = (
+        Entities<'a>,
+        ReadStorage<'a, Vehicle>,
+        WriteStorage<'a, SteeringMoveOrder>,
+        ReadStorage<'a, SteeringFormationMember>,
+        ReadStorage<'a, SteeringFormationLeader>,
+        WriteStorage<'a, SteeringArrival>,
+        Write<'a, MoveOrders>,
+    );
+
+    fn run(
+        &mut self,
+        (entities, vehicles, mut move_orders, formation_members, leaders, mut arrivals, mut orders): Self::SystemData,
+    ) {
+        use specs::Join;
+
+        let mut members: BTreeMap<u32, Vec<SlotMember>> = BTreeMap::new();
+        for (entity, vehicle, move_order, member, leader) in (
+            &entities,
+            &vehicles,
+            &move_orders,
+            formation_members.maybe(),
+            leaders.maybe(),
+        )
+            .join()
+        {
+            members
+                .entry(move_order.order)
+                .or_default()
+                .push(SlotMember {
+                    entity,
+                    pos: vehicle.pos,
+                    radius: vehicle.radius,
+                    formation_index: member.map(|member| member.index),
+                    leader_formation: leader.map(|leader| leader.formation),
+                });
+        }
+
+        // orders without members are complete
+        orders.orders.retain(|id, _| members.contains_key(id));
+
+        for (id, order) in orders.orders.iter_mut() {
+            let group = &members[id];
+            let current: Vec<Entity> = group.iter().map(|member| member.entity).collect();
+            if current == order.assigned {
+                for member in group {
+                    let arrived = arrivals
+                        .get(member.entity)
+                        .map(|arrival| arrival.arrived)
+                        .unwrap_or(true);
+
+                    if arrived && !order.arrived.contains(&member.entity) {
+                        order.arrived.push(member.entity);
+                    }
+                }
                 continue;
             }
-            arrival.arrived = false;
 
-            let dir = delta / distance;
+            for (entity, pos) in compute_slots(order.target_pos, group) {
+                if let Some(arrival) = arrivals.get_mut(entity) {
+                    arrival.enabled = true;
+                    arrival.arrived = false;
+                    arrival.target_pos = pos;
+                }
+            }
 
-            let slowdown_distance = vehicle.max_speed * arrival.distance;
+            order.assigned = current;
+            order.arrived.clear();
+        }
 
-            let speed = if distance > slowdown_distance {
-                vehicle.desired_dir = dir;
-                vehicle.max_speed
-            } else {
-                lerp_2(0.0, vehicle.max_speed, 0.0, slowdown_distance, distance)
-            };
+        // orders with all members arrived are complete, members keep their arrival points
+        let complete: Vec<u32> = orders
+            .orders
+            .iter()
+            .filter(|(_, order)| order.arrived.len() == order.assigned.len())
+            .map(|(id, _)| *id)
+            .collect();
 
-            let desired_vel = dir * speed * arrival.weight;
-            debug_stuff.push_line(
-                vehicle.pos,
-                vehicle.pos + desired_vel,
-                Color::new(1.0, 1.0, 0.0, 1.0),
-            );
-            vehicle.desired_vel += desired_vel - vehicle.get_velocity();
+        for id in complete {
+            for member in &members[&id] {
+                move_orders.remove(member.entity);
+            }
+            orders.orders.remove(&id);
         }
     }
 }
@@ -289,3 +1079,416 @@ impl<'a> System<'a> for UpdateModelPosSystem {
         }
     }
 }
+
+#[cfg(test)]
+mod test {
+    use super::super::scenario::*;
+    use super::super::simulation::Simulation;
+    use super::*;
+
+    fn force(behaviour: SteeringBehaviour, x: f32, y: f32) -> SteeringForce {
+        SteeringForce {
+            behaviour,
+            force: v2(x, y),
+        }
+    }
+
+    #[test]
+    fn test_blend_weighted_sum() {
+        let mut rng = GameRandom::new(0).rng;
+        let forces = vec![
+            force(SteeringBehaviour::Walls, 10.0, 0.0),
+            force(SteeringBehaviour::Arrival, 0.0, 5.0),
+        ];
+
+        let (total, used) = blend_forces(BlendMode::WeightedSum, &forces, 1.0, &mut rng);
+        assert_eq!(total, v2(10.0, 5.0));
+        assert_eq!(used, vec![true, true]);
+    }
+
+    #[test]
+    fn test_blend_prioritized_truncated_sum_respect_max_acc() {
+        let mut rng = GameRandom::new(0).rng;
+        let forces = vec![
+            force(SteeringBehaviour::Walls, 6.0, 0.0),
+            force(SteeringBehaviour::Separation, 0.0, 8.0),
+            force(SteeringBehaviour::Arrival, 3.0, 0.0),
+        ];
+
+        let (total, used) =
+            blend_forces(BlendMode::PrioritizedTruncatedSum, &forces, 10.0, &mut rng);
+        assert_eq!(total, v2(6.0, 4.0));
+        assert_eq!(used, vec![true, true, false]);
+    }
+
+    #[test]
+    fn test_blend_prioritized_dithering_use_single_force() {
+        let mut rng = GameRandom::new(0).rng;
+        let forces = vec![
+            force(SteeringBehaviour::Walls, 0.0, 0.0),
+            force(SteeringBehaviour::Velocity, 1.0, 0.0),
+        ];
+
+        let (total, used) = blend_forces(BlendMode::PrioritizedDithering, &forces, 10.0, &mut rng);
+        assert_eq!(total, v2(1.0, 0.0));
+        assert_eq!(used, vec![false, true]);
+    }
+
+    /// vehicle moving with constant velocity and avoiding others
+    fn avoiding_vehicle(pos: [f32; 2], vel: [f32; 2]) -> VehicleDef {
+        VehicleDef {
+            pos,
+            dir: Some(vel),
+            archetype: None,
+            radius: Some(5.0),
+            color: Some([1.0, 1.0, 1.0, 1.0]),
+            steering: SteeringDef {
+                velocity: Some(VelocityDef {
+                    enabled: true,
+                    vel: Some(vel),
+                    weight: 1.0,
+                }),
+                avoidance: Some(AvoidanceDef {
+                    enabled: true,
+                    time_horizon: 2.0,
+                }),
+                ..Default::default()
+            },
+            formation: None,
+            squad: None,
+            script: None,
+            physics: None,
+        }
+    }
+
+    /// run the simulation and return the smallest distance between vehicle borders
+    fn run_and_get_min_separation(cfg: Cfg, vehicles: Vec<VehicleDef>, steps: usize) -> f32 {
+        let scenario = Scenario {
+            vehicles,
+            ..Default::default()
+        };
+
+        let mut simulation = Simulation::new_with_scenario(cfg, 1.0 / 60.0, &scenario).unwrap();
+
+        let mut min_separation = std::f32::MAX;
+        for _ in 0..steps {
+            simulation.step().unwrap();
+
+            let vehicles = simulation.get_world().read_storage::<Vehicle>();
+            let vehicles: Vec<&Vehicle> = (&vehicles).join().collect();
+            for (i, a) in vehicles.iter().enumerate() {
+                for b in vehicles.iter().skip(i + 1) {
+                    let distance = (b.pos - a.pos).magnitude() - a.radius - b.radius;
+                    min_separation = min_separation.min(distance);
+                }
+            }
+        }
+
+        min_separation
+    }
+
+    #[test]
+    fn test_avoidance_head_on_without_overlap() {
+        let min_separation = run_and_get_min_separation(
+            Default::default(),
+            vec![
+                avoiding_vehicle([200.0, 300.0], [50.0, 0.0]),
+                avoiding_vehicle([600.0, 300.0], [-50.0, 0.0]),
+            ],
+            600,
+        );
+
+        assert!(min_separation >= 0.0, "overlap of {}", min_separation);
+    }
+
+    #[test]
+    fn test_avoidance_crossing_without_overlap() {
+        let min_separation = run_and_get_min_separation(
+            Default::default(),
+            vec![
+                avoiding_vehicle([250.0, 300.0], [50.0, 0.0]),
+                avoiding_vehicle([550.0, 300.0], [-50.0, 0.0]),
+                avoiding_vehicle([400.0, 150.0], [0.0, 50.0]),
+                avoiding_vehicle([400.0, 450.0], [0.0, -50.0]),
+                avoiding_vehicle([300.0, 200.0], [35.0, 35.0]),
+                avoiding_vehicle([500.0, 400.0], [-35.0, -35.0]),
+            ],
+            600,
+        );
+
+        assert!(min_separation >= 0.0, "overlap of {}", min_separation);
+    }
+
+    #[test]
+    fn test_avoidance_crossing_with_low_max_acc_without_overlap() {
+        let cfg = Cfg {
+            max_acc: 6.0,
+            ..Default::default()
+        };
+
+        let min_separation = run_and_get_min_separation(
+            cfg,
+            vec![
+                avoiding_vehicle([250.0, 300.0], [50.0, 0.0]),
+                avoiding_vehicle([550.0, 300.0], [-50.0, 0.0]),
+                avoiding_vehicle([400.0, 150.0], [0.0, 50.0]),
+                avoiding_vehicle([400.0, 450.0], [0.0, -50.0]),
+                avoiding_vehicle([300.0, 200.0], [35.0, 35.0]),
+                avoiding_vehicle([500.0, 400.0], [-35.0, -35.0]),
+            ],
+            600,
+        );
+
+        assert!(min_separation >= 0.0, "overlap of {}", min_separation);
+    }
+
+    #[test]
+    fn test_boundary_bounce_and_clamp() {
+        for (mode, expected_pos, expected_vel) in vec![
+            (BoundaryMode::Bounce, p2(2.0, 300.0), v2(10.0, 5.0)),
+            (BoundaryMode::Clamp, p2(0.0, 300.0), v2(0.0, 5.0)),
+            (BoundaryMode::Wrap, p2(798.0, 300.0), v2(-10.0, 5.0)),
+        ] {
+            let cfg = Cfg {
+                boundary_mode: mode,
+                ..Default::default()
+            };
+            let mut world = super::super::create_world(cfg).unwrap();
+
+            let mut vehicle = Vehicle {
+                pos: p2(-2.0, 300.0),
+                dir: v2(1.0, 0.0),
+                desired_dir: v2(1.0, 0.0),
+                vel_dir: Vector2::zeros(),
+                speed: 0.0,
+                max_acc: 1.0,
+                desired_vel: Vector2::zeros(),
+                rotation_speed: 1.0,
+                max_speed: 20.0,
+                radius: 1.0,
+                forces: vec![],
+            };
+            vehicle.set_velocity(v2(-10.0, 5.0));
+            let entity = world.create_entity().with(vehicle).build();
+
+            BoundarySystem.run_now(&world);
+
+            let vehicles = world.read_storage::<Vehicle>();
+            let vehicle = vehicles.get(entity).unwrap();
+            assert_eq!(vehicle.pos, expected_pos, "{:?}", mode);
+            assert!(
+                (vehicle.get_velocity() - expected_vel).magnitude() < 0.001,
+                "{:?} {:?}",
+                mode,
+                vehicle.get_velocity()
+            );
+        }
+    }
+
+    #[test]
+    fn test_move_do_not_pass_through_thin_walls() {
+        let mut world = super::super::create_world(Default::default()).unwrap();
+        world.insert(GameTime { delta_time: 1.0 });
+        world
+            .create_entity()
+            .with(Wall::new_from_points(
+                p2(0.0, 100.0),
+                p2(200.0, 100.0),
+                1.0,
+                100.0,
+            ))
+            .build();
+
+        let mut vehicle = Vehicle {
+            pos: p2(100.0, 90.0),
+            dir: v2(0.0, 1.0),
+            desired_dir: v2(0.0, 1.0),
+            vel_dir: Vector2::zeros(),
+            speed: 0.0,
+            max_acc: 0.0,
+            desired_vel: Vector2::zeros(),
+            rotation_speed: 1.0,
+            max_speed: 100.0,
+            radius: 1.0,
+            forces: vec![],
+        };
+        vehicle.set_velocity(v2(10.0, 50.0));
+        let entity = world.create_entity().with(vehicle).build();
+
+        MoveSystem.run_now(&world);
+
+        let vehicles = world.read_storage::<Vehicle>();
+        let vehicle = vehicles.get(entity).unwrap();
+        assert!(vehicle.pos.y < 100.0, "{:?}", vehicle.pos);
+        assert!((vehicle.pos.x - 102.0).abs() < 0.001, "{:?}", vehicle.pos);
+        assert!((vehicle.get_velocity() - v2(10.0, 0.0)).magnitude() < 0.001);
+    }
+
+    fn physics_world(vel: V2, desired_vel: V2) -> (World, Entity) {
+        let mut world = super::super::create_world(Default::default()).unwrap();
+        world.insert(GameTime { delta_time: 0.1 });
+
+        let mut vehicle = Vehicle {
+            pos: p2(0.0, 0.0),
+            dir: v2(1.0, 0.0),
+            desired_dir: v2(1.0, 0.0),
+            vel_dir: Vector2::zeros(),
+            speed: 0.0,
+            max_acc: 100.0,
+            desired_vel,
+            rotation_speed: deg2rad(180.0),
+            max_speed: 50.0,
+            radius: 1.0,
+            forces: vec![],
+        };
+        vehicle.set_velocity(vel);
+
+        let entity = world
+            .create_entity()
+            .with(vehicle)
+            .with(VehiclePhysics {
+                mass: 2.0,
+                thrust: 100.0,
+                reverse_thrust: 40.0,
+                lateral_thrust: 10.0,
+                linear_drag: 0.1,
+                angular_drag: 0.0,
+                angular_acc: deg2rad(360.0),
+                burn_angle: deg2rad(20.0),
+                angular_vel: 0.0,
+            })
+            .build();
+
+        (world, entity)
+    }
+
+    #[test]
+    fn test_physics_turn_then_burn() {
+        let (mut world, entity) = physics_world(v2(0.0, 0.0), v2(0.0, 40.0));
+
+        MoveSystem.run_now(&world);
+        {
+            let vehicles = world.read_storage::<Vehicle>();
+            let vehicle = vehicles.get(entity).unwrap();
+            // only lateral thrust while facing away
+            assert!(vehicle.speed <= 10.0 / 2.0 * 0.1 + 0.001, "{:?}", vehicle);
+            assert!(vehicle.dir.y > 0.0);
+        }
+
+        for _ in 0..50 {
+            world
+                .write_storage::<Vehicle>()
+                .get_mut(entity)
+                .unwrap()
+                .desired_vel = v2(0.0, 40.0);
+            MoveSystem.run_now(&world);
+        }
+
+        let vehicles = world.read_storage::<Vehicle>();
+        let vehicle = vehicles.get(entity).unwrap();
+        assert!(
+            (vehicle.dir - v2(0.0, 1.0)).magnitude() < 0.01,
+            "{:?}",
+            vehicle.dir
+        );
+        assert!(
+            (vehicle.get_velocity() - v2(0.0, 40.0)).magnitude() < 2.0,
+            "{:?}",
+            vehicle
+        );
+    }
+
+    #[test]
+    fn test_physics_drag_without_thrust() {
+        let (world, entity) = physics_world(v2(10.0, 0.0), v2(10.0, 0.0));
+
+        {
+            let mut physics = world.write_storage::<VehiclePhysics>();
+            let physics = physics.get_mut(entity).unwrap();
+            physics.thrust = 0.0;
+            physics.reverse_thrust = 0.0;
+            physics.lateral_thrust = 0.0;
+        }
+
+        MoveSystem.run_now(&world);
+
+        let vehicles = world.read_storage::<Vehicle>();
+        let vehicle = vehicles.get(entity).unwrap();
+        assert!(vehicle.speed < 10.0);
+        assert!(vehicle.speed > 9.8);
+        assert_eq!(vehicle.dir, v2(1.0, 0.0));
+    }
+
+    #[test]
+    fn test_flock_squads_keep_fleet_formation() {
+        let scenario: Scenario = serde_json::from_str(
+            r#"{
+                "formations": [{ "name": "command", "formation": "Line" }],
+                "fleets": [{ "name": "fleet", "formation": "Line" }],
+                "squads": [
+                    { "name": "flagship", "fleet": "fleet" },
+                    { "name": "left", "fleet": "fleet", "cohesion": 0.5, "formation_index": 1 },
+                    { "name": "right", "fleet": "fleet", "cohesion": 0.5, "formation_index": 2 }
+                ],
+                "vehicles": [{
+                    "pos": [400, 300],
+                    "dir": [1, 0],
+                    "radius": 3,
+                    "color": [1, 1, 1, 1],
+                    "formation": "command",
+                    "squad": "flagship"
+                }],
+                "spawns": [{
+                    "count": 3,
+                    "area": [100, 100, 200, 200],
+                    "radius": 2,
+                    "color": [1, 0, 0, 1],
+                    "steering": { "separation": {} },
+                    "squad": "left"
+                }, {
+                    "count": 3,
+                    "area": [100, 400, 200, 500],
+                    "radius": 2,
+                    "color": [0, 0, 1, 1],
+                    "steering": { "separation": {} },
+                    "squad": "right"
+                }]
+            }"#,
+        )
+        .unwrap();
+
+        let mut simulation =
+            Simulation::new_with_scenario(Default::default(), 1.0 / 60.0, &scenario).unwrap();
+        for _ in 0..1800 {
+            simulation.step().unwrap();
+        }
+
+        let world = simulation.get_world();
+        let vehicles = world.read_storage::<Vehicle>();
+        let flocks = world.read_storage::<SteeringFlock>();
+        let squads = world.read_storage::<Squad>();
+
+        let mut centers: BTreeMap<Option<usize>, (V2, f32)> = BTreeMap::new();
+        for (vehicle, flock) in (&vehicles, &flocks).join() {
+            let index = squads.get(flock.squad).unwrap().formation_index;
+            let center = centers.entry(index).or_insert((V2::zeros(), 0.0));
+            center.0 += vehicle.pos.coords;
+            center.1 += 1.0;
+        }
+
+        // the anchor don't move, the squads are at each side of it
+        let anchor = centers[&None].0;
+        assert_eq!(anchor, v2(400.0, 300.0));
+
+        for (index, expected) in vec![(Some(1), v2(400.0, 290.0)), (Some(2), v2(400.0, 320.0))] {
+            let (sum, count) = centers[&index];
+            let centroid = sum / count;
+            assert!(
+                (centroid - expected).magnitude() < 3.0,
+                "{:?} {:?}",
+                index,
+                centroid
+            );
+        }
+    }
+}